@@ -2,7 +2,10 @@ use futures_util::{SinkExt, StreamExt};
 use tokio_tungstenite::{connect_async, connect_async_tls_with_config, Connector, tungstenite::protocol::Message as WsMessage};
 use url::Url;
 use uuid::Uuid;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
+use tokio::sync::oneshot;
 use tokio::time;
 use tracing::{info, error, warn};
 use std::fs;
@@ -12,9 +15,11 @@ use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSi
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use rustls::DigitallySignedStruct;
 
-use common::Message;
+use common::{CommandPayload, CommandResult, Message};
 use crate::config::ClientConfig;
 use crate::command_handler;
+use crate::policy::Policy;
+use crate::metrics::Collector;
 
 pub async fn run(shutdown_signal: impl std::future::Future<Output = ()>) -> anyhow::Result<()> {
     // Install default crypto provider if not already installed
@@ -59,6 +64,11 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()>) -> anyh
     let config = ClientConfig::new()?;
     info!("Loaded config: {:?}", config);
 
+    let policy = Policy::load(config.policy_path.as_deref())?;
+    if policy.is_restricted() {
+        info!("Command policy loaded: {:?}", policy);
+    }
+
     let client_id = get_or_create_client_id()?;
     let hostname = hostname::get().unwrap().to_string_lossy().to_string();
     let os = std::env::consts::OS.to_string();
@@ -69,10 +79,14 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()>) -> anyh
         info!("Client alias: {}", alias);
     }
 
+    // Working directory of shell commands and relative paths, kept across reconnects.
+    // The process directory itself never changes, commands run concurrently.
+    let mut cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
     tokio::select! {
         _ = async {
             loop {
-                match connect_and_run(client_id, &hostname, &os, &version, &config).await {
+                match connect_and_run(client_id, &hostname, &os, &version, &config, &policy, &mut cwd).await {
                     Ok(_) => warn!("Connection closed, reconnecting..."),
                     Err(e) => error!("Connection error: {}, reconnecting in 5s...", e),
                 }
@@ -107,7 +121,7 @@ fn get_or_create_client_id() -> anyhow::Result<Uuid> {
     Ok(new_uuid)
 }
 
-async fn connect_and_run(client_id: Uuid, hostname: &str, os: &str, version: &str, config: &ClientConfig) -> anyhow::Result<()> {
+async fn connect_and_run(client_id: Uuid, hostname: &str, os: &str, version: &str, config: &ClientConfig, policy: &Policy, cwd: &mut PathBuf) -> anyhow::Result<()> {
     let url = Url::parse(&config.server_url)?;
     
     let (ws_stream, _) = if config.tls_insecure {
@@ -184,6 +198,44 @@ async fn connect_and_run(client_id: Uuid, hostname: &str, os: &str, version: &st
         })
     };
 
+    // Metrics Task
+    let metrics_task = {
+        let interval = config.metrics_interval_sec;
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if interval == 0 {
+                return;
+            }
+            // sysinfo refreshes block, so they run off the async workers
+            let Ok(mut collector) = tokio::task::spawn_blocking(Collector::default).await else { return };
+            loop {
+                time::sleep(Duration::from_secs(interval)).await;
+                let Ok((c, sample)) = tokio::task::spawn_blocking(move || {
+                    let sample = collector.sample();
+                    (collector, sample)
+                }).await else { break };
+                collector = c;
+                if tx.send(Message::Metrics(sample)).await.is_err() {
+                    break;
+                }
+            }
+        })
+    };
+
+    // Inventory is reported once per connection so the server has it while the host is offline
+    {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Ok(hardware) = tokio::task::spawn_blocking(crate::inventory::collect).await {
+                let _ = tx.send(Message::Inventory(Box::new(hardware))).await;
+            }
+        });
+    }
+
+    // Cancel handles of streaming commands (TailFile with follow), by command id.
+    // Dropping the map when the connection ends stops them too.
+    let mut streams: HashMap<Uuid, oneshot::Sender<()>> = HashMap::new();
+
     // Handle Incoming Commands
     loop {
         tokio::select! {
@@ -198,16 +250,55 @@ async fn connect_and_run(client_id: Uuid, hostname: &str, os: &str, version: &st
                 match msg {
                     WsMessage::Text(text) => {
                          let parsed: Message = serde_json::from_str(&text)?;
-                         match parsed {
-                             Message::Command { id, cmd } => {
-                                 info!("Received command: {:?}", cmd);
-                                 let result = command_handler::handle_command(cmd, config.tls_insecure).await;
-                                 info!("Command execution finished. Result: {:?}", result);
-                                 let response = Message::Response { id, result };
+                         if let Message::Command { id, cmd } = parsed {
+                             // Arguments and results may contain resolved secrets, so only the kind is logged
+                             let kind = cmd.kind();
+                             info!("Received command {} ({})", id, kind);
+                             let cmd = command_handler::resolve_paths(cmd, cwd);
+                             if let Err(reason) = policy.check(&cmd) {
+                                 warn!("Command {} ({}) rejected by policy", id, kind);
+                                 let response = Message::Response { id, result: CommandResult::PolicyDenied(reason) };
                                  let json = serde_json::to_string(&response)?;
                                  write.send(WsMessage::Text(json)).await?;
+                                 continue;
+                             }
+                             // Applied here rather than in the spawned task, so later commands see it
+                             if let Some(result) = command_handler::change_dir(&cmd, cwd) {
+                                 let _ = tx.send(Message::Response { id, result }).await;
+                                 continue;
+                             }
+                             match cmd {
+                                 CommandPayload::CancelCommand { id: target } => {
+                                     let result = match streams.remove(&target).map(|cancel| cancel.send(())) {
+                                         Some(Ok(())) => CommandResult::Success(format!("Cancelled command {}", target)),
+                                         _ => CommandResult::Error(format!("No running command {}", target)),
+                                     };
+                                     let _ = tx.send(Message::Response { id, result }).await;
+                                     continue;
+                                 }
+                                 CommandPayload::TailFile { path, lines, follow: true } => {
+                                     streams.retain(|_, cancel| !cancel.is_closed());
+                                     let (cancel_tx, cancel_rx) = oneshot::channel();
+                                     streams.insert(id, cancel_tx);
+                                     let tx = tx.clone();
+                                     let lines = lines.unwrap_or(crate::tail::DEFAULT_LINES);
+                                     tokio::spawn(async move {
+                                         let result = crate::tail::follow(id, path, lines, tx.clone(), cancel_rx).await;
+                                         let _ = tx.send(Message::Response { id, result }).await;
+                                     });
+                                     continue;
+                                 }
+                                 _ => {}
                              }
-                             _ => {}
+                             // Run in the background so heartbeats and pongs keep flowing during long commands
+                             let tx = tx.clone();
+                             let tls_insecure = config.tls_insecure;
+                             let cwd = cwd.clone();
+                             tokio::spawn(async move {
+                                 let result = command_handler::handle_command(cmd, &cwd, tls_insecure).await;
+                                 info!("Command {} ({}) finished", id, kind);
+                                 let _ = tx.send(Message::Response { id, result }).await;
+                             });
                          }
                     }
                     WsMessage::Close(_) => return Ok(()),
@@ -219,6 +310,7 @@ async fn connect_and_run(client_id: Uuid, hostname: &str, os: &str, version: &st
     }
     
     heartbeat_task.abort();
+    metrics_task.abort();
     Ok(())
 }
 
@@ -1,36 +1,122 @@
 use std::process::Stdio;
-use sysinfo::System;
 use tokio::process::Command;
+use std::collections::VecDeque;
 use std::fs;
-use std::path::PathBuf;
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf};
 use tracing::{info, error};
 use walkdir::WalkDir;
 use zip::write::FileOptions;
 
-use common::{CommandPayload, CommandResult, HardwareInfo, FileInfo};
+use common::{CommandPayload, CommandResult, ContentMatch, FindQuery, SearchQuery};
 
-fn expand_path(path: &str) -> PathBuf {
+fn home_dir() -> String {
+    if cfg!(target_os = "windows") {
+        std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string())
+    } else {
+        std::env::var("HOME").unwrap_or_else(|_| "/".to_string())
+    }
+}
+
+pub(crate) fn expand_path(path: &str) -> PathBuf {
     if path == "~" {
-        let home = if cfg!(target_os = "windows") {
-            std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string())
-        } else {
-            std::env::var("HOME").unwrap_or_else(|_| "/".to_string())
-        };
-        return PathBuf::from(home);
+        return PathBuf::from(home_dir());
     }
     
     if path.starts_with("~/") || (cfg!(target_os = "windows") && path.starts_with("~\\")) {
-        let home = if cfg!(target_os = "windows") {
-            std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string())
-        } else {
-            std::env::var("HOME").unwrap_or_else(|_| "/".to_string())
-        };
-        return PathBuf::from(home).join(&path[2..]);
+        return PathBuf::from(home_dir()).join(&path[2..]);
     }
     
     PathBuf::from(path)
 }
 
+// Joins a path onto the working directory and drops `.` and `..` the way a shell's `cd` does
+fn join_dir(cwd: &Path, path: &str) -> PathBuf {
+    let mut joined = PathBuf::new();
+    for component in cwd.join(expand_path(path)).components() {
+        match component {
+            Component::ParentDir => {
+                joined.pop();
+            }
+            Component::CurDir => {}
+            c => joined.push(c),
+        }
+    }
+    joined
+}
+
+/// Makes the paths of a command absolute against the session's working directory, so neither
+/// the policy check nor the handler depends on the process-wide one.
+pub fn resolve_paths(cmd: CommandPayload, cwd: &Path) -> CommandPayload {
+    let abs = |path: String| join_dir(cwd, &path).to_string_lossy().to_string();
+    match cmd {
+        CommandPayload::ChangeDir { path } => CommandPayload::ChangeDir { path: abs(path) },
+        CommandPayload::DownloadFile { url, dest_path } => CommandPayload::DownloadFile { url, dest_path: abs(dest_path) },
+        CommandPayload::UploadFile { src_path, upload_url } => CommandPayload::UploadFile { src_path: abs(src_path), upload_url },
+        CommandPayload::ListDir { path, depth, limit, cursor } => CommandPayload::ListDir { path: abs(path), depth, limit, cursor },
+        CommandPayload::ReadFile { path } => CommandPayload::ReadFile { path: abs(path) },
+        CommandPayload::WriteFile { path, content } => CommandPayload::WriteFile { path: abs(path), content },
+        CommandPayload::DownloadAndUnzip { url, dest_path } => CommandPayload::DownloadAndUnzip { url, dest_path: abs(dest_path) },
+        CommandPayload::ZipAndUpload { src_path, upload_url } => CommandPayload::ZipAndUpload { src_path: abs(src_path), upload_url },
+        CommandPayload::CopyFile { src_path, dest_path } => CommandPayload::CopyFile { src_path: abs(src_path), dest_path: abs(dest_path) },
+        CommandPayload::MoveFile { src_path, dest_path } => CommandPayload::MoveFile { src_path: abs(src_path), dest_path: abs(dest_path) },
+        CommandPayload::DeleteFile { path } => CommandPayload::DeleteFile { path: abs(path) },
+        CommandPayload::TailFile { path, lines, follow } => CommandPayload::TailFile { path: abs(path), lines, follow },
+        CommandPayload::FindFiles(query) => CommandPayload::FindFiles(FindQuery { path: abs(query.path), ..query }),
+        CommandPayload::SearchContent(query) => CommandPayload::SearchContent(SearchQuery { path: abs(query.path), ..query }),
+        other => other,
+    }
+}
+
+fn is_cd(cmd: &str) -> bool {
+    if cfg!(target_os = "windows") {
+        cmd.trim().eq_ignore_ascii_case("cd")
+    } else {
+        cmd.trim() == "cd"
+    }
+}
+
+fn enter_dir(cwd: &mut PathBuf, path: &str) -> std::io::Result<()> {
+    let target = join_dir(cwd, path);
+    if !fs::metadata(&target)?.is_dir() {
+        return Err(std::io::Error::other("Not a directory"));
+    }
+    *cwd = target;
+    Ok(())
+}
+
+/// Handles `cd` (sent as ShellExec) and ChangeDir by moving the session's working directory.
+/// Returns None for every other command. Commands run concurrently, so this is applied in the
+/// order commands arrive instead of changing the directory of the whole process.
+pub fn change_dir(cmd: &CommandPayload, cwd: &mut PathBuf) -> Option<CommandResult> {
+    match cmd {
+        CommandPayload::ShellExec { cmd, args } if is_cd(cmd) => {
+            let target = args.first().cloned().unwrap_or_else(home_dir);
+            let (stderr, exit_code) = match enter_dir(cwd, &target) {
+                Ok(()) => (String::new(), 0),
+                Err(e) => (format!("cd: failed to change directory to {}: {}\n", target, e), 1),
+            };
+            Some(CommandResult::ShellOutput {
+                stdout: String::new(),
+                stderr,
+                exit_code,
+                cwd: cwd.to_string_lossy().to_string(),
+            })
+        }
+        CommandPayload::ChangeDir { path } => {
+            info!("Changing directory to: {}", path);
+            Some(match enter_dir(cwd, path) {
+                Ok(()) => CommandResult::DirChanged { new_path: cwd.to_string_lossy().to_string() },
+                Err(e) => {
+                    error!("Failed to change dir: {}", e);
+                    CommandResult::Error(format!("Failed to change dir: {}", e))
+                }
+            })
+        }
+        _ => None,
+    }
+}
+
 fn zip_directory(src_dir: &std::path::Path, dst_file: &std::path::Path) -> anyhow::Result<()> {
     if !src_dir.is_dir() {
         return Err(anyhow::anyhow!("Source is not a directory"));
@@ -100,173 +186,334 @@ fn unzip_file(zip_path: &std::path::Path, dest_dir: &std::path::Path) -> anyhow:
     Ok(())
 }
 
-pub async fn handle_command(cmd: CommandPayload, tls_insecure: bool) -> CommandResult {
+const DEFAULT_MAX_RESULTS: usize = 1000;
+const MAX_RESULTS: usize = 10_000;
+const MAX_CONTEXT: usize = 20;
+const MAX_SEARCH_FILE_SIZE: u64 = 64 * 1024 * 1024;
+// Long lines (minified files, binary junk) are cut in search results
+const MAX_LINE_CHARS: usize = 1000;
+
+fn compile_glob(pattern: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(pattern).map_err(|e| format!("Invalid glob '{}': {}", pattern, e))
+}
+
+fn glob_options() -> glob::MatchOptions {
+    glob::MatchOptions {
+        case_sensitive: !cfg!(target_os = "windows"),
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    }
+}
+
+// Path of an entry relative to the searched root; the root itself is named by its file name
+fn relative_name(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel.to_string_lossy().to_string(),
+        _ => path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string(),
+    }
+}
+
+fn find_files(query: &FindQuery) -> Result<CommandResult, String> {
+    let root = expand_path(&query.path);
+    fs::metadata(&root).map_err(|e| format!("Failed to read {}: {}", root.display(), e))?;
+    let pattern = query.pattern.as_deref().filter(|p| !p.is_empty()).map(compile_glob).transpose()?;
+    let match_path = query.pattern.as_deref().is_some_and(|p| p.contains('/'));
+    let size_filtered = query.min_size.is_some() || query.max_size.is_some();
+    let limit = query.max_results.unwrap_or(DEFAULT_MAX_RESULTS).min(MAX_RESULTS);
+
+    let mut walker = WalkDir::new(&root).min_depth(1);
+    if let Some(depth) = query.max_depth {
+        walker = walker.max_depth(depth);
+    }
+    let owners = crate::files::Owners::load();
+    let mut files = Vec::new();
+    let mut truncated = false;
+    // Unreadable directories are skipped rather than failing the whole search
+    for entry in walker.into_iter().filter_map(Result::ok) {
+        let name = relative_name(&root, entry.path());
+        if let Some(pattern) = &pattern {
+            let subject = if match_path { name.replace('\\', "/") } else { entry.file_name().to_string_lossy().to_string() };
+            if !pattern.matches_with(&subject, glob_options()) {
+                continue;
+            }
+        }
+        let Ok(metadata) = entry.metadata() else { continue };
+        if size_filtered && metadata.is_dir() {
+            continue;
+        }
+        if query.min_size.is_some_and(|min| metadata.len() < min) || query.max_size.is_some_and(|max| metadata.len() > max) {
+            continue;
+        }
+        let modified = crate::files::unix_secs(metadata.modified());
+        if query.modified_after.is_some_and(|after| modified.is_none_or(|m| m < after))
+            || query.modified_before.is_some_and(|before| modified.is_none_or(|m| m > before)) {
+            continue;
+        }
+        if files.len() == limit {
+            truncated = true;
+            break;
+        }
+        files.push(crate::files::describe(name, entry.path(), &owners));
+    }
+    Ok(CommandResult::FoundFiles { root: root.to_string_lossy().to_string(), files, truncated })
+}
+
+fn search_content(query: &SearchQuery) -> Result<CommandResult, String> {
+    let root = expand_path(&query.path);
+    fs::metadata(&root).map_err(|e| format!("Failed to read {}: {}", root.display(), e))?;
+    let regex = regex::RegexBuilder::new(&query.pattern)
+        .case_insensitive(query.ignore_case)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))?;
+    let name_glob = query.glob.as_deref().filter(|g| !g.is_empty()).map(compile_glob).transpose()?;
+    let context = query.context.unwrap_or(0).min(MAX_CONTEXT);
+    let limit = query.max_results.unwrap_or(DEFAULT_MAX_RESULTS).min(MAX_RESULTS);
+
+    let mut walker = WalkDir::new(&root);
+    if let Some(depth) = query.max_depth {
+        walker = walker.max_depth(depth);
+    }
+    let mut matches = Vec::new();
+    let mut files_searched = 0;
+    let mut truncated = false;
+    for entry in walker.into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file()) {
+        if name_glob.as_ref().is_some_and(|g| !g.matches_with(&entry.file_name().to_string_lossy(), glob_options())) {
+            continue;
+        }
+        if entry.metadata().map(|m| m.len() > MAX_SEARCH_FILE_SIZE).unwrap_or(true) {
+            continue;
+        }
+        let Ok(file) = fs::File::open(entry.path()) else { continue };
+        let name = relative_name(&root, entry.path());
+        match search_file(file, &name, &regex, context, limit, &mut matches) {
+            Some(hit_limit) => {
+                files_searched += 1;
+                if hit_limit {
+                    truncated = true;
+                    break;
+                }
+            }
+            None => continue,
+        }
+    }
+    Ok(CommandResult::ContentMatches { root: root.to_string_lossy().to_string(), matches, files_searched, truncated })
+}
+
+// Appends the matches in one file. Returns None for binary files, otherwise whether the match
+// limit was hit.
+fn search_file(file: fs::File, name: &str, regex: &regex::Regex, context: usize, limit: usize, matches: &mut Vec<ContentMatch>) -> Option<bool> {
+    let mut reader = std::io::BufReader::new(file);
+    // Same heuristic as grep: a NUL byte near the start means binary
+    let head = reader.fill_buf().ok()?;
+    if head[..head.len().min(8192)].contains(&0) {
+        return None;
+    }
+
+    let first = matches.len();
+    let mut before: VecDeque<String> = VecDeque::new();
+    let mut buf = Vec::new();
+    let mut line_number = 0;
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => line_number += 1,
+        }
+        let text = String::from_utf8_lossy(&buf);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let line: String = text.chars().take(MAX_LINE_CHARS).collect();
+
+        // This line is trailing context for the recent matches of this file
+        for m in matches[first..].iter_mut().rev().take_while(|m| line_number - m.line_number <= context) {
+            m.after.push(line.clone());
+        }
+        if regex.is_match(text) {
+            if matches.len() >= limit {
+                return Some(true);
+            }
+            matches.push(ContentMatch {
+                path: name.to_string(),
+                line_number,
+                line: line.clone(),
+                before: before.iter().cloned().collect(),
+                after: Vec::new(),
+            });
+        }
+        if context > 0 {
+            before.push_back(line);
+            if before.len() > context {
+                before.pop_front();
+            }
+        }
+    }
+    Some(false)
+}
+
+pub async fn handle_command(cmd: CommandPayload, cwd: &Path, tls_insecure: bool) -> CommandResult {
     match cmd {
         CommandPayload::ShellExec { cmd, args } => {
-            info!("Executing shell command: {} {:?}", cmd, args);
+            info!("Executing shell command");
             // Trim command just in case
             let cmd_trimmed = cmd.trim();
+
+            // Append original args if any (Note: for shell execution, args might need to be part of the command string or handled differently.
+            // But for simple "run this program with these args" via shell, we usually just pass the whole command string to sh -c.
+            // If args are present, they are likely arguments to the command 'cmd'.
+            // If the user sent cmd="ls" and args=["-la"], we want `sh -c "ls -la"`.
+            // So we should construct the full command line.
             
-            let is_cd = if cfg!(target_os = "windows") {
-                cmd_trimmed.eq_ignore_ascii_case("cd")
+            let full_cmd = if args.is_empty() {
+                cmd_trimmed.to_string()
             } else {
-                cmd_trimmed == "cd"
+                format!("{} {}", cmd_trimmed, args.join(" "))
             };
-            
-            if is_cd {
-                let default_path = if cfg!(target_os = "windows") {
-                    std::env::var("USERPROFILE").unwrap_or("C:\\".to_string())
-                } else {
-                    std::env::var("HOME").unwrap_or("/".to_string())
-                };
-                
-                let target_path_str = args.get(0).cloned().unwrap_or(default_path);
-                let target_path = expand_path(&target_path_str);
-                
-                match std::env::set_current_dir(&target_path) {
-                    Ok(_) => CommandResult::ShellOutput {
-                        stdout: String::new(),
-                        stderr: String::new(),
-                        exit_code: 0,
-                        cwd: std::env::current_dir().unwrap_or_else(|_| target_path).to_string_lossy().to_string(),
-                    },
-                    Err(e) => CommandResult::ShellOutput {
-                        stdout: String::new(),
-                        stderr: format!("cd: failed to change directory to {}: {}\n", target_path.display(), e),
-                        exit_code: 1,
-                        cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).to_string_lossy().to_string(),
-                    },
-                }
-            } else {
-                // Append original args if any (Note: for shell execution, args might need to be part of the command string or handled differently.
-                // But for simple "run this program with these args" via shell, we usually just pass the whole command string to sh -c.
-                // If args are present, they are likely arguments to the command 'cmd'.
-                // If the user sent cmd="ls" and args=["-la"], we want `sh -c "ls -la"`.
-                // So we should construct the full command line.
-                
-                let full_cmd = if args.is_empty() {
-                    cmd_trimmed.to_string()
-                } else {
-                    format!("{} {}", cmd_trimmed, args.join(" "))
-                };
 
-                let (shell, shell_args) = if cfg!(target_os = "windows") {
-                    ("cmd", vec!["/C", &full_cmd])
-                } else {
-                    ("sh", vec!["-c", &full_cmd])
-                };
+            let (shell, shell_args) = if cfg!(target_os = "windows") {
+                ("cmd", vec!["/C", &full_cmd])
+            } else {
+                ("sh", vec!["-c", &full_cmd])
+            };
 
-                match Command::new(shell)
-                    .args(&shell_args)
-                    // If we've changed directory via `cd`, subsequent commands should run in that dir.
-                    // But `std::env::set_current_dir` already affects the whole process, so `Command::new` inherits it.
-                    // However, if we are on Windows and using `cmd /C`, it might need explicit cwd if it was lost?
-                    // Actually, `std::env::set_current_dir` is process-global, so it should persist.
-                    // But let's verify if `cmd` resets it. `cmd /C` starts a new shell. 
-                    // The new shell should inherit the parent process (client)'s CWD.
-                    // So `cd` handling logic above:
-                    // 1. `if cmd == "cd"` -> `std::env::set_current_dir`. This updates client process CWD.
-                    // 2. Next command -> `Command::new` -> inherits client process CWD.
-                    // So this *should* work.
-                    // If it's not working on Windows, maybe there's a specific issue.
-                    // Let's explicitly set current_dir just in case.
-                    .current_dir(std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                {
-                    Ok(child) => {
-                        match child.wait_with_output().await {
-                            Ok(output) => {
-                                let stdout = if cfg!(target_os = "windows") {
-                                    // Try GBK first, then fallback to lossy UTF-8
-                                    let (cow, _, _) = encoding_rs::GBK.decode(&output.stdout);
-                                    cow.to_string()
-                                } else {
-                                    String::from_utf8_lossy(&output.stdout).to_string()
-                                };
-                                
-                                let stderr = if cfg!(target_os = "windows") {
-                                    let (cow, _, _) = encoding_rs::GBK.decode(&output.stderr);
-                                    cow.to_string()
-                                } else {
-                                    String::from_utf8_lossy(&output.stderr).to_string()
-                                };
+            match Command::new(shell)
+                .args(&shell_args)
+                // The session's directory, as moved by earlier `cd` commands
+                .current_dir(cwd)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => {
+                    match child.wait_with_output().await {
+                        Ok(output) => {
+                            let stdout = if cfg!(target_os = "windows") {
+                                // Try GBK first, then fallback to lossy UTF-8
+                                let (cow, _, _) = encoding_rs::GBK.decode(&output.stdout);
+                                cow.to_string()
+                            } else {
+                                String::from_utf8_lossy(&output.stdout).to_string()
+                            };
+                            
+                            let stderr = if cfg!(target_os = "windows") {
+                                let (cow, _, _) = encoding_rs::GBK.decode(&output.stderr);
+                                cow.to_string()
+                            } else {
+                                String::from_utf8_lossy(&output.stderr).to_string()
+                            };
 
-                                CommandResult::ShellOutput {
-                                    stdout,
-                                    stderr,
-                                    exit_code: output.status.code().unwrap_or(-1),
-                                    cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).to_string_lossy().to_string(),
-                                }
-                            },
-                            Err(e) => CommandResult::Error(format!("Failed to wait on child: {}", e)),
-                        }
+                            CommandResult::ShellOutput {
+                                stdout,
+                                stderr,
+                                exit_code: output.status.code().unwrap_or(-1),
+                                cwd: cwd.to_string_lossy().to_string(),
+                            }
+                        },
+                        Err(e) => CommandResult::Error(format!("Failed to wait on child: {}", e)),
                     }
-                    Err(e) => CommandResult::Error(format!("Failed to spawn shell: {}", e)),
                 }
+                Err(e) => CommandResult::Error(format!("Failed to spawn shell: {}", e)),
             }
         }
-        CommandPayload::ChangeDir { path } => {
-            let expanded = expand_path(&path);
-            info!("Changing directory to: {} (expanded: {:?})", path, expanded);
-            match std::env::set_current_dir(&expanded) {
-                Ok(_) => CommandResult::DirChanged { new_path: expanded.to_string_lossy().to_string() },
+        // Directory changes update the session's working directory, so app.rs handles them
+        CommandPayload::ChangeDir { .. } => CommandResult::Error("Directory changes are handled by the session".to_string()),
+        CommandPayload::GetHardwareInfo => {
+            info!("Getting hardware info");
+            match tokio::task::spawn_blocking(crate::inventory::collect).await {
+                Ok(hardware) => CommandResult::HardwareInfo(Box::new(hardware)),
+                Err(e) => CommandResult::Error(format!("Failed to collect hardware info: {}", e)),
+            }
+        }
+        CommandPayload::ListProcesses => {
+            info!("Listing processes");
+            match tokio::task::spawn_blocking(crate::processes::list).await {
+                Ok(processes) => CommandResult::ProcessList { processes },
+                Err(e) => CommandResult::Error(format!("Failed to list processes: {}", e)),
+            }
+        }
+        CommandPayload::KillProcess { pid, signal } => {
+            info!("Sending {} to process {}", signal.as_deref().unwrap_or("TERM"), pid);
+            match crate::processes::kill(pid, signal.as_deref()) {
+                Ok(msg) => CommandResult::Success(msg),
                 Err(e) => {
-                    error!("Failed to change dir: {}", e);
-                    CommandResult::Error(format!("Failed to change dir: {}", e))
-                },
+                    error!("{}", e);
+                    CommandResult::Error(e)
+                }
             }
         }
-        CommandPayload::GetHardwareInfo => {
-            info!("Getting hardware info");
-            let mut sys = System::new_all();
-            sys.refresh_all();
-            
-            let total_memory = sys.total_memory();
-            let used_memory = sys.used_memory();
-            let cpu_usage = sys.global_cpu_usage();
-            let platform = std::env::consts::OS.to_string();
-
-            CommandResult::HardwareInfo(HardwareInfo {
-                cpu_usage,
-                total_memory,
-                used_memory,
-                platform,
-            })
+        CommandPayload::ListServices => {
+            info!("Listing services");
+            match tokio::task::spawn_blocking(crate::services::list).await {
+                Ok(Ok(services)) => CommandResult::ServiceList { services },
+                Ok(Err(e)) => CommandResult::Error(e),
+                Err(e) => CommandResult::Error(format!("Failed to list services: {}", e)),
+            }
+        }
+        CommandPayload::GetServiceStatus { name } => {
+            info!("Getting status of service {}", name);
+            match tokio::task::spawn_blocking(move || crate::services::status(&name)).await {
+                Ok(Ok(service)) => CommandResult::ServiceStatus(service),
+                Ok(Err(e)) => CommandResult::Error(e),
+                Err(e) => CommandResult::Error(format!("Failed to get service status: {}", e)),
+            }
+        }
+        CommandPayload::ControlService { name, action } => {
+            info!("Service {}: {}", name, action);
+            match tokio::task::spawn_blocking(move || crate::services::control(&name, action)).await {
+                Ok(Ok(service)) => CommandResult::ServiceStatus(service),
+                Ok(Err(e)) => {
+                    error!("{}", e);
+                    CommandResult::Error(e)
+                }
+                Err(e) => CommandResult::Error(format!("Failed to {} service: {}", action, e)),
+            }
+        }
+        CommandPayload::TailFile { path, lines, .. } => {
+            let lines = lines.unwrap_or(crate::tail::DEFAULT_LINES);
+            info!("Reading last {} lines of {}", lines, path);
+            let result = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || crate::tail::last_lines(&path, lines)).await
+            };
+            match result {
+                Ok(Ok((lines, _))) => CommandResult::FileTail { path, lines },
+                Ok(Err(e)) => CommandResult::Error(format!("Failed to read {}: {}", path, e)),
+                Err(e) => CommandResult::Error(format!("Failed to read {}: {}", path, e)),
+            }
+        }
+        CommandPayload::FindFiles(query) => {
+            info!("Finding files under {}", query.path);
+            match tokio::task::spawn_blocking(move || find_files(&query)).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => CommandResult::Error(e),
+                Err(e) => CommandResult::Error(format!("Failed to find files: {}", e)),
+            }
         }
-        CommandPayload::ListDir { path } => {
+        CommandPayload::SearchContent(query) => {
+            info!("Searching file contents under {}", query.path);
+            match tokio::task::spawn_blocking(move || search_content(&query)).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => CommandResult::Error(e),
+                Err(e) => CommandResult::Error(format!("Failed to search files: {}", e)),
+            }
+        }
+        // Following and cancelling need the connection, so app.rs handles them
+        CommandPayload::CancelCommand { .. } => CommandResult::Error("No command to cancel".to_string()),
+        CommandPayload::ListDir { path, depth, limit, cursor } => {
              let expanded = expand_path(&path);
              info!("Listing directory: {} (expanded: {:?})", path, expanded);
-             match std::fs::read_dir(&expanded) {
-                 Ok(entries) => {
-                     let mut files = Vec::new();
-                     for entry in entries {
-                         if let Ok(entry) = entry {
-                             let metadata = entry.metadata().ok();
-                             let is_dir = metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false);
-                             let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
-                             let modified = metadata.as_ref().and_then(|m| m.modified().ok())
-                                 .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs());
-
-                             files.push(FileInfo {
-                                 name: entry.file_name().to_string_lossy().to_string(),
-                                 is_dir,
-                                 size,
-                                 modified,
-                             });
-                         }
-                     }
-                     CommandResult::FileList { files }
-                 }
-                 Err(e) => {
+             let listed = tokio::task::spawn_blocking(move || {
+                 crate::files::list_dir(&expanded, depth.unwrap_or(1), limit, cursor.as_deref())
+             }).await;
+             match listed {
+                 Ok(Ok((files, next_cursor))) => CommandResult::FileList { files, next_cursor },
+                 Ok(Err(e)) => {
                      error!("Failed to read dir: {}", e);
                      CommandResult::Error(format!("Failed to read dir: {}", e))
                  },
+                 Err(e) => CommandResult::Error(format!("Failed to read dir: {}", e)),
              }
         }
         CommandPayload::DownloadFile { url, dest_path } => {
-            info!("Downloading file from {} to {}", url, dest_path);
+            info!("Downloading file to {}", dest_path);
             let client = match reqwest::Client::builder()
                 .danger_accept_invalid_certs(tls_insecure)
                 .timeout(std::time::Duration::from_secs(3600)) // 1 hour timeout for large files
@@ -321,19 +568,15 @@ pub async fn handle_command(cmd: CommandPayload, tls_insecure: bool) -> CommandR
                     }
                 }
                 Err(e) => {
-                    error!("Request failed: {:?}", e);
+                    // The error names the URL, which may carry a secret; only the result reports it
+                    error!("Download request failed");
                     CommandResult::Error(format!("Request failed: {:?}", e))
                 },
             }
         }
         CommandPayload::UploadFile { src_path, upload_url } => {
-            // Ensure path is absolute or relative to current CWD
-            let path = PathBuf::from(&src_path);
-            let abs_path = if path.is_absolute() {
-                path
-            } else {
-                std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join(path)
-            };
+            // resolve_paths already made it absolute against the session's directory
+            let abs_path = PathBuf::from(&src_path);
             
             info!("Uploading file {} to {}", abs_path.display(), upload_url);
             match tokio::fs::read(&abs_path).await {
@@ -376,7 +619,7 @@ pub async fn handle_command(cmd: CommandPayload, tls_insecure: bool) -> CommandR
             }
         }
         CommandPayload::UpdateClient { url } => {
-            info!("Updating client from {}", url);
+            info!("Updating client");
             match download_and_replace(&url).await {
                 Ok(_) => {
                     // This line might not be reached if replacement kills the process immediately,
@@ -425,7 +668,7 @@ pub async fn handle_command(cmd: CommandPayload, tls_insecure: bool) -> CommandR
             }
         }
         CommandPayload::DownloadAndUnzip { url, dest_path } => {
-            info!("Downloading and unzipping from {} to {}", url, dest_path);
+            info!("Downloading and unzipping to {}", dest_path);
             let client = match reqwest::Client::builder()
                 .timeout(std::time::Duration::from_secs(3600))
                 .build() {
@@ -671,3 +914,48 @@ async fn download_and_replace(url: &str) -> anyhow::Result<()> {
         anyhow::bail!("Automatic restart not supported on this platform");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cd_moves_only_the_session_directory() {
+        let base = std::env::temp_dir().join(format!("roam-cwd-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(base.join("sub")).unwrap();
+        fs::write(base.join("file"), b"x").unwrap();
+        let process_dir = std::env::current_dir().unwrap();
+
+        let mut cwd = base.clone();
+        let cd = CommandPayload::ShellExec { cmd: "cd".into(), args: vec!["sub".into()] };
+        assert!(matches!(change_dir(&cd, &mut cwd), Some(CommandResult::ShellOutput { exit_code: 0, .. })));
+        assert_eq!(cwd, base.join("sub"));
+        assert_eq!(std::env::current_dir().unwrap(), process_dir);
+
+        let up = CommandPayload::ChangeDir { path: "..".into() };
+        assert!(matches!(change_dir(&up, &mut cwd), Some(CommandResult::DirChanged { .. })));
+        assert_eq!(cwd, base);
+
+        let into_file = CommandPayload::ChangeDir { path: "file".into() };
+        assert!(matches!(change_dir(&into_file, &mut cwd), Some(CommandResult::Error(_))));
+        assert_eq!(cwd, base);
+        assert!(change_dir(&CommandPayload::ListProcesses, &mut cwd).is_none());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_session_directory() {
+        let cwd = Path::new("/srv/app");
+        let CommandPayload::CopyFile { src_path, dest_path } = resolve_paths(
+            CommandPayload::CopyFile { src_path: "logs/./a.txt".into(), dest_path: "/tmp/b.txt".into() },
+            cwd,
+        ) else { panic!("variant changed") };
+        assert_eq!(PathBuf::from(src_path), PathBuf::from("/srv/app/logs/a.txt"));
+        assert_eq!(dest_path, "/tmp/b.txt");
+
+        let CommandPayload::DeleteFile { path } = resolve_paths(CommandPayload::DeleteFile { path: "../etc".into() }, cwd) else {
+            panic!("variant changed")
+        };
+        assert_eq!(PathBuf::from(path), PathBuf::from("/srv/etc"));
+    }
+}
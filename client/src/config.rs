@@ -1,13 +1,31 @@
 use serde::Deserialize;
 use config::{Config, File};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 pub struct ClientConfig {
     pub server_url: String,
     pub auth_token: String,
     pub heartbeat_interval_sec: u64,
+    // How often resource usage is pushed to the server; 0 disables it
+    pub metrics_interval_sec: u64,
     pub alias: Option<String>,
     pub tls_insecure: bool,
+    pub policy_path: Option<String>,
+}
+
+// Written out so that the auth token never reaches the log with the rest of the config
+impl std::fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("server_url", &self.server_url)
+            .field("auth_token", &"******")
+            .field("heartbeat_interval_sec", &self.heartbeat_interval_sec)
+            .field("metrics_interval_sec", &self.metrics_interval_sec)
+            .field("alias", &self.alias)
+            .field("tls_insecure", &self.tls_insecure)
+            .field("policy_path", &self.policy_path)
+            .finish()
+    }
 }
 
 impl ClientConfig {
@@ -16,8 +34,10 @@ impl ClientConfig {
             .set_default("server_url", "ws://127.0.0.1:3333/ws")?
             .set_default("auth_token", "secret-token")?
             .set_default("heartbeat_interval_sec", 10)?
+            .set_default("metrics_interval_sec", 60)?
             .set_default("alias", None::<String>)?
-            .set_default("tls_insecure", false)?;
+            .set_default("tls_insecure", false)?
+            .set_default("policy_path", None::<String>)?;
 
         // 1. Prioritize loading config from executable directory (Production/Service)
         let mut config_found = false;
@@ -0,0 +1,242 @@
+use common::FileInfo;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub(crate) fn unix_secs(time: std::io::Result<std::time::SystemTime>) -> Option<u64> {
+    time.ok().map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+/// User and group names by id, loaded once per listing.
+#[derive(Default)]
+pub struct Owners {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Owners {
+    #[cfg(unix)]
+    pub fn load() -> Self {
+        let users = sysinfo::Users::new_with_refreshed_list();
+        let groups = sysinfo::Groups::new_with_refreshed_list();
+        Self {
+            users: users.list().iter().map(|u| (**u.id(), u.name().to_string())).collect(),
+            groups: groups.list().iter().map(|g| (**g.id(), g.name().to_string())).collect(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn load() -> Self {
+        Self::default()
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn user(&self, uid: u32) -> String {
+        self.users.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn group(&self, gid: u32) -> String {
+        self.groups.get(&gid).cloned().unwrap_or_else(|| gid.to_string())
+    }
+}
+
+// `ls -l` form of the mode, with `kind` as the first character
+#[cfg(unix)]
+fn permission_string(kind: char, mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(kind);
+    // setuid, setgid and sticky replace the x of user, group and other
+    for (shift, special, marker) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (mode & special != 0, bits & 0o1 != 0) {
+            (true, true) => marker,
+            (true, false) => marker.to_ascii_uppercase(),
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+#[cfg(unix)]
+fn kind_char(file_type: fs::FileType) -> char {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_dir() {
+        'd'
+    } else if file_type.is_char_device() {
+        'c'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else {
+        '-'
+    }
+}
+
+#[cfg(windows)]
+fn hidden_attribute(meta: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn hidden_attribute(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Describes the entry at `path` under the given name.
+pub fn describe(name: String, path: &Path, owners: &Owners) -> FileInfo {
+    let link = fs::symlink_metadata(path).ok();
+    let is_symlink = link.as_ref().is_some_and(|m| m.file_type().is_symlink());
+    let symlink_target = if is_symlink { fs::read_link(path).ok().map(|t| t.to_string_lossy().to_string()) } else { None };
+    let hidden = path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        || link.as_ref().is_some_and(hidden_attribute);
+    // A dangling link describes the link itself
+    let meta = if is_symlink { fs::metadata(path).ok().or(link) } else { link };
+    let Some(meta) = meta else {
+        return FileInfo { name, is_symlink, symlink_target, hidden, ..Default::default() };
+    };
+
+    #[cfg(unix)]
+    let (mode, permissions, owner, group) = {
+        use std::os::unix::fs::MetadataExt;
+        let mode = meta.mode() & 0o7777;
+        let kind = if is_symlink { 'l' } else { kind_char(meta.file_type()) };
+        (Some(mode), Some(permission_string(kind, mode)), Some(owners.user(meta.uid())), Some(owners.group(meta.gid())))
+    };
+    #[cfg(not(unix))]
+    let (mode, permissions, owner, group) = {
+        let _ = owners;
+        (None, None, None, None)
+    };
+
+    FileInfo {
+        name,
+        is_dir: meta.is_dir(),
+        size: meta.len(),
+        modified: unix_secs(meta.modified()),
+        created: unix_secs(meta.created()),
+        accessed: unix_secs(meta.accessed()),
+        mode,
+        permissions,
+        readonly: meta.permissions().readonly(),
+        owner,
+        group,
+        is_symlink,
+        symlink_target,
+        hidden,
+    }
+}
+
+/// Lists `root` down to `depth` levels in path order, starting after `cursor`. Returns the page
+/// and, when `limit` cut it short, the cursor for the next one.
+pub fn list_dir(root: &Path, depth: usize, limit: Option<usize>, cursor: Option<&str>) -> std::io::Result<(Vec<FileInfo>, Option<String>)> {
+    // Surfaces a missing or unreadable root the way read_dir reports it
+    fs::read_dir(root)?;
+    let owners = Owners::load();
+    let cursor = cursor.filter(|c| !c.is_empty()).map(PathBuf::from);
+    let limit = limit.map(|l| l.max(1));
+
+    // Sorting children by name makes the walk visit paths in `Path` order, so a page resumes
+    // from the cursor without listing earlier entries again.
+    let walker = WalkDir::new(root).min_depth(1).max_depth(depth.max(1)).sort_by_file_name();
+    let entries = walker.into_iter().filter_entry(|entry| match &cursor {
+        // Prunes directories that earlier pages finished; keeps the ancestors of the cursor
+        Some(cursor) => {
+            let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            rel > cursor.as_path() || cursor.starts_with(rel)
+        }
+        None => true,
+    });
+
+    let mut files = Vec::new();
+    let mut next_cursor = None;
+    for entry in entries.filter_map(Result::ok) {
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if cursor.as_deref().is_some_and(|c| rel <= c) {
+            continue;
+        }
+        if limit.is_some_and(|limit| files.len() >= limit) {
+            next_cursor = files.last().map(|f: &FileInfo| f.name.clone());
+            break;
+        }
+        files.push(describe(rel.to_string_lossy().to_string(), entry.path(), &owners));
+    }
+    Ok((files, next_cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(files: &[FileInfo]) -> Vec<String> {
+        files.iter().map(|f| f.name.replace('\\', "/")).collect()
+    }
+
+    // Pages through the listing and returns every name in order
+    fn all_pages(root: &Path, depth: usize, limit: usize) -> Vec<String> {
+        let mut listed = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let (files, next) = list_dir(root, depth, Some(limit), cursor.as_deref()).unwrap();
+            assert!(files.len() <= limit);
+            listed.extend(names(&files));
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return listed,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_cover_nested_entries_exactly_once() {
+        let root = std::env::temp_dir().join(format!("roam-files-{}", uuid::Uuid::new_v4()));
+        // "a-b" and "a.txt" sort between "a" and its children by name, but after them by path
+        for dir in ["a/x/deep", "a/y", "a-b", "b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["a/1.txt", "a/x/2.txt", "a/x/deep/3.txt", "a-b/4.txt", "a.txt", "b/5.txt", "c.txt"] {
+            fs::write(root.join(file), b"x").unwrap();
+        }
+
+        for depth in [1, 2, 3, 4] {
+            let (full, next) = list_dir(&root, depth, None, None).unwrap();
+            assert!(next.is_none());
+            let full = names(&full);
+            let mut sorted = full.clone();
+            sorted.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+            sorted.dedup();
+            assert_eq!(full, sorted, "depth {}", depth);
+            for limit in 1..=full.len() + 1 {
+                assert_eq!(all_pages(&root, depth, limit), full, "depth {} limit {}", depth, limit);
+            }
+        }
+        let (top, _) = list_dir(&root, 1, None, None).unwrap();
+        assert_eq!(names(&top), vec!["a", "a-b", "a.txt", "b", "c.txt"]);
+        let (deep, _) = list_dir(&root, 4, None, None).unwrap();
+        assert_eq!(deep.len(), 13);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cursor_of_a_removed_entry_resumes_after_it() {
+        let root = std::env::temp_dir().join(format!("roam-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("a/sub")).unwrap();
+        fs::write(root.join("a/sub/f"), b"x").unwrap();
+        fs::write(root.join("b"), b"x").unwrap();
+
+        let (files, _) = list_dir(&root, 3, None, Some("a/gone")).unwrap();
+        assert_eq!(names(&files), vec!["a/sub", "a/sub/f", "b"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use common::{DiskInfo, HardwareInfo, InterfaceInfo};
+use std::collections::{BTreeSet, HashSet};
+use sysinfo::{Disks, Networks, System};
+
+/// Collects the hardware and OS inventory of this host. Blocks for about a fifth of a second
+/// while CPU usage is measured.
+pub fn collect() -> HardwareInfo {
+    let mut sys = System::new_all();
+    std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+    sys.refresh_cpu_usage();
+
+    let cpu = sys.cpus().first();
+
+    // The same filesystem can be mounted more than once (bind mounts, snaps)
+    let mut seen = HashSet::new();
+    let disks = Disks::new_with_refreshed_list().list().iter()
+        .filter(|d| d.total_space() > 0)
+        .filter(|d| seen.insert(d.mount_point().to_path_buf()))
+        .map(|d| DiskInfo {
+            name: d.name().to_string_lossy().to_string(),
+            mount_point: d.mount_point().to_string_lossy().to_string(),
+            file_system: d.file_system().to_string_lossy().to_string(),
+            kind: d.kind().to_string(),
+            removable: d.is_removable(),
+            total_space: d.total_space(),
+            available_space: d.available_space(),
+        })
+        .collect();
+
+    let mut network_interfaces: Vec<InterfaceInfo> = Networks::new_with_refreshed_list().list().iter()
+        .map(|(name, data)| {
+            let mut addresses: Vec<String> = data.ip_networks().iter().map(|n| format!("{}/{}", n.addr, n.prefix)).collect();
+            addresses.sort();
+            InterfaceInfo { name: name.clone(), mac_address: data.mac_address().to_string(), addresses }
+        })
+        .collect();
+    network_interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+    HardwareInfo {
+        cpu_usage: sys.global_cpu_usage(),
+        total_memory: sys.total_memory(),
+        used_memory: sys.used_memory(),
+        platform: std::env::consts::OS.to_string(),
+        hostname: System::host_name().unwrap_or_default(),
+        cpu_model: cpu.map(|c| c.brand().trim().to_string()).unwrap_or_default(),
+        cpu_vendor: cpu.map(|c| c.vendor_id().to_string()).unwrap_or_default(),
+        cpu_cores: sys.cpus().len(),
+        physical_cores: sys.physical_core_count(),
+        os_name: System::name(),
+        os_version: System::long_os_version().or_else(System::os_version),
+        kernel_version: System::kernel_version(),
+        arch: System::cpu_arch(),
+        total_swap: sys.total_swap(),
+        disks,
+        network_interfaces,
+        uptime_sec: System::uptime(),
+        boot_time: System::boot_time(),
+        logged_in_users: logged_in_users(),
+    }
+}
+
+// Users with an interactive session, from `who` (utmp) or `query user` on Windows
+fn logged_in_users() -> Vec<String> {
+    let output = if cfg!(windows) {
+        std::process::Command::new("query").arg("user").output()
+    } else {
+        std::process::Command::new("who").output()
+    };
+    // `query user` exits non-zero when nobody is logged in
+    let Ok(output) = output else { return Vec::new() };
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let lines = text.lines().skip(if cfg!(windows) { 1 } else { 0 });
+    let users: BTreeSet<String> = lines
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|u| u.trim_start_matches('>').to_string())
+        .filter(|u| !u.is_empty())
+        .collect();
+    users.into_iter().collect()
+}
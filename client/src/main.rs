@@ -2,6 +2,13 @@ pub mod command_handler;
 pub mod config;
 pub mod service;
 pub mod app;
+pub mod policy;
+pub mod metrics;
+pub mod inventory;
+pub mod processes;
+pub mod services;
+pub mod tail;
+pub mod files;
 
 use clap::{Parser, Subcommand};
 
@@ -83,12 +90,12 @@ fn main() -> anyhow::Result<()> {
     };
 
     match cli.command {
-        Some(Commands::Install) => return service::install_service(),
-        Some(Commands::Uninstall) => return service::uninstall_service(),
-        Some(Commands::Start) => return service::start_service(),
-        Some(Commands::Stop) => return service::stop_service(),
+        Some(Commands::Install) => service::install_service(),
+        Some(Commands::Uninstall) => service::uninstall_service(),
+        Some(Commands::Start) => service::start_service(),
+        Some(Commands::Stop) => service::stop_service(),
         #[cfg(windows)]
-        Some(Commands::RunService) => return service::run_windows_service(),
+        Some(Commands::RunService) => service::run_windows_service(),
         None => {
             let rt = tokio::runtime::Runtime::new()?;
             rt.block_on(app::run(async {
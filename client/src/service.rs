@@ -10,13 +10,16 @@ pub fn install_service() -> anyhow::Result<()> {
         .ok_or_else(|| anyhow::anyhow!("Failed to get executable directory"))?
         .to_path_buf();
 
-    // Copy .env, client_config.toml/json if they exist in current directory
+    // Copy .env, client_config.toml/json and client_policy.* if they exist in current directory
     let current_dir = std::env::current_dir()?;
     let files_to_copy = vec![
         ".env", 
         "client_config.toml",
         "client_config.json",
-        "client_config.yaml"
+        "client_config.yaml",
+        "client_policy.toml",
+        "client_policy.json",
+        "client_policy.yaml"
     ];
     
     for filename in files_to_copy {
@@ -0,0 +1,479 @@
+use common::{ServiceAction, ServiceInfo};
+use service_manager::ServiceManagerKind;
+use std::collections::{BTreeMap, HashSet};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+// The service-manager crate only knows how to handle the services it installs, so arbitrary
+// services are driven through the native tool of the platform it detects.
+
+fn backend() -> Result<ServiceManagerKind, String> {
+    ServiceManagerKind::native().map_err(|e| format!("No supported service manager: {}", e))
+}
+
+// Service names are passed as separate arguments, so only option injection has to be ruled out
+fn check_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() || name.starts_with('-') || name.contains(['/', '\\']) || name.chars().any(char::is_control) {
+        return Err(format!("Invalid service name '{}'", name));
+    }
+    Ok(())
+}
+
+fn decode(bytes: &[u8]) -> String {
+    if cfg!(target_os = "windows") {
+        let (cow, _, _) = encoding_rs::GBK.decode(bytes);
+        cow.to_string()
+    } else {
+        String::from_utf8_lossy(bytes).to_string()
+    }
+}
+
+// Runs a command and returns its exit code and stdout
+fn output(program: &str, args: &[&str]) -> Result<(i32, String), String> {
+    let out = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    Ok((out.status.code().unwrap_or(-1), decode(&out.stdout)))
+}
+
+// Like `output`, but a non-zero exit is an error carrying what the tool printed
+fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let out = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if out.status.success() {
+        return Ok(decode(&out.stdout));
+    }
+    let stderr = decode(&out.stderr);
+    let message = if stderr.trim().is_empty() { decode(&out.stdout) } else { stderr };
+    Err(format!("{} {} failed: {}", program, args.join(" "), message.trim()))
+}
+
+pub fn list() -> Result<Vec<ServiceInfo>, String> {
+    let mut services = match backend()? {
+        ServiceManagerKind::Systemd => systemd::list()?,
+        ServiceManagerKind::OpenRc => openrc::list()?,
+        ServiceManagerKind::Sc | ServiceManagerKind::WinSw => sc::list()?,
+        ServiceManagerKind::Launchd => launchd::list()?,
+        ServiceManagerKind::Rcd => rcd::list()?,
+    };
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(services)
+}
+
+pub fn status(name: &str) -> Result<ServiceInfo, String> {
+    check_name(name)?;
+    match backend()? {
+        ServiceManagerKind::Systemd => systemd::status(name),
+        ServiceManagerKind::OpenRc => openrc::status(name),
+        ServiceManagerKind::Sc | ServiceManagerKind::WinSw => sc::status(name),
+        ServiceManagerKind::Launchd => launchd::status(name),
+        ServiceManagerKind::Rcd => rcd::status(name),
+    }
+}
+
+/// Applies the action and returns the state of the service afterwards.
+pub fn control(name: &str, action: ServiceAction) -> Result<ServiceInfo, String> {
+    check_name(name)?;
+    match backend()? {
+        ServiceManagerKind::Systemd => systemd::control(name, action)?,
+        ServiceManagerKind::OpenRc => openrc::control(name, action)?,
+        ServiceManagerKind::Sc | ServiceManagerKind::WinSw => sc::control(name, action)?,
+        ServiceManagerKind::Launchd => launchd::control(name, action)?,
+        ServiceManagerKind::Rcd => rcd::control(name, action)?,
+    }
+    status(name)
+}
+
+mod systemd {
+    use super::*;
+
+    // "nginx" means "nginx.service"; other unit suffixes are left alone
+    fn unit(name: &str) -> String {
+        if name.contains('.') { name.to_string() } else { format!("{}.service", name) }
+    }
+
+    fn state(active: &str) -> &'static str {
+        match active {
+            "active" | "reloading" => "running",
+            "inactive" => "stopped",
+            "failed" => "failed",
+            "activating" => "starting",
+            "deactivating" => "stopping",
+            _ => "unknown",
+        }
+    }
+
+    // `list-units --plain` pads UNIT and the other columns to their widest value, so columns are
+    // split on runs of whitespace and the description is whatever follows the fourth one
+    pub(super) fn unit_columns(line: &str) -> Option<([&str; 4], &str)> {
+        let mut rest = line.trim_start_matches(|c: char| c == '●' || c.is_whitespace());
+        let mut cols = [""; 4];
+        for col in &mut cols {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (*col, rest) = rest.split_at(end);
+        }
+        (!cols[3].is_empty()).then(|| (cols, rest.trim()))
+    }
+
+    pub fn list() -> Result<Vec<ServiceInfo>, String> {
+        let files = run("systemctl", &["list-unit-files", "--type=service", "--no-legend", "--no-pager"])?;
+        let startup: BTreeMap<String, String> = files.lines()
+            .filter_map(|l| {
+                let mut cols = l.split_whitespace();
+                Some((cols.next()?.to_string(), cols.next()?.to_string()))
+            })
+            .collect();
+
+        let units = run("systemctl", &["list-units", "--type=service", "--all", "--no-legend", "--no-pager", "--plain"])?;
+        let mut services: BTreeMap<String, ServiceInfo> = BTreeMap::new();
+        for line in units.lines() {
+            let Some(([name, load, active, sub], description)) = unit_columns(line) else { continue };
+            if load == "not-found" {
+                continue;
+            }
+            let description = description.to_string();
+            services.insert(name.to_string(), ServiceInfo {
+                name: name.to_string(),
+                description: (!description.is_empty()).then_some(description),
+                state: state(active).to_string(),
+                native_state: Some(sub.to_string()),
+                startup: startup.get(name).cloned(),
+                pid: None,
+            });
+        }
+        // Installed but never loaded units; templates can't run without an instance name
+        for (name, mode) in startup {
+            if !name.ends_with("@.service") && !services.contains_key(&name) {
+                services.insert(name.clone(), ServiceInfo {
+                    name,
+                    description: None,
+                    state: "stopped".to_string(),
+                    native_state: None,
+                    startup: Some(mode),
+                    pid: None,
+                });
+            }
+        }
+        Ok(services.into_values().collect())
+    }
+
+    pub fn status(name: &str) -> Result<ServiceInfo, String> {
+        let unit = unit(name);
+        let out = run("systemctl", &["show", &unit, "--no-pager", "--property=Id,Description,LoadState,ActiveState,SubState,UnitFileState,MainPID"])?;
+        let props: BTreeMap<&str, &str> = out.lines().filter_map(|l| l.split_once('=')).collect();
+        if props.get("LoadState") == Some(&"not-found") {
+            return Err(format!("Service {} not found", unit));
+        }
+        let non_empty = |key: &str| props.get(key).filter(|v| !v.is_empty()).map(|v| v.to_string());
+        Ok(ServiceInfo {
+            name: non_empty("Id").unwrap_or(unit),
+            description: non_empty("Description"),
+            state: state(props.get("ActiveState").copied().unwrap_or("")).to_string(),
+            native_state: non_empty("SubState"),
+            startup: non_empty("UnitFileState"),
+            pid: props.get("MainPID").and_then(|p| p.parse().ok()).filter(|p| *p != 0),
+        })
+    }
+
+    pub fn control(name: &str, action: ServiceAction) -> Result<(), String> {
+        run("systemctl", &[&action.to_string(), &unit(name)]).map(|_| ())
+    }
+}
+
+mod openrc {
+    use super::*;
+
+    fn state(native: &str) -> &'static str {
+        match native {
+            "started" => "running",
+            "stopped" | "inactive" => "stopped",
+            "crashed" => "failed",
+            "starting" => "starting",
+            "stopping" => "stopping",
+            _ => "unknown",
+        }
+    }
+
+    // Service -> runlevels it is added to
+    fn runlevels() -> Result<BTreeMap<String, String>, String> {
+        let out = run("rc-update", &["show", "-v"])?;
+        Ok(out.lines()
+            .filter_map(|l| l.split_once('|'))
+            .map(|(name, levels)| (name.trim().to_string(), levels.trim().to_string()))
+            .collect())
+    }
+
+    fn startup(levels: Option<&String>) -> Option<String> {
+        levels.map(|l| if l.is_empty() { "disabled".to_string() } else { format!("enabled ({})", l) })
+    }
+
+    pub fn list() -> Result<Vec<ServiceInfo>, String> {
+        let levels = runlevels()?;
+        let out = run("rc-status", &["--all", "--nocolor"])?;
+        let mut seen = HashSet::new();
+        let mut services = Vec::new();
+        // Lines look like " sshd      [  started  ]"; runlevel headers have no brackets
+        for line in out.lines() {
+            let (Some(open), Some(close)) = (line.find('['), line.rfind(']')) else { continue };
+            let Some(name) = line[..open].split_whitespace().next() else { continue };
+            if !seen.insert(name.to_string()) {
+                continue;
+            }
+            let native = line[open + 1..close].split_whitespace().next().unwrap_or("").to_string();
+            services.push(ServiceInfo {
+                name: name.to_string(),
+                description: None,
+                state: state(&native).to_string(),
+                native_state: Some(native),
+                startup: startup(levels.get(name)),
+                pid: None,
+            });
+        }
+        for (name, l) in &levels {
+            if seen.insert(name.clone()) {
+                services.push(ServiceInfo {
+                    name: name.clone(),
+                    description: None,
+                    state: "stopped".to_string(),
+                    native_state: None,
+                    startup: startup(Some(l)),
+                    pid: None,
+                });
+            }
+        }
+        Ok(services)
+    }
+
+    pub fn status(name: &str) -> Result<ServiceInfo, String> {
+        let (_, out) = output("rc-service", &[name, "status"])?;
+        // " * status: started"
+        let Some(native) = out.lines().find_map(|l| l.split_once("status:").map(|(_, s)| s.trim().to_string())) else {
+            return Err(format!("Service {} not found", name));
+        };
+        Ok(ServiceInfo {
+            name: name.to_string(),
+            description: None,
+            state: state(&native).to_string(),
+            native_state: Some(native),
+            startup: startup(runlevels()?.get(name)),
+            pid: None,
+        })
+    }
+
+    pub fn control(name: &str, action: ServiceAction) -> Result<(), String> {
+        match action {
+            ServiceAction::Enable => run("rc-update", &["add", name, "default"]),
+            ServiceAction::Disable => run("rc-update", &["del", name, "default"]),
+            _ => run("rc-service", &[name, &action.to_string()]),
+        }.map(|_| ())
+    }
+}
+
+mod sc {
+    use super::*;
+
+    fn state(native: &str) -> &'static str {
+        match native {
+            "RUNNING" => "running",
+            "STOPPED" => "stopped",
+            "START_PENDING" | "CONTINUE_PENDING" => "starting",
+            "STOP_PENDING" | "PAUSE_PENDING" => "stopping",
+            _ => "unknown",
+        }
+    }
+
+    // `sc` prints "KEY : value" lines, one block per service
+    fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+        let (k, v) = line.split_once(':')?;
+        (k.trim() == key).then(|| v.trim())
+    }
+
+    // "4  RUNNING" -> "RUNNING", "2   AUTO_START  (DELAYED)" -> "AUTO_START"
+    fn symbol(value: &str) -> String {
+        value.split_whitespace().nth(1).unwrap_or("").to_string()
+    }
+
+    pub fn list() -> Result<Vec<ServiceInfo>, String> {
+        let out = run("sc", &["query", "type=", "service", "state=", "all"])?;
+        let mut services: Vec<ServiceInfo> = Vec::new();
+        for line in out.lines() {
+            if let Some(name) = field(line, "SERVICE_NAME") {
+                services.push(ServiceInfo {
+                    name: name.to_string(),
+                    description: None,
+                    state: "unknown".to_string(),
+                    native_state: None,
+                    startup: None,
+                    pid: None,
+                });
+            } else if let Some(service) = services.last_mut() {
+                if let Some(display) = field(line, "DISPLAY_NAME") {
+                    service.description = Some(display.to_string());
+                } else if let Some(value) = field(line, "STATE") {
+                    let native = symbol(value);
+                    service.state = state(&native).to_string();
+                    service.native_state = Some(native);
+                }
+            }
+        }
+        Ok(services)
+    }
+
+    pub fn status(name: &str) -> Result<ServiceInfo, String> {
+        let query = run("sc", &["queryex", name])?;
+        let config = run("sc", &["qc", name])?;
+        let native = query.lines().find_map(|l| field(l, "STATE")).map(symbol).unwrap_or_default();
+        let startup = config.lines().find_map(|l| field(l, "START_TYPE")).map(|v| match symbol(v).as_str() {
+            "AUTO_START" => "auto".to_string(),
+            "DEMAND_START" => "manual".to_string(),
+            "DISABLED" => "disabled".to_string(),
+            "BOOT_START" => "boot".to_string(),
+            "SYSTEM_START" => "system".to_string(),
+            other => other.to_lowercase(),
+        });
+        Ok(ServiceInfo {
+            name: name.to_string(),
+            description: config.lines().find_map(|l| field(l, "DISPLAY_NAME")).map(str::to_string),
+            state: state(&native).to_string(),
+            native_state: Some(native),
+            startup,
+            pid: query.lines().find_map(|l| field(l, "PID")).and_then(|p| p.parse().ok()).filter(|p| *p != 0),
+        })
+    }
+
+    pub fn control(name: &str, action: ServiceAction) -> Result<(), String> {
+        match action {
+            ServiceAction::Start => run("sc", &["start", name]).map(|_| ()),
+            ServiceAction::Stop => run("sc", &["stop", name]).map(|_| ()),
+            ServiceAction::Enable => run("sc", &["config", name, "start=", "auto"]).map(|_| ()),
+            ServiceAction::Disable => run("sc", &["config", name, "start=", "disabled"]).map(|_| ()),
+            ServiceAction::Restart => {
+                // SCM has no restart; a service that isn't running just gets started
+                if status(name)?.state != "stopped" {
+                    run("sc", &["stop", name])?;
+                    let deadline = Instant::now() + Duration::from_secs(30);
+                    while status(name)?.state != "stopped" {
+                        if Instant::now() > deadline {
+                            return Err(format!("Service {} did not stop within 30s", name));
+                        }
+                        std::thread::sleep(Duration::from_millis(500));
+                    }
+                }
+                run("sc", &["start", name]).map(|_| ())
+            }
+        }
+    }
+}
+
+mod launchd {
+    use super::*;
+
+    pub fn list() -> Result<Vec<ServiceInfo>, String> {
+        let out = run("launchctl", &["list"])?;
+        // "PID\tStatus\tLabel", with "-" for no pid
+        Ok(out.lines().skip(1).filter_map(|line| {
+            let mut cols = line.split('\t');
+            let (pid, last_exit, label) = (cols.next()?, cols.next()?, cols.next()?);
+            let pid = pid.parse().ok();
+            Some(ServiceInfo {
+                name: label.to_string(),
+                description: None,
+                state: if pid.is_some() { "running" } else { "stopped" }.to_string(),
+                native_state: Some(format!("last exit {}", last_exit)),
+                startup: None,
+                pid,
+            })
+        }).collect())
+    }
+
+    pub fn status(name: &str) -> Result<ServiceInfo, String> {
+        list()?.into_iter().find(|s| s.name == name).ok_or_else(|| format!("Service {} not found", name))
+    }
+
+    pub fn control(name: &str, action: ServiceAction) -> Result<(), String> {
+        let target = format!("system/{}", name);
+        match action {
+            ServiceAction::Start => run("launchctl", &["kickstart", &target]),
+            ServiceAction::Stop => run("launchctl", &["kill", "SIGTERM", &target]),
+            ServiceAction::Restart => run("launchctl", &["kickstart", "-k", &target]),
+            ServiceAction::Enable => run("launchctl", &["enable", &target]),
+            ServiceAction::Disable => run("launchctl", &["disable", &target]),
+        }.map(|_| ())
+    }
+}
+
+mod rcd {
+    use super::*;
+
+    fn enabled() -> Result<HashSet<String>, String> {
+        // Paths of the scripts enabled in rc.conf
+        let out = run("service", &["-e"])?;
+        Ok(out.lines().filter_map(|l| l.rsplit('/').next()).map(str::to_string).collect())
+    }
+
+    fn startup(enabled: &HashSet<String>, name: &str) -> Option<String> {
+        Some(if enabled.contains(name) { "enabled" } else { "disabled" }.to_string())
+    }
+
+    pub fn list() -> Result<Vec<ServiceInfo>, String> {
+        let enabled = enabled()?;
+        let out = run("service", &["-l"])?;
+        // Checking the state would run every rc script's status command, so it is left unknown
+        Ok(out.lines().map(str::trim).filter(|l| !l.is_empty()).map(|name| ServiceInfo {
+            name: name.to_string(),
+            description: None,
+            state: "unknown".to_string(),
+            native_state: None,
+            startup: startup(&enabled, name),
+            pid: None,
+        }).collect())
+    }
+
+    pub fn status(name: &str) -> Result<ServiceInfo, String> {
+        let (code, out) = output("service", &[name, "onestatus"])?;
+        // "sshd is running as pid 812."
+        let pid = out.split("pid").nth(1).and_then(|s| s.trim().trim_end_matches('.').parse().ok());
+        Ok(ServiceInfo {
+            name: name.to_string(),
+            description: None,
+            state: if code == 0 { "running" } else { "stopped" }.to_string(),
+            native_state: Some(out.trim().to_string()).filter(|s| !s.is_empty()),
+            startup: startup(&enabled()?, name),
+            pid,
+        })
+    }
+
+    pub fn control(name: &str, action: ServiceAction) -> Result<(), String> {
+        // one* variants work whether or not the service is enabled in rc.conf
+        let verb = match action {
+            ServiceAction::Start => "onestart",
+            ServiceAction::Stop => "onestop",
+            ServiceAction::Restart => "onerestart",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        };
+        run("service", &[name, verb]).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::systemd::unit_columns;
+
+    #[test]
+    fn systemd_columns_with_padding() {
+        let line = "cron.service                 loaded active   running Regular background program processing daemon";
+        assert_eq!(
+            unit_columns(line),
+            Some((["cron.service", "loaded", "active", "running"], "Regular background program processing daemon"))
+        );
+        let failed = "● nginx.service     loaded    failed   failed  A high performance web server";
+        assert_eq!(unit_columns(failed).map(|(cols, _)| cols[2]), Some("failed"));
+        assert_eq!(unit_columns("x.service loaded inactive dead"), Some((["x.service", "loaded", "inactive", "dead"], "")));
+        assert_eq!(unit_columns("x.service   loaded   active"), None);
+    }
+}
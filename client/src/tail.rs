@@ -0,0 +1,257 @@
+use common::{CommandResult, Message};
+use std::fs::Metadata;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+use uuid::Uuid;
+
+pub const DEFAULT_LINES: usize = 100;
+const MAX_LINES: usize = 10_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Caps what one poll reads and one message carries when a file grows in a burst
+const MAX_READ: u64 = 1024 * 1024;
+const MAX_BATCH: usize = 1000;
+// A line that never ends is still delivered once it gets this long
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Returns the last `lines` lines of the file and the offset they end at. At most `MAX_READ`
+/// bytes are read, so a file without newlines yields its tail as one line.
+pub fn last_lines(path: &str, lines: usize) -> std::io::Result<(Vec<String>, u64)> {
+    let lines = lines.min(MAX_LINES);
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // Read blocks backwards until there are enough newlines; a trailing newline doesn't start a line
+    let mut start = len;
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+    let mut read = 0u64;
+    let mut newlines = 0;
+    let mut needed = lines;
+    while start > 0 && newlines <= needed && read < MAX_READ {
+        let size = (64 * 1024).min(start).min(MAX_READ - read) as usize;
+        start -= size as u64;
+        let mut block = vec![0u8; size];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block)?;
+        if blocks.is_empty() && block.ends_with(b"\n") {
+            needed += 1;
+        }
+        newlines += block.iter().filter(|b| **b == b'\n').count();
+        read += size as u64;
+        blocks.push(block);
+    }
+    let mut tail: Vec<u8> = blocks.into_iter().rev().flatten().collect();
+    // Cut off mid-line by the cap: drop the partial first line when there are whole ones
+    if start > 0 && newlines <= needed {
+        let first = tail.iter().position(|b| *b == b'\n').map_or(0, |i| i + 1);
+        if first < tail.len() {
+            tail.drain(..first);
+        }
+    }
+
+    let text = String::from_utf8_lossy(&tail);
+    let all: Vec<&str> = text.lines().collect();
+    let skip = all.len().saturating_sub(lines);
+    Ok((all[skip..].iter().map(|l| l.to_string()).collect(), len))
+}
+
+// Identifies the file behind a path, so a rotated (renamed and recreated) log is noticed
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    let created = meta.created().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some((created.as_secs(), created.subsec_nanos() as u64))
+}
+
+// Splits off the complete lines in `pending`, leaving a trailing partial line
+fn take_lines(pending: &mut Vec<u8>) -> Vec<String> {
+    let end = match pending.iter().rposition(|b| *b == b'\n') {
+        Some(i) => i + 1,
+        None if pending.len() >= MAX_PENDING => pending.len(),
+        None => return Vec::new(),
+    };
+    let complete: Vec<u8> = pending.drain(..end).collect();
+    String::from_utf8_lossy(&complete).lines().map(str::to_string).collect()
+}
+
+struct Follower {
+    id: Uuid,
+    tx: mpsc::Sender<Message>,
+}
+
+impl Follower {
+    // Err means the connection is gone and following should stop
+    async fn send(&self, lines: Vec<String>, notice: Option<String>) -> Result<(), ()> {
+        if lines.is_empty() && notice.is_none() {
+            return Ok(());
+        }
+        let mut batches: Vec<Vec<String>> = lines.chunks(MAX_BATCH).map(|c| c.to_vec()).collect();
+        if batches.is_empty() {
+            batches.push(Vec::new());
+        }
+        let mut notice = notice;
+        for lines in batches {
+            let msg = Message::CommandOutput { id: self.id, lines, notice: notice.take() };
+            self.tx.send(msg).await.map_err(|_| ())?;
+        }
+        Ok(())
+    }
+}
+
+async fn read_appended(file: &mut File, offset: &mut u64, pending: &mut Vec<u8>) -> std::io::Result<Vec<String>> {
+    let mut buf = Vec::new();
+    (&mut *file).take(MAX_READ).read_to_end(&mut buf).await?;
+    *offset += buf.len() as u64;
+    pending.extend_from_slice(&buf);
+    Ok(take_lines(pending))
+}
+
+/// Sends the last `lines` lines, then every appended line, until `cancel` fires or the
+/// connection drops. A truncated file is read again from the start; a replaced one (rotation)
+/// is drained and then the new file at the same path is followed.
+pub async fn follow(id: Uuid, path: String, lines: usize, tx: mpsc::Sender<Message>, mut cancel: oneshot::Receiver<()>) -> CommandResult {
+    let initial = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || last_lines(&path, lines)).await
+    };
+    let (initial, mut offset) = match initial {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => return CommandResult::Error(format!("Failed to read {}: {}", path, e)),
+        Err(e) => return CommandResult::Error(format!("Failed to read {}: {}", path, e)),
+    };
+    let mut file = match File::open(&path).await {
+        Ok(f) => f,
+        Err(e) => return CommandResult::Error(format!("Failed to open {}: {}", path, e)),
+    };
+    let mut current = match file.metadata().await {
+        Ok(m) => file_id(&m),
+        Err(e) => return CommandResult::Error(format!("Failed to read metadata of {}: {}", path, e)),
+    };
+    if let Err(e) = file.seek(SeekFrom::Start(offset)).await {
+        return CommandResult::Error(format!("Failed to seek in {}: {}", path, e));
+    }
+
+    let follower = Follower { id, tx };
+    // The first message also tells the browser that following has started
+    if follower.tx.send(Message::CommandOutput { id, lines: initial, notice: None }).await.is_err() {
+        return CommandResult::Success(format!("Stopped following {}", path));
+    }
+
+    let mut pending = Vec::new();
+    let mut missing = false;
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut cancel => break,
+            _ = interval.tick() => {}
+        }
+
+        let mut notice = None;
+        let mut lines = Vec::new();
+        match tokio::fs::metadata(&path).await {
+            Ok(meta) if file_id(&meta) != current => {
+                // Rotated: finish the old file, then switch to the new one from its start
+                if let Ok(rest) = read_appended(&mut file, &mut offset, &mut pending).await {
+                    if follower.send(rest, None).await.is_err() {
+                        break;
+                    }
+                }
+                match File::open(&path).await {
+                    Ok(f) => {
+                        file = f;
+                        current = file_id(&meta);
+                        offset = 0;
+                        pending.clear();
+                        missing = false;
+                        notice = Some(format!("{} was replaced; following the new file", path));
+                    }
+                    Err(e) => warn!("Failed to reopen {}: {}", path, e),
+                }
+            }
+            Ok(meta) if meta.len() < offset => {
+                if let Err(e) = file.seek(SeekFrom::Start(0)).await {
+                    return CommandResult::Error(format!("Failed to seek in {}: {}", path, e));
+                }
+                offset = 0;
+                pending.clear();
+                missing = false;
+                notice = Some(format!("{} was truncated", path));
+            }
+            Ok(_) => missing = false,
+            Err(_) if !missing => {
+                // Usually the gap between renaming a log and creating the new one
+                missing = true;
+                notice = Some(format!("{} is gone; waiting for it to reappear", path));
+            }
+            Err(_) => {}
+        }
+
+        match read_appended(&mut file, &mut offset, &mut pending).await {
+            Ok(more) => lines.extend(more),
+            Err(e) => return CommandResult::Error(format!("Failed to read {}: {}", path, e)),
+        }
+        if follower.send(lines, notice).await.is_err() {
+            break;
+        }
+    }
+    CommandResult::Success(format!("Stopped following {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("roam-tail-{}-{}", name, Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn last_lines_of_small_files() {
+        let path = temp_file("small", b"a\nb\nc\n");
+        assert_eq!(last_lines(&path, 2).unwrap(), (vec!["b".to_string(), "c".to_string()], 6));
+        assert_eq!(last_lines(&path, 10).unwrap().0, vec!["a", "b", "c"]);
+        std::fs::write(&path, b"a\nb\nc").unwrap();
+        assert_eq!(last_lines(&path, 2).unwrap().0, vec!["b", "c"]);
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(last_lines(&path, 2).unwrap(), (Vec::new(), 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_lines_across_blocks() {
+        let content: String = (0..20_000).map(|i| format!("line {}\n", i)).collect();
+        let path = temp_file("blocks", content.as_bytes());
+        let (lines, end) = last_lines(&path, 3).unwrap();
+        assert_eq!(lines, vec!["line 19997", "line 19998", "line 19999"]);
+        assert_eq!(end, content.len() as u64);
+        assert_eq!(last_lines(&path, 9000).unwrap().0.first().map(String::as_str), Some("line 11000"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_lines_reads_at_most_max_read() {
+        let huge = vec![b'x'; 3 * MAX_READ as usize];
+        let path = temp_file("huge", &huge);
+        let (lines, end) = last_lines(&path, 5).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), MAX_READ as usize);
+        assert_eq!(end, huge.len() as u64);
+
+        // Lines cut by the cap are not returned half
+        let mut content = vec![b'y'; 2 * MAX_READ as usize];
+        content.extend_from_slice(b"\nlast\n");
+        std::fs::write(&path, &content).unwrap();
+        assert_eq!(last_lines(&path, 5).unwrap().0, vec!["last"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
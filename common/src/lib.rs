@@ -32,6 +32,22 @@ pub enum Message {
         id: Uuid, // Correlates to Command ID
         result: CommandResult,
     },
+
+    // Periodic resource usage (Client -> Server)
+    Metrics(MetricsSample),
+
+    // Hardware and OS inventory, sent once after registration (Client -> Server)
+    Inventory(Box<HardwareInfo>),
+
+    // Partial output of a running command, e.g. lines appended to a followed file (Client -> Server).
+    // The command still ends with a Response.
+    CommandOutput {
+        id: Uuid,
+        lines: Vec<String>,
+        // Something that happened to the source, such as a log rotation
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        notice: Option<String>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -43,7 +59,18 @@ pub enum CommandPayload {
     DownloadFile { url: String, dest_path: String }, 
     // Server provides a URL for the client to upload file TO
     UploadFile { src_path: String, upload_url: String }, 
-    ListDir { path: String },
+    // Entries are sorted by path. `depth` > 1 also lists subdirectories, naming entries by their
+    // path relative to `path`. With `limit`, the response carries a `next_cursor` to pass back for
+    // the next page.
+    ListDir {
+        path: String,
+        #[serde(default)]
+        depth: Option<usize>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        cursor: Option<String>,
+    },
     GetHardwareInfo,
     UpdateClient { url: String },
     ReadFile { path: String },
@@ -55,6 +82,93 @@ pub enum CommandPayload {
     CopyFile { src_path: String, dest_path: String },
     MoveFile { src_path: String, dest_path: String },
     DeleteFile { path: String },
+    ListProcesses,
+    // `signal` is a name such as "TERM", "KILL" or "HUP"; defaults to TERM. Windows only supports KILL
+    // and treats TERM (the default) as KILL.
+    KillProcess { pid: u32, signal: Option<String> },
+    // System services (systemd, OpenRC, Windows SCM, launchd, rc.d)
+    ListServices,
+    GetServiceStatus { name: String },
+    ControlService { name: String, action: ServiceAction },
+    // Last `lines` lines of a file (default 100). With `follow`, appended lines are streamed as
+    // CommandOutput until the command is cancelled.
+    TailFile {
+        path: String,
+        lines: Option<usize>,
+        #[serde(default)]
+        follow: bool,
+    },
+    // Stops a running streaming command, such as TailFile with follow
+    CancelCommand { id: Uuid },
+    FindFiles(FindQuery),
+    SearchContent(SearchQuery),
+}
+
+impl CommandPayload {
+    /// The variant name (e.g. "ShellExec"), for logs that must not show arguments,
+    /// which can carry resolved secrets.
+    pub fn kind(&self) -> String {
+        serde_json::to_value(self).ok()
+            .and_then(|v| v["cmd_type"].as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+/// Walks `path` for files and directories. All filters are optional; sizes are bytes and
+/// times Unix seconds. Size filters only match files.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct FindQuery {
+    pub path: String,
+    // Glob such as "*.log"; matched against the path relative to `path` when it contains a '/'
+    pub pattern: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<u64>,
+    pub modified_before: Option<u64>,
+    // 1 lists only the entries of `path` itself
+    pub max_depth: Option<usize>,
+    // Defaults to 1000
+    pub max_results: Option<usize>,
+}
+
+/// Searches the lines of text files under `path` (or `path` itself) for a regex.
+/// Binary files and files over 64 MiB are skipped.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SearchQuery {
+    pub path: String,
+    pub pattern: String,
+    pub ignore_case: bool,
+    // Only search files whose name matches this glob
+    pub glob: Option<String>,
+    // Lines of context before and after each match
+    pub context: Option<usize>,
+    pub max_depth: Option<usize>,
+    // Matches, defaults to 1000
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Enable,
+    Disable,
+}
+
+impl std::fmt::Display for ServiceAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -62,25 +176,182 @@ pub enum CommandPayload {
 pub enum CommandResult {
     ShellOutput { stdout: String, stderr: String, exit_code: i32, cwd: String },
     DirChanged { new_path: String },
-    FileList { files: Vec<FileInfo> },
+    FileList {
+        files: Vec<FileInfo>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+    },
     FileContent { content: String },
-    HardwareInfo(HardwareInfo),
+    HardwareInfo(Box<HardwareInfo>),
+    ProcessList { processes: Vec<ProcessInfo> },
+    ServiceList { services: Vec<ServiceInfo> },
+    // State of one service, also returned after ControlService
+    ServiceStatus(ServiceInfo),
+    FileTail { path: String, lines: Vec<String> },
+    // `name` of each entry is its path relative to `root`
+    FoundFiles { root: String, files: Vec<FileInfo>, truncated: bool },
+    ContentMatches { root: String, matches: Vec<ContentMatch>, files_searched: usize, truncated: bool },
     Success(String),
     Error(String),
+    // Rejected by the client's local command policy
+    PolicyDenied(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Times are Unix seconds. A symlink reports the size, times and mode of its target.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct FileInfo {
     pub name: String,
     pub is_dir: bool,
     pub size: u64,
     pub modified: Option<u64>,
+    // The rest is missing from older clients
+    #[serde(default)]
+    pub created: Option<u64>,
+    #[serde(default)]
+    pub accessed: Option<u64>,
+    // Unix permission bits (e.g. 0o644) and their `ls -l` form such as "-rw-r--r--"; None on Windows
+    #[serde(default)]
+    pub mode: Option<u32>,
+    #[serde(default)]
+    pub permissions: Option<String>,
+    #[serde(default)]
+    pub readonly: bool,
+    // User and group names, or the numeric id when it has no name; None on Windows
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub is_symlink: bool,
+    #[serde(default)]
+    pub symlink_target: Option<String>,
+    // Dot files, and on Windows files with the hidden attribute
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContentMatch {
+    // Relative to the searched root
+    pub path: String,
+    // 1-based
+    pub line_number: usize,
+    pub line: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: Option<u32>,
+    pub name: String,
+    pub cmdline: Vec<String>,
+    pub exe: Option<String>,
+    pub user: Option<String>,
+    pub status: String,
+    // Percent of one core, so it can exceed 100 on multi-core hosts
+    pub cpu_usage: f32,
+    // Resident and virtual memory in bytes
+    pub memory: u64,
+    pub virtual_memory: u64,
+    // Unix seconds
+    pub start_time: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub description: Option<String>,
+    // "running", "stopped", "failed", "starting", "stopping" or "unknown"
+    pub state: String,
+    // State as reported by the service manager, e.g. systemd's sub-state "exited"
+    pub native_state: Option<String>,
+    // Start mode, e.g. "enabled", "disabled", "static" (systemd) or "auto", "manual" (Windows)
+    pub startup: Option<String>,
+    pub pid: Option<u32>,
+}
+
+// Fields after `platform` are missing from older clients and default to empty
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
 pub struct HardwareInfo {
     pub cpu_usage: f32,
     pub total_memory: u64,
     pub used_memory: u64,
     pub platform: String,
+    pub hostname: String,
+    pub cpu_model: String,
+    pub cpu_vendor: String,
+    // Logical cores
+    pub cpu_cores: usize,
+    pub physical_cores: Option<usize>,
+    pub os_name: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: String,
+    pub total_swap: u64,
+    pub disks: Vec<DiskInfo>,
+    pub network_interfaces: Vec<InterfaceInfo>,
+    pub uptime_sec: u64,
+    // Unix seconds
+    pub boot_time: u64,
+    pub logged_in_users: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskInfo {
+    pub name: String,
+    pub mount_point: String,
+    pub file_system: String,
+    // "SSD", "HDD" or "Unknown"
+    pub kind: String,
+    pub removable: bool,
+    pub total_space: u64,
+    pub available_space: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub mac_address: String,
+    // Addresses with prefix length, e.g. "192.168.1.5/24"
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsSample {
+    pub cpu_usage: f32,
+    pub cpu_per_core: Vec<f32>,
+    pub total_memory: u64,
+    pub used_memory: u64,
+    pub total_swap: u64,
+    pub used_swap: u64,
+    pub disks: Vec<DiskUsage>,
+    pub network: Vec<NetworkUsage>,
+    // None where the platform has no load average (Windows)
+    pub load_average: Option<LoadAverage>,
+    pub process_count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    pub total_space: u64,
+    pub used_space: u64,
+}
+
+// Throughput since the previous sample
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkUsage {
+    pub interface: String,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
 }
@@ -0,0 +1,569 @@
+use common::MetricsSample;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{Pool, Row, Sqlite};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::events::{self, ServerEvent};
+use crate::state::AppState;
+use crate::{handlers, metrics, template, webhooks};
+
+// Rules are checked against every metrics sample a client pushes. A breach opens a 'pending'
+// alert, which fires once the breach has lasted `duration_sec` and resolves on the first sample
+// back within the threshold. Resolved alerts are kept as history.
+
+// Percentages of capacity
+pub const CPU: &str = "cpu";
+pub const MEMORY: &str = "memory";
+pub const SWAP: &str = "swap";
+// Usage of `mount_point`, or of the fullest disk when it is None
+pub const DISK: &str = "disk";
+// Absolute values
+pub const LOAD_1: &str = "load_1";
+pub const LOAD_5: &str = "load_5";
+pub const LOAD_15: &str = "load_15";
+pub const PROCESS_COUNT: &str = "process_count";
+// Bytes per second over all interfaces except loopback
+pub const NET_RX: &str = "net_rx";
+pub const NET_TX: &str = "net_tx";
+
+pub const METRICS: &[&str] = &[CPU, MEMORY, SWAP, DISK, LOAD_1, LOAD_5, LOAD_15, PROCESS_COUNT, NET_RX, NET_TX];
+pub const OPERATORS: &[&str] = &["above", "below"];
+
+pub const PENDING: &str = "pending";
+pub const FIRING: &str = "firing";
+pub const RESOLVED: &str = "resolved";
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Rule {
+    pub id: Uuid,
+    pub name: String,
+    pub metric: String,
+    pub mount_point: Option<String>,
+    pub operator: String,
+    pub threshold: f64,
+    // How long the breach must last before the alert fires; 0 fires on the first sample
+    pub duration_sec: u64,
+    // Scope: one client, the members of one group, or every client when both are None
+    pub client_id: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    // Run on the client when the alert fires
+    pub remediation_script_id: Option<Uuid>,
+    pub remediation_params: HashMap<String, Value>,
+    pub enabled: bool,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Alert {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub rule_name: String,
+    pub client_id: Uuid,
+    pub state: String,
+    pub metric: String,
+    pub threshold: f64,
+    // Latest value seen while the alert was open, or the one that resolved it
+    pub value: f64,
+    // When the breach began
+    pub started_at: String,
+    pub fired_at: Option<String>,
+    pub resolved_at: Option<String>,
+    // 'running', 'succeeded', 'failed' or a reason it could not start
+    pub remediation_status: Option<String>,
+}
+
+pub fn validate(rule: &Rule) -> Result<(), String> {
+    if !METRICS.contains(&rule.metric.as_str()) {
+        return Err(format!("Unknown metric '{}', expected one of: {}", rule.metric, METRICS.join(", ")));
+    }
+    if !OPERATORS.contains(&rule.operator.as_str()) {
+        return Err(format!("Unknown operator '{}', expected above or below", rule.operator));
+    }
+    if !rule.threshold.is_finite() {
+        return Err("Threshold must be a number".to_string());
+    }
+    if rule.mount_point.is_some() && rule.metric != DISK {
+        return Err("mount_point only applies to the disk metric".to_string());
+    }
+    if rule.client_id.is_some() && rule.group_id.is_some() {
+        return Err("Set either client_id or group_id, not both".to_string());
+    }
+    Ok(())
+}
+
+fn now() -> String {
+    chrono::Utc::now().format(TIME_FORMAT).to_string()
+}
+
+const SELECT_RULES: &str = "SELECT id, name, metric, mount_point, operator, threshold, duration_sec, client_id, group_id, remediation_script_id, remediation_params, enabled, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at FROM alert_rules";
+
+fn rule_from_row(r: &sqlx::sqlite::SqliteRow) -> Rule {
+    let parse_id = |col: &str| r.get::<Option<String>, _>(col).and_then(|s| Uuid::parse_str(&s).ok());
+    Rule {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        name: r.get("name"),
+        metric: r.get("metric"),
+        mount_point: r.get("mount_point"),
+        operator: r.get("operator"),
+        threshold: r.get("threshold"),
+        duration_sec: r.get::<i64, _>("duration_sec").max(0) as u64,
+        client_id: parse_id("client_id"),
+        group_id: parse_id("group_id"),
+        remediation_script_id: parse_id("remediation_script_id"),
+        remediation_params: r.get::<Option<String>, _>("remediation_params").as_deref().and_then(|p| serde_json::from_str(p).ok()).unwrap_or_default(),
+        enabled: r.get::<i64, _>("enabled") != 0,
+        created_at: r.get("created_at"),
+    }
+}
+
+pub async fn list_rules(db: &Pool<Sqlite>) -> Result<Vec<Rule>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{} ORDER BY created_at DESC", SELECT_RULES)).fetch_all(db).await?;
+    Ok(rows.iter().map(rule_from_row).collect())
+}
+
+pub async fn get_rule(db: &Pool<Sqlite>, id: Uuid) -> Result<Option<Rule>, sqlx::Error> {
+    let row = sqlx::query(&format!("{} WHERE id = ?", SELECT_RULES))
+        .bind(id.to_string())
+        .fetch_optional(db)
+        .await?;
+    Ok(row.as_ref().map(rule_from_row))
+}
+
+/// Creates or replaces a rule. Alerts opened under the previous version are closed, since they
+/// were measured against a condition that no longer exists.
+pub async fn save_rule(db: &Pool<Sqlite>, rule: &Rule) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        r#"INSERT INTO alert_rules (id, name, metric, mount_point, operator, threshold, duration_sec, client_id, group_id, remediation_script_id, remediation_params, enabled)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT(id) DO UPDATE SET name = excluded.name, metric = excluded.metric, mount_point = excluded.mount_point,
+               operator = excluded.operator, threshold = excluded.threshold, duration_sec = excluded.duration_sec,
+               client_id = excluded.client_id, group_id = excluded.group_id, remediation_script_id = excluded.remediation_script_id,
+               remediation_params = excluded.remediation_params, enabled = excluded.enabled"#
+    )
+        .bind(rule.id.to_string())
+        .bind(&rule.name)
+        .bind(&rule.metric)
+        .bind(&rule.mount_point)
+        .bind(&rule.operator)
+        .bind(rule.threshold)
+        .bind(rule.duration_sec as i64)
+        .bind(rule.client_id.map(|id| id.to_string()))
+        .bind(rule.group_id.map(|id| id.to_string()))
+        .bind(rule.remediation_script_id.map(|id| id.to_string()))
+        .bind(serde_json::to_string(&rule.remediation_params).unwrap_or("{}".to_string()))
+        .bind(rule.enabled as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM alerts WHERE rule_id = ? AND state = 'pending'")
+        .bind(rule.id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE alerts SET state = 'resolved', resolved_at = ? WHERE rule_id = ? AND state = 'firing'")
+        .bind(now())
+        .bind(rule.id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+const SELECT_ALERTS: &str = "SELECT a.id, a.rule_id, r.name as rule_name, a.client_id, a.state, r.metric, r.threshold, a.value, a.started_at, a.fired_at, a.resolved_at, a.remediation_status FROM alerts a JOIN alert_rules r ON r.id = a.rule_id";
+
+fn alert_from_row(r: &sqlx::sqlite::SqliteRow) -> Alert {
+    Alert {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        rule_id: Uuid::parse_str(&r.get::<String, _>("rule_id")).unwrap_or_default(),
+        rule_name: r.get("rule_name"),
+        client_id: Uuid::parse_str(&r.get::<String, _>("client_id")).unwrap_or_default(),
+        state: r.get("state"),
+        metric: r.get("metric"),
+        threshold: r.get("threshold"),
+        value: r.get("value"),
+        started_at: r.get("started_at"),
+        fired_at: r.get("fired_at"),
+        resolved_at: r.get("resolved_at"),
+        remediation_status: r.get("remediation_status"),
+    }
+}
+
+/// Alerts, newest first, optionally narrowed to one state and/or client.
+pub async fn list_alerts(db: &Pool<Sqlite>, state: Option<&str>, client_id: Option<Uuid>, limit: i64) -> Result<Vec<Alert>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "{} WHERE (? IS NULL OR a.state = ?) AND (? IS NULL OR a.client_id = ?) ORDER BY a.started_at DESC LIMIT ?",
+        SELECT_ALERTS
+    ))
+        .bind(state)
+        .bind(state)
+        .bind(client_id.map(|id| id.to_string()))
+        .bind(client_id.map(|id| id.to_string()))
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.iter().map(alert_from_row).collect())
+}
+
+/// Firing alerts of the given clients, for the client list.
+pub async fn firing_by_client(db: &Pool<Sqlite>, client_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Alert>>, sqlx::Error> {
+    let mut by_client: HashMap<Uuid, Vec<Alert>> = HashMap::new();
+    if client_ids.is_empty() {
+        return Ok(by_client);
+    }
+    let ids = serde_json::to_string(&client_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>()).unwrap_or_default();
+    let rows = sqlx::query(&format!(
+        "{} WHERE a.state = 'firing' AND a.client_id IN (SELECT value FROM json_each(?)) ORDER BY a.fired_at",
+        SELECT_ALERTS
+    ))
+        .bind(ids)
+        .fetch_all(db)
+        .await?;
+    for alert in rows.iter().map(alert_from_row) {
+        by_client.entry(alert.client_id).or_default().push(alert);
+    }
+    Ok(by_client)
+}
+
+// Value of the rule's metric in a sample; None when the sample doesn't have it (no swap, no load
+// average on Windows, unknown mount point)
+fn measure(rule: &Rule, sample: &MetricsSample) -> Option<f64> {
+    let percent = |used: u64, total: u64| (total > 0).then(|| used as f64 * 100.0 / total as f64);
+    let external = || sample.network.iter().filter(|n| !metrics::is_loopback(&n.interface));
+    match rule.metric.as_str() {
+        CPU => Some(sample.cpu_usage as f64),
+        MEMORY => percent(sample.used_memory, sample.total_memory),
+        SWAP => percent(sample.used_swap, sample.total_swap),
+        DISK => sample.disks.iter()
+            .filter(|d| rule.mount_point.as_ref().is_none_or(|m| *m == d.mount_point))
+            .filter_map(|d| percent(d.used_space, d.total_space))
+            .reduce(f64::max),
+        LOAD_1 => sample.load_average.map(|l| l.one),
+        LOAD_5 => sample.load_average.map(|l| l.five),
+        LOAD_15 => sample.load_average.map(|l| l.fifteen),
+        PROCESS_COUNT => Some(sample.process_count as f64),
+        NET_RX => Some(external().map(|n| n.rx_bytes_per_sec as f64).sum()),
+        NET_TX => Some(external().map(|n| n.tx_bytes_per_sec as f64).sum()),
+        _ => None,
+    }
+}
+
+/// Checks the rules that apply to a client against the sample it just reported.
+pub async fn evaluate(state: &Arc<AppState>, client_id: Uuid, sample: &MetricsSample) -> Result<(), sqlx::Error> {
+    let rules = list_rules(&state.db).await?;
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let groups: Vec<String> = sqlx::query_scalar("SELECT group_id FROM client_group_members WHERE client_id = ?")
+        .bind(client_id.to_string())
+        .fetch_all(&state.db)
+        .await?;
+    let open: Vec<Alert> = sqlx::query(&format!("{} WHERE a.client_id = ? AND a.state != 'resolved'", SELECT_ALERTS))
+        .bind(client_id.to_string())
+        .fetch_all(&state.db)
+        .await?
+        .iter()
+        .map(alert_from_row)
+        .collect();
+
+    let applies = |r: &Rule| match (r.client_id, r.group_id) {
+        (Some(id), _) => id == client_id,
+        (None, Some(group)) => groups.contains(&group.to_string()),
+        (None, None) => true,
+    };
+    for rule in rules.iter().filter(|r| r.enabled && applies(r)) {
+        let Some(value) = measure(rule, sample) else { continue };
+        let breached = if rule.operator == "below" { value < rule.threshold } else { value > rule.threshold };
+        let current = open.iter().find(|a| a.rule_id == rule.id);
+        let now = chrono::Utc::now();
+
+        match (current, breached) {
+            (None, true) => {
+                let alert_id = Uuid::new_v4();
+                sqlx::query("INSERT INTO alerts (id, rule_id, client_id, state, value, started_at) VALUES (?, ?, ?, 'pending', ?, ?)")
+                    .bind(alert_id.to_string())
+                    .bind(rule.id.to_string())
+                    .bind(client_id.to_string())
+                    .bind(value)
+                    .bind(now.format(TIME_FORMAT).to_string())
+                    .execute(&state.db)
+                    .await?;
+                if rule.duration_sec == 0 {
+                    fire(state, rule, alert_id, client_id, value).await?;
+                }
+            }
+            (Some(alert), true) => {
+                sqlx::query("UPDATE alerts SET value = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(alert.id.to_string())
+                    .execute(&state.db)
+                    .await?;
+                let since = chrono::NaiveDateTime::parse_from_str(&alert.started_at, TIME_FORMAT).map(|t| t.and_utc()).unwrap_or(now);
+                if alert.state == PENDING && (now - since).num_seconds() >= rule.duration_sec as i64 {
+                    fire(state, rule, alert.id, client_id, value).await?;
+                }
+            }
+            (Some(alert), false) if alert.state == PENDING => {
+                sqlx::query("DELETE FROM alerts WHERE id = ?").bind(alert.id.to_string()).execute(&state.db).await?;
+            }
+            (Some(alert), false) => {
+                sqlx::query("UPDATE alerts SET state = 'resolved', value = ?, resolved_at = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(now.format(TIME_FORMAT).to_string())
+                    .bind(alert.id.to_string())
+                    .execute(&state.db)
+                    .await?;
+                info!("Alert '{}' resolved on client {} ({:.2})", rule.name, client_id, value);
+                announce(state, rule, alert.id, client_id, RESOLVED, value);
+            }
+            (None, false) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Closes the open alerts of a client that went away: pending ones are dropped and firing ones
+/// resolved at their last value, since no more samples will come to resolve them.
+pub async fn client_disconnected(state: &Arc<AppState>, client_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM alerts WHERE client_id = ? AND state = 'pending'")
+        .bind(client_id.to_string())
+        .execute(&state.db)
+        .await?;
+    let firing: Vec<Alert> = sqlx::query(&format!("{} WHERE a.client_id = ? AND a.state = 'firing'", SELECT_ALERTS))
+        .bind(client_id.to_string())
+        .fetch_all(&state.db)
+        .await?
+        .iter()
+        .map(alert_from_row)
+        .collect();
+    if firing.is_empty() {
+        return Ok(());
+    }
+    let rules = list_rules(&state.db).await?;
+    for alert in firing {
+        sqlx::query("UPDATE alerts SET state = 'resolved', resolved_at = ? WHERE id = ?")
+            .bind(now())
+            .bind(alert.id.to_string())
+            .execute(&state.db)
+            .await?;
+        info!("Alert '{}' resolved on client {}: client disconnected", alert.rule_name, client_id);
+        if let Some(rule) = rules.iter().find(|r| r.id == alert.rule_id) {
+            announce(state, rule, alert.id, client_id, RESOLVED, alert.value);
+        }
+    }
+    Ok(())
+}
+
+async fn fire(state: &Arc<AppState>, rule: &Rule, alert_id: Uuid, client_id: Uuid, value: f64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE alerts SET state = 'firing', fired_at = ? WHERE id = ?")
+        .bind(now())
+        .bind(alert_id.to_string())
+        .execute(&state.db)
+        .await?;
+    warn!("Alert '{}' firing on client {}: {} {:.2} is {} {}", rule.name, client_id, rule.metric, value, rule.operator, rule.threshold);
+    announce(state, rule, alert_id, client_id, FIRING, value);
+
+    if let Some(script_id) = rule.remediation_script_id {
+        tokio::spawn(remediate(state.clone(), rule.clone(), alert_id, client_id, script_id));
+    }
+    Ok(())
+}
+
+fn announce(state: &Arc<AppState>, rule: &Rule, alert_id: Uuid, client_id: Uuid, alert_state: &str, value: f64) {
+    events::publish(state, ServerEvent::AlertChanged {
+        alert_id,
+        client_id,
+        rule_name: rule.name.clone(),
+        state: alert_state.to_string(),
+        value,
+    });
+    let hostname = state.clients.get(&client_id).map(|c| c.hostname.clone());
+    let event = if alert_state == FIRING { webhooks::ALERT_FIRING } else { webhooks::ALERT_RESOLVED };
+    webhooks::emit(state, event, json!({
+        "alert_id": alert_id,
+        "rule_id": rule.id,
+        "rule_name": rule.name,
+        "client_id": client_id,
+        "hostname": hostname,
+        "metric": rule.metric,
+        "mount_point": rule.mount_point,
+        "operator": rule.operator,
+        "threshold": rule.threshold,
+        "value": value,
+    }));
+}
+
+// Runs the rule's remediation script on the client and records how it went on the alert
+async fn remediate(state: Arc<AppState>, rule: Rule, alert_id: Uuid, client_id: Uuid, script_id: Uuid) {
+    let set_status = |status: String| {
+        let db = state.db.clone();
+        async move {
+            if let Err(e) = sqlx::query("UPDATE alerts SET remediation_status = ? WHERE id = ?")
+                .bind(status)
+                .bind(alert_id.to_string())
+                .execute(&db)
+                .await
+            {
+                error!("Failed to record remediation of alert {}: {}", alert_id, e);
+            }
+        }
+    };
+
+    let script = match handlers::fetch_script(&state, &script_id.to_string()).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return set_status(format!("script {} no longer exists", script_id)).await,
+        Err(e) => return set_status(format!("failed to load script: {}", e)).await,
+    };
+    let params = match template::resolve_params(&script.params, &rule.remediation_params) {
+        Ok(p) => p,
+        Err(e) => return set_status(e).await,
+    };
+    if !state.clients.contains_key(&client_id) {
+        return set_status("client not connected".to_string()).await;
+    }
+
+    info!("Alert '{}': running remediation script {} on client {}", rule.name, script.name, client_id);
+    set_status("running".to_string()).await;
+    let host = format!("{}:{}", state.config.host, state.config.port);
+    let success = handlers::run_script_on_client(state.clone(), client_id, script, params, host, None).await;
+    set_status(if success { "succeeded" } else { "failed" }.to_string()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::DiskUsage;
+    use std::path::PathBuf;
+
+    async fn test_state() -> (Arc<AppState>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("roam-alerts-{}", Uuid::new_v4()));
+        let config = crate::config::ServerConfig::new().unwrap();
+        (AppState::for_tests(&dir, config).await, dir)
+    }
+
+    fn rule(metric: &str, operator: &str, threshold: f64, duration_sec: u64) -> Rule {
+        Rule {
+            id: Uuid::new_v4(),
+            name: format!("{} {} {}", metric, operator, threshold),
+            metric: metric.to_string(),
+            mount_point: None,
+            operator: operator.to_string(),
+            threshold,
+            duration_sec,
+            client_id: None,
+            group_id: None,
+            remediation_script_id: None,
+            remediation_params: HashMap::new(),
+            enabled: true,
+            created_at: None,
+        }
+    }
+
+    fn sample(cpu: f32) -> MetricsSample {
+        MetricsSample {
+            cpu_usage: cpu,
+            cpu_per_core: Vec::new(),
+            total_memory: 1000,
+            used_memory: 500,
+            total_swap: 0,
+            used_swap: 0,
+            disks: vec![
+                DiskUsage { mount_point: "/".into(), total_space: 100, used_space: 50 },
+                DiskUsage { mount_point: "/data".into(), total_space: 100, used_space: 95 },
+            ],
+            network: Vec::new(),
+            load_average: None,
+            process_count: 10,
+        }
+    }
+
+    async fn alert_states(state: &AppState, client_id: Uuid) -> Vec<String> {
+        list_alerts(&state.db, None, Some(client_id), 10).await.unwrap().into_iter().map(|a| a.state).collect()
+    }
+
+    #[test]
+    fn measures_the_rule_metric() {
+        let s = sample(42.0);
+        assert_eq!(measure(&rule(CPU, "above", 0.0, 0), &s), Some(42.0));
+        assert_eq!(measure(&rule(MEMORY, "above", 0.0, 0), &s), Some(50.0));
+        // No swap and no load average are missing values, not zero
+        assert_eq!(measure(&rule(SWAP, "above", 0.0, 0), &s), None);
+        assert_eq!(measure(&rule(LOAD_1, "above", 0.0, 0), &s), None);
+        assert_eq!(measure(&rule(DISK, "above", 0.0, 0), &s), Some(95.0));
+        let root = Rule { mount_point: Some("/".into()), ..rule(DISK, "above", 0.0, 0) };
+        assert_eq!(measure(&root, &s), Some(50.0));
+    }
+
+    #[tokio::test]
+    async fn alerts_go_pending_firing_resolved() {
+        let (state, dir) = test_state().await;
+        let client_id = Uuid::new_v4();
+        let cpu = rule(CPU, "above", 90.0, 60);
+        save_rule(&state.db, &cpu).await.unwrap();
+
+        evaluate(&state, client_id, &sample(50.0)).await.unwrap();
+        assert!(alert_states(&state, client_id).await.is_empty());
+
+        // The breach has to last duration_sec before the alert fires
+        evaluate(&state, client_id, &sample(95.0)).await.unwrap();
+        evaluate(&state, client_id, &sample(96.0)).await.unwrap();
+        assert_eq!(alert_states(&state, client_id).await, vec![PENDING]);
+        let started = (chrono::Utc::now() - chrono::Duration::seconds(61)).format(TIME_FORMAT).to_string();
+        sqlx::query("UPDATE alerts SET started_at = ?").bind(started).execute(&state.db).await.unwrap();
+        evaluate(&state, client_id, &sample(97.0)).await.unwrap();
+        let alerts = list_alerts(&state.db, None, Some(client_id), 10).await.unwrap();
+        assert_eq!((alerts.len(), alerts[0].state.as_str(), alerts[0].value), (1, FIRING, 97.0));
+        assert!(alerts[0].fired_at.is_some());
+
+        evaluate(&state, client_id, &sample(10.0)).await.unwrap();
+        let alerts = list_alerts(&state.db, None, Some(client_id), 10).await.unwrap();
+        assert_eq!((alerts[0].state.as_str(), alerts[0].value), (RESOLVED, 10.0));
+        assert!(alerts[0].resolved_at.is_some());
+
+        // A breach that ends while pending leaves nothing behind
+        evaluate(&state, client_id, &sample(95.0)).await.unwrap();
+        evaluate(&state, client_id, &sample(10.0)).await.unwrap();
+        assert_eq!(alert_states(&state, client_id).await, vec![RESOLVED]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn below_rules_without_duration_fire_at_once() {
+        let (state, dir) = test_state().await;
+        let client_id = Uuid::new_v4();
+        save_rule(&state.db, &rule(PROCESS_COUNT, "below", 5.0, 0)).await.unwrap();
+
+        evaluate(&state, client_id, &sample(0.0)).await.unwrap();
+        assert!(alert_states(&state, client_id).await.is_empty());
+        let mut few = sample(0.0);
+        few.process_count = 3;
+        evaluate(&state, client_id, &few).await.unwrap();
+        assert_eq!(alert_states(&state, client_id).await, vec![FIRING]);
+        // Exactly at the threshold is not a breach
+        few.process_count = 5;
+        evaluate(&state, client_id, &few).await.unwrap();
+        assert_eq!(alert_states(&state, client_id).await, vec![RESOLVED]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnecting_closes_open_alerts() {
+        let (state, dir) = test_state().await;
+        let client_id = Uuid::new_v4();
+        save_rule(&state.db, &rule(CPU, "above", 90.0, 0)).await.unwrap();
+        save_rule(&state.db, &rule(DISK, "above", 90.0, 600)).await.unwrap();
+        evaluate(&state, client_id, &sample(95.0)).await.unwrap();
+        let mut open = alert_states(&state, client_id).await;
+        open.sort();
+        assert_eq!(open, vec![FIRING, PENDING]);
+
+        client_disconnected(&state, client_id).await.unwrap();
+        let alerts = list_alerts(&state.db, None, Some(client_id), 10).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].state.as_str(), alerts[0].value), (RESOLVED, 95.0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -16,6 +16,7 @@ use crate::config::ServerConfig;
 use crate::db;
 use crate::handlers;
 use crate::assets;
+use crate::storage;
 
 pub async fn run(shutdown_signal: impl std::future::Future<Output = ()> + Send + 'static) -> anyhow::Result<()> {
     // Load .env file
@@ -56,7 +57,20 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()> + Send +
     let pool = db::init_db(&config.database_url).await?;
 
     // App State
-    let app_state = Arc::new(AppState::new(pool, config.clone()));
+    let secret_cipher = crate::secrets::SecretCipher::new(&crate::secrets::load_key(&config)?);
+    crate::secrets::migrate_legacy(&pool, &secret_cipher, &config).await;
+    let app_state = Arc::new(AppState::new(pool, config.clone(), secret_cipher));
+
+    // Executions orphaned by the previous process
+    crate::executions::recover(app_state.clone()).await;
+    tokio::spawn(crate::executions::run_expiry(app_state.clone()));
+    tokio::spawn(crate::events::run_result_expiry(app_state.clone()));
+    tokio::spawn(crate::notifications::run_offline_checks(app_state.clone()));
+    tokio::spawn(crate::metrics::run_retention(app_state.clone()));
+    tokio::spawn(crate::webhooks::run_retention(app_state.clone()));
+
+    // Cron schedules
+    tokio::spawn(crate::scheduler::run(app_state.clone()));
 
     // Router
     let app = Router::new()
@@ -65,16 +79,34 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()> + Send +
         .route("/api/clients/:id/remark", axum::routing::put(handlers::update_client_remark))
         .route("/api/clients/:id/working_directory", axum::routing::put(handlers::update_client_working_directory))
         .route("/api/clients/:id/display_ip", axum::routing::put(handlers::update_client_display_ip))
+        .route("/api/clients/:id/sessions", get(handlers::list_client_sessions))
+        .route("/api/clients/:id/uptime", get(handlers::get_client_uptime))
+        .route("/api/clients/:id/metrics", get(handlers::get_client_metrics))
+        .route("/api/clients/:id/inventory", get(handlers::get_client_inventory))
+        .route("/api/clients/:id/inventory/refresh", post(handlers::refresh_client_inventory))
+        .route("/api/inventory", get(handlers::search_inventory))
+        .route("/api/inventory/compare", get(handlers::compare_inventory))
+        .route("/api/alert_rules", get(handlers::list_alert_rules).post(handlers::create_alert_rule))
+        .route("/api/alert_rules/:id", axum::routing::put(handlers::update_alert_rule).delete(handlers::delete_alert_rule))
+        .route("/api/alerts", get(handlers::list_alerts))
         .route("/api/info", get(handlers::get_server_info))
         .route("/api/clients/:id/command", post(handlers::send_command))
         .route("/api/commands/:id/result", get(handlers::get_command_result))
         .route("/api/files/admin-upload", post(handlers::upload_file_admin))
         .route("/api/files/client-upload/:id", post(handlers::upload_file_client))
-        .nest_service("/api/files/download", ServeDir::new("uploads"))
+        .route("/api/files/staging", get(handlers::list_staging_files))
+        .route("/api/files/staging/*path", get(handlers::get_staging_file).delete(handlers::delete_staging_file))
+        .nest_service("/api/files/download", ServeDir::new(storage::UPLOADS_DIR))
         .route("/api/groups", get(handlers::list_groups).post(handlers::create_group))
         .route("/api/groups/:id", axum::routing::delete(handlers::delete_group).put(handlers::update_group))
         .route("/api/groups/:id/run", post(handlers::run_group_scripts))
+        .route("/api/groups/:id/uptime", get(handlers::get_group_uptime))
         .route("/api/executions/active", get(handlers::get_active_executions))
+        .route("/api/events", get(handlers::event_stream))
+        .route("/api/events/ticket", post(handlers::create_event_ticket))
+        .route("/api/schedules", get(handlers::list_schedules).post(handlers::create_schedule))
+        .route("/api/schedules/:id", get(handlers::get_schedule).put(handlers::update_schedule).delete(handlers::delete_schedule))
+        .route("/api/schedules/:id/run", post(handlers::run_schedule_now))
         .route("/api/scripts", get(handlers::list_scripts).post(handlers::create_script))
         .route("/api/scripts/:id", axum::routing::put(handlers::update_script).delete(handlers::delete_script))
         .route("/api/scripts/:id/run", post(handlers::run_script))
@@ -82,6 +114,17 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()> + Send +
         .route("/api/updates/:id", axum::routing::delete(handlers::delete_update))
         .route("/api/updates/trigger", post(handlers::trigger_update_clients))
         .route("/api/history", get(handlers::get_script_history).delete(handlers::clear_script_history))
+        .route("/api/policy/violations", get(handlers::list_policy_violations))
+        .route("/api/webhooks", get(handlers::list_webhooks).post(handlers::create_webhook))
+        .route("/api/webhooks/:id", axum::routing::put(handlers::update_webhook).delete(handlers::delete_webhook))
+        .route("/api/webhooks/:id/deliveries", get(handlers::list_webhook_deliveries))
+        .route("/api/webhooks/:id/test", post(handlers::test_webhook))
+        .route("/api/notifications", get(handlers::list_notification_rules).post(handlers::create_notification_rule))
+        .route("/api/notifications/:id", axum::routing::put(handlers::update_notification_rule).delete(handlers::delete_notification_rule))
+        .route("/api/notifications/:id/log", get(handlers::list_notification_log))
+        .route("/api/notifications/:id/test", post(handlers::test_notification_rule))
+        .route("/api/secrets", get(handlers::list_secrets).post(handlers::create_secret))
+        .route("/api/secrets/:name", axum::routing::put(handlers::update_secret).delete(handlers::delete_secret))
         .route("/ws", get(handlers::ws_handler))
         // Auth Routes
         .route("/api/auth/login", post(handlers::login))
@@ -104,6 +147,15 @@ pub async fn run(shutdown_signal: impl std::future::Future<Output = ()> + Send +
         )
         .await?;
 
+        // Pick up rotated certificates without restarting (connected clients stay connected)
+        tokio::spawn(crate::tls::watch_certificates(
+            tls_config.clone(),
+            cert_path.clone(),
+            key_path.clone(),
+            config.tls_reload_interval_sec,
+            config.tls_expiry_warn_days,
+        ));
+
         let handle = axum_server::Handle::new();
         let shutdown_handle = handle.clone();
         tokio::spawn(async move {
@@ -163,6 +215,19 @@ async fn auth_middleware(
          return next.run(request).await;
     }
 
+    // EventSource cannot set headers, so the event stream is opened with a single-use ticket
+    // from /api/events/ticket rather than the login token, which would end up in access logs
+    if path == "/api/events" {
+        let ticket = request.uri().query()
+            .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("ticket=")))
+            .unwrap_or_default();
+        return if crate::events::redeem_ticket(&state, ticket) {
+            next.run(request).await
+        } else {
+            (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+        };
+    }
+
     let token = request.headers().get("Authorization")
         .and_then(|h| h.to_str().ok())
         .map(|s| s.replace("Bearer ", ""))
@@ -1,7 +1,7 @@
 use serde::Deserialize;
 use config::{Config, File};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
@@ -12,6 +12,77 @@ pub struct ServerConfig {
     pub tls_cert_path: Option<String>,
     pub tls_key_path: Option<String>,
     pub download_url_prefix: Option<String>,
+    pub tls_reload_interval_sec: u64,
+    pub tls_expiry_warn_days: i64,
+    pub secrets_key: Option<String>,
+    // Holds a generated key when secrets_key is not set
+    pub secrets_key_file: String,
+    // What to do with executions left running by a restart: "interrupt" or "resume"
+    pub execution_recovery: String,
+    pub execution_resume_timeout_sec: u64,
+    // How long runs queued for offline clients wait before expiring
+    pub pending_execution_ttl_sec: u64,
+    // Results of interactive commands are kept this long, and at most this many
+    pub result_ttl_sec: u64,
+    pub result_cache_size: usize,
+    // Clients are pinged every interval and evicted after this many intervals without any message
+    pub heartbeat_interval_sec: u64,
+    pub heartbeat_max_missed: u32,
+    // Retention of pushed client metrics, per resolution
+    pub metrics_minute_retention_hours: u64,
+    pub metrics_hour_retention_days: u64,
+    // Finished webhook deliveries are kept this long
+    pub webhook_delivery_retention_days: u64,
+    // Outgoing mail for notification rules. tls is "starttls", "tls" or "none"
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_tls: String,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_from: String,
+    // When set, messages are written here as .eml files instead of being sent (for testing)
+    pub smtp_outbox_dir: Option<String>,
+}
+
+const REDACTED: &str = "******";
+
+// Written out so that tokens, keys and passwords never reach the log with the rest of the config
+impl std::fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redact = |value: &Option<String>| value.as_ref().map(|_| REDACTED);
+        f.debug_struct("ServerConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("database_url", &self.database_url)
+            .field("auth_token", &REDACTED)
+            .field("web_auth_enabled", &self.web_auth_enabled)
+            .field("web_jwt_secret", &REDACTED)
+            .field("tls_cert_path", &self.tls_cert_path)
+            .field("tls_key_path", &self.tls_key_path)
+            .field("download_url_prefix", &self.download_url_prefix)
+            .field("tls_reload_interval_sec", &self.tls_reload_interval_sec)
+            .field("tls_expiry_warn_days", &self.tls_expiry_warn_days)
+            .field("secrets_key", &redact(&self.secrets_key))
+            .field("secrets_key_file", &self.secrets_key_file)
+            .field("execution_recovery", &self.execution_recovery)
+            .field("execution_resume_timeout_sec", &self.execution_resume_timeout_sec)
+            .field("pending_execution_ttl_sec", &self.pending_execution_ttl_sec)
+            .field("result_ttl_sec", &self.result_ttl_sec)
+            .field("result_cache_size", &self.result_cache_size)
+            .field("heartbeat_interval_sec", &self.heartbeat_interval_sec)
+            .field("heartbeat_max_missed", &self.heartbeat_max_missed)
+            .field("metrics_minute_retention_hours", &self.metrics_minute_retention_hours)
+            .field("metrics_hour_retention_days", &self.metrics_hour_retention_days)
+            .field("webhook_delivery_retention_days", &self.webhook_delivery_retention_days)
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_tls", &self.smtp_tls)
+            .field("smtp_username", &self.smtp_username)
+            .field("smtp_password", &redact(&self.smtp_password))
+            .field("smtp_from", &self.smtp_from)
+            .field("smtp_outbox_dir", &self.smtp_outbox_dir)
+            .finish()
+    }
 }
 
 impl ServerConfig {
@@ -25,7 +96,28 @@ impl ServerConfig {
             .set_default("web_jwt_secret", "roam-secret-key")?
             .set_default("tls_cert_path", None::<String>)?
             .set_default("tls_key_path", None::<String>)?
-            .set_default("download_url_prefix", None::<String>)?;
+            .set_default("download_url_prefix", None::<String>)?
+            .set_default("tls_reload_interval_sec", 60)?
+            .set_default("tls_expiry_warn_days", 30)?
+            .set_default("secrets_key", None::<String>)?
+            .set_default("secrets_key_file", "secrets.key")?
+            .set_default("execution_recovery", "interrupt")?
+            .set_default("execution_resume_timeout_sec", 3600)?
+            .set_default("pending_execution_ttl_sec", 86400)?
+            .set_default("result_ttl_sec", 600)?
+            .set_default("result_cache_size", 1000)?
+            .set_default("heartbeat_interval_sec", 10)?
+            .set_default("heartbeat_max_missed", 3)?
+            .set_default("metrics_minute_retention_hours", 48)?
+            .set_default("metrics_hour_retention_days", 90)?
+            .set_default("webhook_delivery_retention_days", 30)?
+            .set_default("smtp_host", None::<String>)?
+            .set_default("smtp_port", 587)?
+            .set_default("smtp_tls", "starttls")?
+            .set_default("smtp_username", None::<String>)?
+            .set_default("smtp_password", None::<String>)?
+            .set_default("smtp_from", "roam@localhost")?
+            .set_default("smtp_outbox_dir", None::<String>)?;
 
         // 1. Prioritize loading config from executable directory (Production/Service)
         let mut config_found = false;
@@ -68,6 +160,15 @@ impl ServerConfig {
             }
         }
 
+        // The generated secrets key lives next to the executable, like the database
+        if !std::path::Path::new(&server_config.secrets_key_file).is_absolute() {
+            if let Ok(exe_path) = std::env::current_exe() {
+                if let Some(exe_dir) = exe_path.parent() {
+                    server_config.secrets_key_file = exe_dir.join(&server_config.secrets_key_file).to_string_lossy().to_string();
+                }
+            }
+        }
+
         // Fix TLS paths if they are relative and exist in the executable directory
         if let Ok(exe_path) = std::env::current_exe() {
             if let Some(exe_dir) = exe_path.parent() {
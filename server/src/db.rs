@@ -43,11 +43,172 @@ pub async fn init_db(db_url: &str) -> anyhow::Result<Pool<Sqlite>> {
             id TEXT PRIMARY KEY,
             script_id TEXT NOT NULL,
             client_id TEXT NOT NULL,
-            status TEXT NOT NULL, -- 'running', 'completed', 'failed'
+            status TEXT NOT NULL, -- 'running', 'completed', 'failed', 'pending', 'expired', 'resuming', 'interrupted'
             started_at DATETIME DEFAULT CURRENT_TIMESTAMP,
             completed_at DATETIME,
             logs TEXT -- JSON array of log entries
         );
+        CREATE TABLE IF NOT EXISTS execution_steps (
+            execution_id TEXT NOT NULL,
+            step_index INTEGER NOT NULL, -- 1-based, as in the logs
+            step_type TEXT NOT NULL,
+            status TEXT NOT NULL, -- 'succeeded', 'failed', 'skipped'
+            attempts INTEGER NOT NULL DEFAULT 0,
+            started_at DATETIME,
+            completed_at DATETIME,
+            exit_code INTEGER,
+            stdout TEXT,
+            stderr TEXT,
+            error TEXT,
+            PRIMARY KEY (execution_id, step_index),
+            FOREIGN KEY(execution_id) REFERENCES execution_history(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_execution_steps_status ON execution_steps(status, step_type);
+
+        CREATE TABLE IF NOT EXISTS schedules (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            cron TEXT NOT NULL,
+            timezone TEXT NOT NULL DEFAULT 'UTC',
+            target_type TEXT NOT NULL, -- 'script', 'group'
+            target_id TEXT NOT NULL,
+            client_ids TEXT, -- JSON array, for script targets
+            params TEXT, -- JSON object
+            overlap_policy TEXT NOT NULL DEFAULT 'skip', -- 'skip', 'queue', 'parallel'
+            missed_run_policy TEXT NOT NULL DEFAULT 'skip', -- 'skip', 'run_once'
+            enabled INTEGER NOT NULL DEFAULT 1,
+            last_run_at TEXT,
+            next_run_at TEXT, -- UTC, '%Y-%m-%dT%H:%M:%SZ'
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS webhooks (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            events TEXT NOT NULL, -- JSON array of event names, "*" for all
+            secret TEXT, -- encrypted HMAC key
+            payload_template TEXT, -- JSON
+            enabled INTEGER NOT NULL DEFAULT 1,
+            max_retries INTEGER NOT NULL DEFAULT 3,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id TEXT PRIMARY KEY,
+            webhook_id TEXT NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL, -- 'pending', 'succeeded', 'failed'
+            attempts INTEGER NOT NULL DEFAULT 0,
+            response_status INTEGER,
+            error TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            completed_at DATETIME,
+            FOREIGN KEY(webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at);
+
+        CREATE TABLE IF NOT EXISTS notification_rules (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL, -- 'execution_failed', 'execution_completed', 'client_offline'
+            script_id TEXT, -- NULL matches any script
+            client_id TEXT, -- NULL matches any client
+            offline_minutes INTEGER NOT NULL DEFAULT 10,
+            recipients TEXT NOT NULL, -- JSON array of addresses
+            enabled INTEGER NOT NULL DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS notification_log (
+            id TEXT PRIMARY KEY,
+            rule_id TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            recipients TEXT NOT NULL,
+            status TEXT NOT NULL, -- 'sent', 'failed'
+            error TEXT,
+            reference TEXT, -- what triggered it, e.g. execution id or client_id@last_seen
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(rule_id) REFERENCES notification_rules(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_notification_log_rule ON notification_log(rule_id, created_at);
+
+        CREATE TABLE IF NOT EXISTS client_sessions (
+            id TEXT PRIMARY KEY, -- connection id
+            client_id TEXT NOT NULL,
+            connected_at TEXT NOT NULL, -- UTC, '%Y-%m-%dT%H:%M:%SZ'
+            disconnected_at TEXT, -- NULL while connected
+            remote_ip TEXT,
+            client_version TEXT,
+            disconnect_reason TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_client_sessions_client ON client_sessions(client_id, connected_at);
+
+        CREATE TABLE IF NOT EXISTS client_metrics (
+            client_id TEXT NOT NULL,
+            resolution INTEGER NOT NULL, -- bucket width in seconds
+            bucket INTEGER NOT NULL, -- bucket start, unix seconds
+            samples INTEGER NOT NULL,
+            cpu_usage REAL NOT NULL,
+            memory_used REAL NOT NULL,
+            memory_total REAL NOT NULL,
+            swap_used REAL NOT NULL,
+            swap_total REAL NOT NULL,
+            load_1 REAL,
+            load_5 REAL,
+            load_15 REAL,
+            process_count REAL NOT NULL,
+            net_rx_bps REAL NOT NULL,
+            net_tx_bps REAL NOT NULL,
+            detail TEXT, -- JSON: per-core CPU, disks and interfaces
+            PRIMARY KEY (client_id, resolution, bucket)
+        );
+
+        CREATE TABLE IF NOT EXISTS client_inventory (
+            client_id TEXT PRIMARY KEY,
+            collected_at TEXT NOT NULL,
+            cpu_model TEXT NOT NULL,
+            cpu_cores INTEGER NOT NULL,
+            total_memory INTEGER NOT NULL,
+            os_name TEXT,
+            os_version TEXT,
+            kernel_version TEXT,
+            arch TEXT NOT NULL,
+            data TEXT NOT NULL -- JSON HardwareInfo
+        );
+
+        CREATE TABLE IF NOT EXISTS alert_rules (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            metric TEXT NOT NULL, -- 'cpu', 'memory', 'swap', 'disk', 'load_1', 'load_5', 'load_15', 'process_count', 'net_rx', 'net_tx'
+            mount_point TEXT, -- disk only; NULL means the fullest disk
+            operator TEXT NOT NULL DEFAULT 'above', -- 'above', 'below'
+            threshold REAL NOT NULL,
+            duration_sec INTEGER NOT NULL DEFAULT 0,
+            client_id TEXT, -- scope: one client, one group, or all when both are NULL
+            group_id TEXT,
+            remediation_script_id TEXT,
+            remediation_params TEXT, -- JSON object
+            enabled INTEGER NOT NULL DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS alerts (
+            id TEXT PRIMARY KEY,
+            rule_id TEXT NOT NULL,
+            client_id TEXT NOT NULL,
+            state TEXT NOT NULL, -- 'pending', 'firing', 'resolved'
+            value REAL NOT NULL,
+            started_at TEXT NOT NULL, -- UTC, '%Y-%m-%dT%H:%M:%SZ'
+            fired_at TEXT,
+            resolved_at TEXT,
+            remediation_status TEXT,
+            FOREIGN KEY(rule_id) REFERENCES alert_rules(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_alerts_client ON alerts(client_id, state);
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_alerts_open ON alerts(rule_id, client_id) WHERE state != 'resolved';
+
         CREATE TABLE IF NOT EXISTS client_groups (
             id TEXT PRIMARY KEY,
             name TEXT NOT NULL,
@@ -84,6 +245,22 @@ pub async fn init_db(db_url: &str) -> anyhow::Result<Pool<Sqlite>> {
             username TEXT UNIQUE NOT NULL,
             password_hash TEXT NOT NULL
         );
+
+        CREATE TABLE IF NOT EXISTS secrets (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL, -- hex(nonce || AES-256-GCM ciphertext)
+            description TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS policy_violations (
+            id TEXT PRIMARY KEY,
+            client_id TEXT NOT NULL,
+            command_id TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
         "#,
     )
     .execute(&pool)
@@ -98,7 +275,19 @@ pub async fn init_db(db_url: &str) -> anyhow::Result<Pool<Sqlite>> {
     let _ = sqlx::query("ALTER TABLE clients ADD COLUMN remark TEXT").execute(&pool).await;
     let _ = sqlx::query("ALTER TABLE clients ADD COLUMN working_directory TEXT").execute(&pool).await;
     let _ = sqlx::query("ALTER TABLE clients ADD COLUMN display_ip TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE clients ADD COLUMN disconnect_reason TEXT").execute(&pool).await;
     let _ = sqlx::query("ALTER TABLE group_scripts ADD COLUMN sort_order INTEGER DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE scripts ADD COLUMN params TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN params TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN outputs TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN schedule_id TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN script_snapshot TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN current_step INTEGER DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN server_host TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN expires_at DATETIME").execute(&pool).await;
+    // Scripts of a group run still to come after this one, and the unmasked outputs (encrypted) for resuming
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN remaining_runs TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE execution_history ADD COLUMN resume_outputs TEXT").execute(&pool).await;
 
     // Seed admin user if not exists
     // Use runtime query to avoid compile-time check failure on fresh db
@@ -225,7 +414,13 @@ pub async fn init_db(db_url: &str) -> anyhow::Result<Pool<Sqlite>> {
     }
 
     // Reset client status on startup (all disconnected until they reconnect)
-    let _ = sqlx::query("UPDATE clients SET status = 'disconnected'").execute(&pool).await;
+    let _ = sqlx::query("UPDATE clients SET status = 'disconnected', disconnect_reason = 'server restarted' WHERE status = 'connected'").execute(&pool).await;
+    // Sessions left open by the previous process ended when their client was last heard from
+    let _ = sqlx::query(
+        "UPDATE client_sessions SET disconnect_reason = 'server restarted',
+             disconnected_at = COALESCE((SELECT strftime('%Y-%m-%dT%H:%M:%SZ', last_seen) FROM clients WHERE clients.id = client_sessions.client_id), connected_at)
+         WHERE disconnected_at IS NULL"
+    ).execute(&pool).await;
 
     Ok(pool)
 }
@@ -0,0 +1,146 @@
+use common::CommandResult;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+/// Pushed to browsers over `/api/events` (server-sent events, one JSON object per event).
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    CommandResult {
+        command_id: Uuid,
+        client_id: Uuid,
+        result: CommandResult,
+    },
+    // Snapshot of an entry in active_executions, with the log line that was just added
+    ExecutionProgress {
+        execution_id: Uuid,
+        script_name: String,
+        client_hostname: String,
+        status: String,
+        current_step: usize,
+        total_steps: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        log: Option<String>,
+    },
+    // Partial output of a running interactive command, such as a followed file
+    CommandOutput {
+        command_id: Uuid,
+        client_id: Uuid,
+        lines: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        notice: Option<String>,
+    },
+    ClientConnected {
+        client_id: Uuid,
+        hostname: String,
+    },
+    ClientDisconnected {
+        client_id: Uuid,
+        hostname: String,
+        reason: String,
+    },
+    // A threshold alert started firing or resolved
+    AlertChanged {
+        alert_id: Uuid,
+        client_id: Uuid,
+        rule_name: String,
+        state: String,
+        value: f64,
+    },
+}
+
+// Sending only fails when no browser is listening, which is fine
+pub fn publish(state: &AppState, event: ServerEvent) {
+    let _ = state.events.send(event);
+}
+
+/// Publishes the current state of an active execution. Must not be called while holding a
+/// guard on the same `active_executions` entry.
+pub fn execution_updated(state: &AppState, execution_id: Uuid, log: Option<String>) {
+    let event = match state.active_executions.get(&execution_id) {
+        Some(p) => ServerEvent::ExecutionProgress {
+            execution_id,
+            script_name: p.script_name.clone(),
+            client_hostname: p.client_hostname.clone(),
+            status: p.status.clone(),
+            current_step: p.current_step,
+            total_steps: p.total_steps,
+            log,
+        },
+        None => return,
+    };
+    publish(state, event);
+}
+
+/// Keeps an interactive command's result for `/api/commands/:id/result`, evicting the oldest
+/// entries beyond `result_cache_size`.
+pub fn store_result(state: &AppState, command_id: Uuid, result: CommandResult) {
+    state.results.insert(command_id, (result, Instant::now()));
+    let excess = state.results.len().saturating_sub(state.config.result_cache_size);
+    if excess > 0 {
+        let mut entries: Vec<(Uuid, Instant)> = state.results.iter().map(|r| (*r.key(), r.value().1)).collect();
+        entries.sort_by_key(|(_, at)| *at);
+        for (id, _) in entries.into_iter().take(excess) {
+            state.results.remove(&id);
+        }
+    }
+}
+
+/// How long a ticket from `/api/events/ticket` can wait before opening the event stream.
+const TICKET_TTL: Duration = Duration::from_secs(30);
+
+/// Issues a single-use ticket for opening `/api/events`. EventSource cannot send an
+/// Authorization header, and a ticket in the URL is harmless once used, unlike the login token.
+pub fn issue_ticket(state: &AppState) -> String {
+    let ticket = Uuid::new_v4().to_string();
+    state.event_tickets.insert(ticket.clone(), Instant::now());
+    ticket
+}
+
+/// Consumes a ticket, returning whether it was valid and unexpired.
+pub fn redeem_ticket(state: &AppState, ticket: &str) -> bool {
+    state.event_tickets.remove(ticket).is_some_and(|(_, issued_at)| issued_at.elapsed() <= TICKET_TTL)
+}
+
+pub fn result_expired(state: &AppState, stored_at: Instant) -> bool {
+    stored_at.elapsed() > Duration::from_secs(state.config.result_ttl_sec)
+}
+
+/// Drops command results older than `result_ttl_sec`, the masking values of timed out
+/// commands whose response never came, and unused event stream tickets.
+pub async fn run_result_expiry(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        state.results.retain(|_, (_, stored_at)| !result_expired(&state, *stored_at));
+        state.command_secrets.retain(|_, (_, timed_out)| !timed_out.is_some_and(|t| result_expired(&state, t)));
+        state.event_tickets.retain(|_, issued_at| issued_at.elapsed() <= TICKET_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AppState;
+
+    #[tokio::test]
+    async fn tickets_open_the_stream_once() {
+        let dir = std::env::temp_dir().join(format!("roam-events-{}", Uuid::new_v4()));
+        let state = AppState::for_tests(&dir, crate::config::ServerConfig::new().unwrap()).await;
+
+        let ticket = issue_ticket(&state);
+        assert!(redeem_ticket(&state, &ticket));
+        assert!(!redeem_ticket(&state, &ticket));
+        assert!(!redeem_ticket(&state, "made-up"));
+
+        let stale = issue_ticket(&state);
+        state.event_tickets.insert(stale.clone(), Instant::now() - TICKET_TTL - Duration::from_secs(1));
+        assert!(!redeem_ticket(&state, &stale));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
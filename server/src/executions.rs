@@ -0,0 +1,196 @@
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::handlers;
+use crate::state::{AppState, ScriptGroup};
+
+// Executions survive a restart through their history row: the script snapshot, resolved
+// params, the step cursor written by run_script_task before each step and, for group runs,
+// the scripts still to come.
+
+/// Recovers executions left `running` by the previous server process. Under the "resume"
+/// policy they become `resuming` and continue when their client reconnects; otherwise, or
+/// when nothing can be resumed, they are marked `interrupted`.
+pub async fn recover(state: Arc<AppState>) {
+    let resume = state.config.execution_recovery.eq_ignore_ascii_case("resume");
+    if !resume && !state.config.execution_recovery.eq_ignore_ascii_case("interrupt") {
+        warn!("Unknown execution_recovery policy '{}', using 'interrupt'", state.config.execution_recovery);
+    }
+
+    if resume {
+        match sqlx::query("UPDATE execution_history SET status = 'resuming' WHERE status = 'running' AND script_snapshot IS NOT NULL")
+            .execute(&state.db)
+            .await
+        {
+            Ok(r) if r.rows_affected() > 0 => info!("{} interrupted execution(s) will resume when their clients reconnect", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => error!("Failed to recover executions: {}", e),
+        }
+    }
+    // Rows from before snapshots existed cannot be resumed
+    mark_interrupted(&state, "running", "Interrupted by server restart").await;
+
+    if resume {
+        // Clients that never come back shouldn't leave runs waiting forever
+        let timeout = state.config.execution_resume_timeout_sec;
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(timeout)).await;
+            mark_interrupted(&state, "resuming", "Interrupted by server restart, client did not reconnect in time").await;
+        });
+    }
+}
+
+async fn mark_interrupted(state: &AppState, from_status: &str, reason: &str) {
+    match sqlx::query("UPDATE execution_history SET status = 'interrupted', completed_at = CURRENT_TIMESTAMP, logs = json_insert(COALESCE(logs, '[]'), '$[#]', ?) WHERE status = ?")
+        .bind(reason)
+        .bind(from_status)
+        .execute(&state.db)
+        .await
+    {
+        Ok(r) if r.rows_affected() > 0 => warn!("Marked {} execution(s) as interrupted", r.rows_affected()),
+        Ok(_) => {}
+        Err(e) => error!("Failed to mark executions as interrupted: {}", e),
+    }
+}
+
+/// Queues a run for a client that is offline. The execution shows up as `pending` in history and
+/// is dispatched when the client registers again, unless it expires first. Returns `None` for
+/// unknown clients.
+pub async fn enqueue(
+    state: &AppState,
+    script: &ScriptGroup,
+    client_id: Uuid,
+    params: &HashMap<String, String>,
+    server_host: &str,
+    ttl_sec: Option<u64>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let known: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM clients WHERE id = ?")
+        .bind(client_id.to_string())
+        .fetch_one(&state.db)
+        .await?;
+    if known == 0 {
+        return Ok(None);
+    }
+
+    let history_id = Uuid::new_v4();
+    let now = chrono::Utc::now();
+    let ttl = ttl_sec.unwrap_or(state.config.pending_execution_ttl_sec);
+    let expires_at = chrono::TimeDelta::try_seconds(ttl.min(i64::MAX as u64) as i64)
+        .and_then(|d| now.checked_add_signed(d))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+    sqlx::query("INSERT INTO execution_history (id, script_id, client_id, status, started_at, params, script_snapshot, current_step, server_host, expires_at, logs) VALUES (?, ?, ?, 'pending', ?, ?, ?, 0, ?, ?, ?)")
+        .bind(history_id.to_string())
+        .bind(script.id.to_string())
+        .bind(client_id.to_string())
+        .bind(now)
+        .bind(serde_json::to_string(params).unwrap_or("{}".to_string()))
+        .bind(serde_json::to_string(script).unwrap_or_default())
+        .bind(server_host)
+        .bind(expires_at)
+        .bind(serde_json::to_string(&[format!("Queued until client reconnects (expires {})", expires_at.format("%Y-%m-%d %H:%M:%S UTC"))]).unwrap_or_default())
+        .execute(&state.db)
+        .await?;
+    info!("Queued script {} for offline client {}", script.name, client_id);
+    Ok(Some(history_id))
+}
+
+/// Marks queued executions whose client did not come back in time as `expired`.
+pub async fn run_expiry(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        match sqlx::query("UPDATE execution_history SET status = 'expired', completed_at = CURRENT_TIMESTAMP, logs = json_insert(COALESCE(logs, '[]'), '$[#]', 'Expired before the client reconnected') WHERE status = 'pending' AND expires_at <= ?")
+            .bind(chrono::Utc::now())
+            .execute(&state.db)
+            .await
+        {
+            Ok(r) if r.rows_affected() > 0 => info!("{} queued execution(s) expired", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => error!("Failed to expire queued executions: {}", e),
+        }
+    }
+}
+
+/// Runs the client's `resuming` and `pending` executions, oldest first. Called when a client registers.
+/// The batching and failure limits of a rolling group run only live in its rollout task, so a
+/// continuation started here runs on its own and doesn't count towards them.
+pub async fn dispatch_for_client(state: Arc<AppState>, client_id: Uuid) {
+    let rows = match sqlx::query("SELECT id, status, script_snapshot, params, server_host, schedule_id, remaining_runs FROM execution_history WHERE client_id = ? AND status IN ('resuming', 'pending') ORDER BY started_at")
+        .bind(client_id.to_string())
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to load queued executions for client {}: {}", client_id, e);
+            return;
+        }
+    };
+
+    for row in rows {
+        let id_str: String = row.get("id");
+        let Ok(history_id) = Uuid::parse_str(&id_str) else { continue };
+        let queued = row.get::<String, _>("status") == "pending";
+
+        // Claim the row so a quick reconnect doesn't run it twice
+        let now = chrono::Utc::now();
+        let claim = if queued {
+            sqlx::query("UPDATE execution_history SET status = 'running', started_at = ? WHERE id = ? AND status = 'pending' AND (expires_at IS NULL OR expires_at > ?)")
+                .bind(now)
+                .bind(&id_str)
+                .bind(now)
+        } else {
+            sqlx::query("UPDATE execution_history SET status = 'running' WHERE id = ? AND status = 'resuming'")
+                .bind(&id_str)
+        };
+        let claimed = claim.execute(&state.db)
+            .await
+            .map(|r| r.rows_affected() == 1)
+            .unwrap_or(false);
+        if !claimed {
+            continue;
+        }
+
+        let script: Option<ScriptGroup> = row.get::<Option<String>, _>("script_snapshot").as_deref().and_then(|s| serde_json::from_str(s).ok());
+        let Some(script) = script else {
+            mark_one_interrupted(&state, &id_str, "Could not start, script snapshot unreadable").await;
+            continue;
+        };
+        let params: HashMap<String, String> = row.get::<Option<String>, _>("params").as_deref().and_then(|p| serde_json::from_str(p).ok()).unwrap_or_default();
+        let server_host = row.get::<Option<String>, _>("server_host").unwrap_or_default();
+
+        let point = if queued {
+            None
+        } else {
+            match handlers::load_resume_point(&state, history_id).await {
+                Ok(point) => Some(point),
+                Err(e) => {
+                    error!("Failed to load cursor of execution {}: {}", history_id, e);
+                    mark_one_interrupted(&state, &id_str, &format!("Interrupted by server restart, cannot resume: {}", e)).await;
+                    continue;
+                }
+            }
+        };
+        // The rest of a group run that was interrupted along with this script
+        let remaining: Vec<(ScriptGroup, HashMap<String, String>)> = row.get::<Option<String>, _>("remaining_runs").as_deref().and_then(|r| serde_json::from_str(r).ok()).unwrap_or_default();
+        let schedule_id = row.get::<Option<String>, _>("schedule_id").as_deref().and_then(|s| Uuid::parse_str(s).ok());
+
+        handlers::run_script_task(state.clone(), client_id, script, params, history_id, server_host.clone(), point).await;
+        if !remaining.is_empty() {
+            info!("Continuing group run on client {} with {} more script(s)", client_id, remaining.len());
+            handlers::run_group_on_client(state.clone(), client_id, Arc::new(remaining), server_host, schedule_id).await;
+        }
+    }
+}
+
+async fn mark_one_interrupted(state: &AppState, id: &str, reason: &str) {
+    let _ = sqlx::query("UPDATE execution_history SET status = 'interrupted', completed_at = CURRENT_TIMESTAMP, logs = json_insert(COALESCE(logs, '[]'), '$[#]', ?) WHERE id = ?")
+        .bind(reason)
+        .bind(id)
+        .execute(&state.db)
+        .await;
+}
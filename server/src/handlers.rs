@@ -1,6 +1,6 @@
 use axum::{
     extract::{ws::{Message as WsMessage, WebSocket, WebSocketUpgrade}, State, Json, Path, ConnectInfo, Multipart, Query},
-    response::IntoResponse,
+    response::{IntoResponse, sse::{Event, KeepAlive, Sse}},
     http::{StatusCode, HeaderMap},
 };
 use futures::{sink::SinkExt, stream::StreamExt};
@@ -13,8 +13,14 @@ use std::net::SocketAddr;
 use sqlx::Row;
 use sha2::{Sha256, Digest};
 use hex;
-
-use crate::state::{AppState, ClientConnection, ScriptGroup, ScriptStep, ExecutionProgress};
+use std::collections::HashMap;
+
+use crate::state::{AppState, ClientConnection, ScriptGroup, ScriptParam, ScriptStep, StepCondition, StepDefinition, StepOptions, StepStatus, ExecutionProgress};
+use crate::storage::{self, StorageError};
+use crate::{alerts, events, executions, inventory, metrics, notifications, rollout, scheduler, secrets, sessions, template, webhooks};
+use crate::events::ServerEvent;
+use crate::rollout::RolloutOptions;
+use crate::scheduler::{MissedRunPolicy, OverlapPolicy, Schedule, ScheduleTarget};
 use common::{Message, CommandPayload, CommandResult};
 
 #[allow(dead_code)]
@@ -175,99 +181,199 @@ pub async fn update_group(
 }
 
 // API: Run Group Scripts
+#[derive(serde::Deserialize, Default)]
+pub struct RunGroupRequest {
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    // Queue the run for offline members instead of skipping them
+    #[serde(default)]
+    pub queue_offline: bool,
+    pub queue_ttl_sec: Option<u64>,
+    #[serde(flatten)]
+    pub rollout: RolloutOptions,
+}
+
 pub async fn run_group_scripts(
     State(state): State<Arc<AppState>>,
     Path(group_id): Path<Uuid>,
     headers: HeaderMap,
+    payload: Option<Json<RunGroupRequest>>,
 ) -> impl IntoResponse {
-    let group_id_str = group_id.to_string();
-    
+    let Json(request) = payload.unwrap_or_default();
+    let provided = request.params;
+    if let Err(e) = request.rollout.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     // Determine server host
     let host = headers.get("host")
         .and_then(|h| h.to_str().ok())
         .map(|h| h.to_string())
         .unwrap_or_else(|| format!("{}:{}", state.config.host, state.config.port));
     
+    let group_run = match prepare_group_run(&state, group_id, &provided).await {
+        Ok(r) => r,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let runs = Arc::new(group_run.runs);
+
+    // Spawn Tasks
+    let mut online = Vec::new();
+    for client_id in group_run.members {
+        if !state.clients.contains_key(&client_id) {
+            if request.queue_offline {
+                if request.rollout.is_rolling() {
+                    warn!("Queued run of group {} for offline client {} will not be batched", group_id, client_id);
+                }
+                for (script, params) in runs.iter() {
+                    if let Err(e) = executions::enqueue(&state, script, client_id, params, &host, request.queue_ttl_sec).await {
+                        error!("Failed to queue script {} for client {}: {}", script.name, client_id, e);
+                    }
+                }
+            }
+            continue;
+        }
+        online.push(client_id);
+    }
+
+    if request.rollout.is_rolling() && !online.is_empty() {
+        let group_name = sqlx::query_scalar::<_, String>("SELECT name FROM client_groups WHERE id = ?")
+            .bind(group_id.to_string())
+            .fetch_optional(&state.db)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| group_id.to_string());
+        let rollout_id = rollout::start(state.clone(), group_name, online, runs, host, request.rollout);
+        return (StatusCode::ACCEPTED, format!("Rolling group execution {} started", rollout_id)).into_response();
+    }
+    for client_id in online {
+        tokio::spawn(run_group_on_client(state.clone(), client_id, runs.clone(), host.clone(), None));
+    }
+
+    (StatusCode::ACCEPTED, "Group execution started").into_response()
+}
+
+// Members of a group and the scripts bound to it (with resolved parameters), in bound order
+pub(crate) struct GroupRun {
+    pub members: Vec<Uuid>,
+    pub runs: Vec<(ScriptGroup, HashMap<String, String>)>,
+}
+
+pub(crate) async fn prepare_group_run(
+    state: &AppState,
+    group_id: Uuid,
+    provided: &HashMap<String, serde_json::Value>,
+) -> Result<GroupRun, (StatusCode, String)> {
+    let group_id_str = group_id.to_string();
+
     // 1. Fetch Group Members
-    let members = match sqlx::query!("SELECT client_id FROM client_group_members WHERE group_id = ?", group_id_str)
+    let members = sqlx::query("SELECT client_id FROM client_group_members WHERE group_id = ?")
+        .bind(&group_id_str)
         .fetch_all(&state.db)
-        .await {
-            Ok(m) => m,
-            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch members: {}", e)).into_response(),
-        };
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch members: {}", e)))?;
 
     if members.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Group has no members").into_response();
+        return Err((StatusCode::BAD_REQUEST, "Group has no members".to_string()));
     }
 
     // 2. Fetch Group Scripts
-    let scripts_rows = match sqlx::query("SELECT script_id FROM group_scripts WHERE group_id = ? ORDER BY sort_order ASC")
-        .bind(group_id_str)
+    let scripts_rows = sqlx::query("SELECT script_id FROM group_scripts WHERE group_id = ? ORDER BY sort_order ASC")
+        .bind(&group_id_str)
         .fetch_all(&state.db)
-        .await {
-            Ok(s) => s,
-            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch scripts: {}", e)).into_response(),
-        };
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch scripts: {}", e)))?;
 
     if scripts_rows.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Group has no bound scripts").into_response();
+        return Err((StatusCode::BAD_REQUEST, "Group has no bound scripts".to_string()));
     }
 
     let mut scripts = Vec::new();
     for row in scripts_rows {
         let script_id_str: String = row.get("script_id");
-         let script_row = match sqlx::query!("SELECT id, name, steps FROM scripts WHERE id = ?", script_id_str)
-            .fetch_optional(&state.db)
-            .await {
-                Ok(Some(r)) => r,
-                Ok(None) => continue,
-                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)).into_response(),
-            };
-        
-        let steps: Vec<ScriptStep> = serde_json::from_str(&script_row.steps).unwrap_or_default();
-        scripts.push(ScriptGroup {
-            id: Uuid::parse_str(script_row.id.as_deref().unwrap_or("")).unwrap_or_default(),
-            name: script_row.name,
-            steps,
-        });
+        match fetch_script(state, &script_id_str).await {
+            Ok(Some(s)) => scripts.push(s),
+            Ok(None) => continue,
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e))),
+        }
     }
 
-    let scripts = Arc::new(scripts);
-
-    // 3. Spawn Tasks
-    for member in members {
-        let client_id = Uuid::parse_str(&member.client_id).unwrap_or_default();
-        if !state.clients.contains_key(&client_id) {
-            continue;
+    // Every bound script receives the same values, each takes the ones it declares
+    if let Some(name) = provided.keys().find(|k| !scripts.iter().any(|s| s.params.iter().any(|p| &p.name == *k))) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown parameter '{}'", name)));
+    }
+    let mut runs = Vec::new();
+    for script in scripts {
+        match template::resolve_params(&script.params, provided) {
+            Ok(params) => runs.push((script, params)),
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("{}: {}", script.name, e))),
         }
+    }
 
-        let state_clone = state.clone();
-        let scripts_clone = scripts.clone();
-        let host_clone = host.clone();
-        
-        tokio::spawn(async move {
-            for script in scripts_clone.iter().cloned() {
-                let history_id = Uuid::new_v4();
-                let history_id_str = history_id.to_string();
-                let script_id_str = script.id.to_string();
-                let client_id_str = client_id.to_string();
-                
-                // Create History Record
-                let now_utc = chrono::Utc::now();
-                if let Err(e) = sqlx::query!(
-                    "INSERT INTO execution_history (id, script_id, client_id, status, started_at) VALUES (?, ?, ?, ?, ?)",
-                    history_id_str, script_id_str, client_id_str, "running", now_utc
-                ).execute(&state_clone.db).await {
-                    error!("Failed to create history record: {}", e);
-                    continue;
-                }
+    let members = members.iter()
+        .map(|r| Uuid::parse_str(&r.get::<String, _>("client_id")).unwrap_or_default())
+        .collect();
+    Ok(GroupRun { members, runs })
+}
 
-                run_script_task(state_clone.clone(), client_id, script, history_id, host_clone.clone()).await;
-            }
-        });
+/// Runs the group's scripts one after another on a single client. Returns whether all of them succeeded.
+pub(crate) async fn run_group_on_client(
+    state: Arc<AppState>,
+    client_id: Uuid,
+    runs: Arc<Vec<(ScriptGroup, HashMap<String, String>)>>,
+    host: String,
+    schedule_id: Option<Uuid>,
+) -> bool {
+    let mut success = true;
+    for (i, (script, params)) in runs.iter().cloned().enumerate() {
+        success &= run_chained_script(state.clone(), client_id, script, params, &runs[i + 1..], host.clone(), schedule_id).await;
+    }
+    success
+}
+
+/// Creates the history record and runs the script to completion on one client. Returns whether it succeeded.
+pub(crate) async fn run_script_on_client(
+    state: Arc<AppState>,
+    client_id: Uuid,
+    script: ScriptGroup,
+    params: HashMap<String, String>,
+    host: String,
+    schedule_id: Option<Uuid>,
+) -> bool {
+    run_chained_script(state, client_id, script, params, &[], host, schedule_id).await
+}
+
+// `remaining` are the group scripts still to run after this one, recorded so a resumed run continues with them
+async fn run_chained_script(
+    state: Arc<AppState>,
+    client_id: Uuid,
+    script: ScriptGroup,
+    params: HashMap<String, String>,
+    remaining: &[(ScriptGroup, HashMap<String, String>)],
+    host: String,
+    schedule_id: Option<Uuid>,
+) -> bool {
+    let history_id = Uuid::new_v4();
+
+    // Create History Record
+    if let Err(e) = create_history_record(&state, history_id, &script, client_id, &params, schedule_id, &host).await {
+        error!("Failed to create history record: {}", e);
+        return false;
+    }
+    if !remaining.is_empty() {
+        let remaining_json = serde_json::to_string(remaining).unwrap_or_default();
+        if let Err(e) = sqlx::query("UPDATE execution_history SET remaining_runs = ? WHERE id = ?")
+            .bind(remaining_json)
+            .bind(history_id.to_string())
+            .execute(&state.db)
+            .await
+        {
+            error!("Failed to record remaining group scripts of execution {}: {}", history_id, e);
+        }
     }
 
-    (StatusCode::ACCEPTED, "Group execution started").into_response()
+    run_script_task(state, client_id, script, params, history_id, host, None).await
 }
 
 // API: Get Active Executions
@@ -281,43 +387,150 @@ pub async fn get_active_executions(
     Json(list)
 }
 
+fn script_from_row(r: &sqlx::sqlite::SqliteRow) -> ScriptGroup {
+    ScriptGroup {
+        id: Uuid::parse_str(r.get::<Option<String>, _>("id").as_deref().unwrap_or("")).unwrap_or_default(),
+        name: r.get("name"),
+        steps: serde_json::from_str(&r.get::<String, _>("steps")).unwrap_or_default(),
+        params: r.get::<Option<String>, _>("params").as_deref().and_then(|p| serde_json::from_str(p).ok()).unwrap_or_default(),
+    }
+}
+
+pub(crate) async fn fetch_script(state: &AppState, id: &str) -> Result<Option<ScriptGroup>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, name, steps, params FROM scripts WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?;
+    Ok(row.as_ref().map(script_from_row))
+}
+
+// The script is snapshotted with the record so an interrupted run can be resumed after a restart
+async fn create_history_record(
+    state: &AppState,
+    history_id: Uuid,
+    script: &ScriptGroup,
+    client_id: Uuid,
+    params: &HashMap<String, String>,
+    schedule_id: Option<Uuid>,
+    server_host: &str,
+) -> Result<(), sqlx::Error> {
+    let params_json = serde_json::to_string(params).unwrap_or("{}".to_string());
+    let script_json = serde_json::to_string(script).unwrap_or_default();
+    sqlx::query("INSERT INTO execution_history (id, script_id, client_id, status, started_at, params, schedule_id, script_snapshot, current_step, server_host) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 0, ?)")
+        .bind(history_id.to_string())
+        .bind(script.id.to_string())
+        .bind(client_id.to_string())
+        .bind("running")
+        .bind(chrono::Utc::now())
+        .bind(params_json)
+        .bind(schedule_id.map(|id| id.to_string()))
+        .bind(script_json)
+        .bind(server_host)
+        .execute(&state.db)
+        .await?;
+    Ok(())
+}
+
 pub async fn list_scripts(State(state): State<Arc<AppState>>) -> Json<Vec<ScriptGroup>> {
-    let rows = sqlx::query!("SELECT id, name, steps FROM scripts ORDER BY created_at DESC")
+    let rows = sqlx::query("SELECT id, name, steps, params FROM scripts ORDER BY created_at DESC")
         .fetch_all(&state.db)
         .await
         .unwrap_or_default();
 
-    let scripts = rows.into_iter().map(|r| {
-        let steps: Vec<ScriptStep> = serde_json::from_str(&r.steps).unwrap_or_default();
-        ScriptGroup {
-            id: Uuid::parse_str(r.id.as_deref().unwrap_or("")).unwrap_or_default(),
-            name: r.name,
-            steps,
-        }
-    }).collect();
-    Json(scripts)
+    Json(rows.iter().map(script_from_row).collect())
 }
 
 // API: Create Script
 #[derive(serde::Deserialize)]
 pub struct CreateScriptRequest {
     pub name: String,
-    pub steps: Vec<ScriptStep>,
+    pub steps: Vec<StepDefinition>,
+    #[serde(default)]
+    pub params: Vec<ScriptParam>,
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn validate_script(payload: &CreateScriptRequest) -> Result<(), String> {
+    validate_script_params(&payload.params)?;
+    let mut output_names: Vec<&str> = Vec::new();
+    for (i, def) in payload.steps.iter().enumerate() {
+        // Outputs can only be used by the steps after the one that captures them
+        for name in template::references(def)? {
+            if let Some(output) = name.strip_prefix("outputs.") {
+                if !output_names.contains(&output) {
+                    return Err(format!("Step {}: '{{{{{}}}}}' is not an output of an earlier step", i + 1, name));
+                }
+            }
+        }
+        for output in &def.outputs {
+            if !is_identifier(&output.name) {
+                return Err(format!("Step {}: invalid output name '{}'", i + 1, output.name));
+            }
+            if output_names.contains(&output.name.as_str()) {
+                return Err(format!("Step {}: duplicate output '{}'", i + 1, output.name));
+            }
+            if output.regex.is_some() && output.json_path.is_some() {
+                return Err(format!("Step {}: output '{}' can use either regex or json_path", i + 1, output.name));
+            }
+            if let Some(Err(e)) = output.regex.as_deref().map(regex::Regex::new) {
+                return Err(format!("Step {}: invalid regex for output '{}': {}", i + 1, output.name, e));
+            }
+            output_names.push(&output.name);
+        }
+
+        let Some(cond) = &def.options.when else { continue };
+        if let Some(step) = cond.step {
+            if step == 0 || step > i {
+                return Err(format!("Step {}: condition can only refer to an earlier step", i + 1));
+            }
+        }
+        if let Some(pattern) = cond.output_matches.as_deref().filter(|p| !p.contains("{{")) {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(format!("Step {}: invalid output_matches pattern: {}", i + 1, e));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_script_params(params: &[ScriptParam]) -> Result<(), String> {
+    for (i, param) in params.iter().enumerate() {
+        if !is_identifier(&param.name) {
+            return Err(format!("Invalid parameter name '{}'", param.name));
+        }
+        if params[..i].iter().any(|p| p.name == param.name) {
+            return Err(format!("Duplicate parameter '{}'", param.name));
+        }
+        // Defaults must satisfy their own declaration
+        if param.default.is_some() {
+            template::resolve_params(std::slice::from_ref(param), &HashMap::new())?;
+        }
+    }
+    Ok(())
 }
 
 pub async fn create_script(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<CreateScriptRequest>,
 ) -> impl IntoResponse {
+    if let Err(e) = validate_script(&payload) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let id = Uuid::new_v4();
-    let id_str = id.to_string();
-    let name = &payload.name;
     let steps_json = serde_json::to_string(&payload.steps).unwrap_or("[]".to_string());
+    let params_json = serde_json::to_string(&payload.params).unwrap_or("[]".to_string());
     
-    if let Err(e) = sqlx::query!(
-        "INSERT INTO scripts (id, name, steps) VALUES (?, ?, ?)",
-        id_str, name, steps_json
-    ).execute(&state.db).await {
+    if let Err(e) = sqlx::query("INSERT INTO scripts (id, name, steps, params) VALUES (?, ?, ?, ?)")
+        .bind(id.to_string())
+        .bind(&payload.name)
+        .bind(steps_json)
+        .bind(params_json)
+        .execute(&state.db).await {
          return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create script: {}", e)).into_response();
     }
     
@@ -330,14 +543,19 @@ pub async fn update_script(
     Path(id): Path<Uuid>,
     Json(payload): Json<CreateScriptRequest>,
 ) -> impl IntoResponse {
-    let id_str = id.to_string();
-    let name = &payload.name;
+    if let Err(e) = validate_script(&payload) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
     let steps_json = serde_json::to_string(&payload.steps).unwrap_or("[]".to_string());
+    let params_json = serde_json::to_string(&payload.params).unwrap_or("[]".to_string());
     
-    if let Err(e) = sqlx::query!(
-        "UPDATE scripts SET name = ?, steps = ? WHERE id = ?",
-        name, steps_json, id_str
-    ).execute(&state.db).await {
+    if let Err(e) = sqlx::query("UPDATE scripts SET name = ?, steps = ?, params = ? WHERE id = ?")
+        .bind(&payload.name)
+        .bind(steps_json)
+        .bind(params_json)
+        .bind(id.to_string())
+        .execute(&state.db).await {
          return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update script: {}", e)).into_response();
     }
     
@@ -364,6 +582,12 @@ pub async fn delete_script(
 #[derive(serde::Deserialize)]
 pub struct RunScriptRequest {
     pub client_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    // Queue the run for offline clients instead of skipping them
+    #[serde(default)]
+    pub queue_offline: bool,
+    pub queue_ttl_sec: Option<u64>,
 }
 
 pub async fn run_script(
@@ -381,22 +605,22 @@ pub async fn run_script(
         .unwrap_or_else(|| format!("{}:{}", state.config.host, state.config.port));
 
     // Fetch script from DB
-    let row = match sqlx::query!("SELECT name, steps FROM scripts WHERE id = ?", script_id_str)
-        .fetch_optional(&state.db)
-        .await {
-            Ok(Some(r)) => r,
-            Ok(None) => return (StatusCode::NOT_FOUND, "Script not found").into_response(),
-            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)).into_response(),
-        };
+    let script = match fetch_script(&state, &script_id_str).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Script not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)).into_response(),
+    };
 
-    let steps: Vec<ScriptStep> = serde_json::from_str(&row.steps).unwrap_or_default();
-    let script = ScriptGroup {
-        id: script_id,
-        name: row.name,
-        steps,
+    if let Some(name) = payload.params.keys().find(|k| !script.params.iter().any(|p| &p.name == *k)) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown parameter '{}'", name)).into_response();
+    }
+    let params = match template::resolve_params(&script.params, &payload.params) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
     };
 
     let client_ids = payload.client_ids;
+    let (queue_offline, queue_ttl_sec) = (payload.queue_offline, payload.queue_ttl_sec);
     let state_clone = state.clone();
     let script_clone = script.clone();
     let host_clone = host.clone();
@@ -405,31 +629,28 @@ pub async fn run_script(
         // Create history and dispatch in the background to avoid request timeouts when many clients are selected.
         for client_id in client_ids {
             if !state_clone.clients.contains_key(&client_id) {
+                if queue_offline {
+                    match executions::enqueue(&state_clone, &script_clone, client_id, &params, &host_clone, queue_ttl_sec).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => warn!("Not queueing script {} for unknown client {}", script_clone.name, client_id),
+                        Err(e) => error!("Failed to queue script {} for client {}: {}", script_clone.name, client_id, e),
+                    }
+                }
                 continue;
             }
 
             let history_id = Uuid::new_v4();
-            let history_id_str = history_id.to_string();
-            let script_id_str_run = script_clone.id.to_string();
-            let client_id_str = client_id.to_string();
-
-            let now_utc = chrono::Utc::now();
-            if let Err(e) = sqlx::query!(
-                "INSERT INTO execution_history (id, script_id, client_id, status, started_at) VALUES (?, ?, ?, ?, ?)",
-                history_id_str, script_id_str_run, client_id_str, "running", now_utc
-            )
-            .execute(&state_clone.db)
-            .await
-            {
+            if let Err(e) = create_history_record(&state_clone, history_id, &script_clone, client_id, &params, None, &host_clone).await {
                 error!("Failed to create history record: {}", e);
                 continue;
             }
 
             let state_task = state_clone.clone();
             let script_task = script_clone.clone();
+            let params_task = params.clone();
             let host_task = host_clone.clone();
             tokio::spawn(async move {
-                run_script_task(state_task, client_id, script_task, history_id, host_task).await;
+                run_script_task(state_task, client_id, script_task, params_task, history_id, host_task, None).await;
             });
         }
     });
@@ -437,142 +658,1008 @@ pub async fn run_script(
     (StatusCode::ACCEPTED, "Script execution started on selected clients").into_response()
 }
 
-use walkdir::WalkDir;
-use zip::write::FileOptions;
+// API: Schedules
+#[derive(serde::Deserialize)]
+pub struct ScheduleRequest {
+    pub name: String,
+    pub cron: String,
+    pub timezone: Option<String>,
+    pub target: ScheduleTarget,
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub overlap_policy: OverlapPolicy,
+    #[serde(default)]
+    pub missed_run_policy: MissedRunPolicy,
+    pub enabled: Option<bool>,
+}
 
-fn zip_directory(src_dir: &str, dst_file: &str) -> anyhow::Result<()> {
-    if !std::path::Path::new(src_dir).is_dir() {
-        return Err(anyhow::anyhow!("Source is not a directory"));
+async fn validate_schedule(state: &AppState, payload: &ScheduleRequest, timezone: &str) -> Result<(), (StatusCode, String)> {
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+    scheduler::parse_cron(&payload.cron).map_err(bad_request)?;
+    scheduler::parse_timezone(timezone).map_err(bad_request)?;
+
+    match &payload.target {
+        ScheduleTarget::Script { script_id, client_ids } => {
+            let script = fetch_script(state, &script_id.to_string()).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)))?
+                .ok_or_else(|| bad_request("Script not found".to_string()))?;
+            if client_ids.is_empty() {
+                return Err(bad_request("Schedule has no target clients".to_string()));
+            }
+            if let Some(name) = payload.params.keys().find(|k| !script.params.iter().any(|p| &p.name == *k)) {
+                return Err(bad_request(format!("Unknown parameter '{}'", name)));
+            }
+            template::resolve_params(&script.params, &payload.params).map_err(bad_request)?;
+        }
+        ScheduleTarget::Group { group_id } => {
+            let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM client_groups WHERE id = ?")
+                .bind(group_id.to_string())
+                .fetch_one(&state.db)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)))?;
+            if exists == 0 {
+                return Err(bad_request("Group not found".to_string()));
+            }
+        }
     }
+    Ok(())
+}
 
-    let file = std::fs::File::create(dst_file)?;
-    let mut zip = zip::ZipWriter::new(file);
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+pub async fn list_schedules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match scheduler::list(&state.db).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list schedules: {}", e)).into_response(),
+    }
+}
 
-    let walkdir = WalkDir::new(src_dir);
-    let it = walkdir.into_iter();
+pub async fn get_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match scheduler::get(&state.db, id).await {
+        Ok(Some(schedule)) => Json(schedule).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Schedule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load schedule: {}", e)).into_response(),
+    }
+}
 
-    for entry in it {
-        let entry = entry?;
-        let path = entry.path();
-        let name = path.strip_prefix(std::path::Path::new(src_dir))?;
-        let path_as_string = name
-            .to_str()
-            .map(str::to_owned)
-            .ok_or_else(|| anyhow::anyhow!("Invalid path"))?;
+pub async fn create_schedule(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ScheduleRequest>,
+) -> impl IntoResponse {
+    save_schedule(&state, Uuid::new_v4(), payload, StatusCode::CREATED).await
+}
 
-        if path.is_file() {
-            zip.start_file(path_as_string, options)?;
-            let mut f = std::fs::File::open(path)?;
-            std::io::copy(&mut f, &mut zip)?;
-        } else if !name.as_os_str().is_empty() {
-            zip.add_directory(path_as_string, options)?;
-        }
+pub async fn update_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ScheduleRequest>,
+) -> impl IntoResponse {
+    match scheduler::get(&state.db, id).await {
+        Ok(Some(_)) => save_schedule(&state, id, payload, StatusCode::OK).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "Schedule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load schedule: {}", e)).into_response(),
     }
-    zip.finish()?;
-    Ok(())
 }
 
-async fn run_script_task(state: Arc<AppState>, client_id: Uuid, script: ScriptGroup, history_id: Uuid, server_host: String) {
-    info!("Starting script {} on client {}", script.name, client_id);
-    
-    // Get client hostname for progress
-    let client_hostname = if let Some(c) = state.clients.get(&client_id) {
-        c.hostname.clone()
-    } else {
-        "Unknown".to_string()
+async fn save_schedule(state: &AppState, id: Uuid, payload: ScheduleRequest, success: StatusCode) -> axum::response::Response {
+    let timezone = payload.timezone.clone().unwrap_or_else(|| "UTC".to_string());
+    if let Err((status, msg)) = validate_schedule(state, &payload, &timezone).await {
+        return (status, msg).into_response();
+    }
+
+    let schedule = Schedule {
+        id,
+        name: payload.name,
+        cron: payload.cron,
+        timezone,
+        target: payload.target,
+        params: payload.params,
+        overlap_policy: payload.overlap_policy,
+        missed_run_policy: payload.missed_run_policy,
+        enabled: payload.enabled.unwrap_or(true),
+        last_run_at: None,
+        next_run_at: None,
+        created_at: None,
     };
+    if let Err(e) = scheduler::save(&state.db, &schedule).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save schedule: {}", e)).into_response();
+    }
 
-    let mut logs = Vec::new();
-    let mut success = true;
-    let total_steps = script.steps.len();
+    match scheduler::get(&state.db, id).await {
+        Ok(Some(saved)) => (success, Json(saved)).into_response(),
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "Schedule disappeared after saving").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load schedule: {}", e)).into_response(),
+    }
+}
 
-    // Initialize Active Execution
-    state.active_executions.insert(history_id, ExecutionProgress {
-        execution_id: history_id,
-        script_name: script.name.clone(),
-        client_hostname: client_hostname.clone(),
-        status: "running".to_string(),
-        logs: Vec::new(),
-        current_step: 0,
-        total_steps,
-    });
+pub async fn delete_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM schedules WHERE id = ?").bind(id.to_string()).execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Schedule not found").into_response(),
+        Ok(_) => {
+            state.schedule_locks.remove(&id);
+            (StatusCode::OK, "Schedule deleted").into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete schedule: {}", e)).into_response(),
+    }
+}
 
-    for (i, step) in script.steps.iter().enumerate() {
-        // Update Progress
-        if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-            progress.current_step = i + 1;
+// API: Trigger a schedule immediately (overlap policy still applies)
+pub async fn run_schedule_now(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match scheduler::get(&state.db, id).await {
+        Ok(Some(schedule)) => {
+            scheduler::trigger(state.clone(), schedule);
+            (StatusCode::ACCEPTED, "Schedule triggered").into_response()
         }
-        
-        let base_url = get_download_base_url(&state, Some(client_id), Some(&server_host));
+        Ok(None) => (StatusCode::NOT_FOUND, "Schedule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load schedule: {}", e)).into_response(),
+    }
+}
 
-        let cmd_payload_result = match step {
-            ScriptStep::Shell { cmd, args } => Ok(CommandPayload::ShellExec { cmd: cmd.clone(), args: args.clone() }),
-            ScriptStep::Upload { local_path, remote_path } => {
-                let download_url = format!("{}/api/files/download/staging/{}", base_url, local_path);
-                Ok(CommandPayload::DownloadFile { url: download_url, dest_path: remote_path.clone() })
-            },
-            ScriptStep::Download { remote_path, browser_download } => {
-                let upload_id = Uuid::new_v4();
-                let upload_url = format!("{}/api/files/client-upload/{}", base_url, upload_id);
-                
-                if browser_download.unwrap_or(false) {
-                    let file_name = std::path::Path::new(remote_path).file_name().unwrap_or_default().to_string_lossy();
-                    let download_link = format!("{}/api/files/download/client_data/{}/{}", base_url, upload_id, file_name);
-                    let log_msg = format!("BROWSER_DOWNLOAD: {}", download_link);
-                    logs.push(log_msg.clone());
-                    if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                        progress.logs.push(log_msg);
-                    }
-                }
-                
-                Ok(CommandPayload::UploadFile { src_path: remote_path.clone(), upload_url })
-            },
-            ScriptStep::UploadDir { local_path, remote_path } => {
-                // Zip the directory first
-                let src_dir = format!("uploads/staging/{}", local_path);
-                let zip_name = format!("{}.zip", local_path);
-                let dst_zip = format!("uploads/staging/{}", zip_name);
-                
-                match zip_directory(&src_dir, &dst_zip) {
-                    Ok(_) => {
-                        let download_url = format!("{}/api/files/download/staging/{}", base_url, zip_name);
-                        Ok(CommandPayload::DownloadAndUnzip { url: download_url, dest_path: remote_path.clone() })
-                    },
-                    Err(e) => Err(format!("Failed to zip directory: {}", e))
-                }
-            },
-            ScriptStep::DownloadDir { remote_path, browser_download } => {
-                let upload_id = Uuid::new_v4();
-                // Client will upload a zip file, server receives it as generic file upload
-                let upload_url = format!("{}/api/files/client-upload/{}", base_url, upload_id);
-                
-                if browser_download.unwrap_or(false) {
-                    let file_name = format!("{}.zip", std::path::Path::new(remote_path).file_name().unwrap_or_default().to_string_lossy());
-                    let download_link = format!("{}/api/files/download/client_data/{}/{}", base_url, upload_id, file_name);
-                    let log_msg = format!("BROWSER_DOWNLOAD: {}", download_link);
-                    logs.push(log_msg.clone());
-                    if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                        progress.logs.push(log_msg);
-                    }
-                }
+// API: Webhooks
+#[derive(serde::Deserialize)]
+pub struct WebhookRequest {
+    pub name: String,
+    pub url: String,
+    pub events: Vec<String>,
+    // HMAC-SHA256 signing key. Omit on update to keep the current one, "" to remove it
+    pub secret: Option<String>,
+    pub payload_template: Option<serde_json::Value>,
+    pub enabled: Option<bool>,
+    pub max_retries: Option<u32>,
+}
 
-                Ok(CommandPayload::ZipAndUpload { src_path: remote_path.clone(), upload_url })
-            },
-            ScriptStep::Copy { src_path, dest_path } => {
-                Ok(CommandPayload::CopyFile { src_path: src_path.clone(), dest_path: dest_path.clone() })
-            },
-            ScriptStep::Move { src_path, dest_path } => {
-                Ok(CommandPayload::MoveFile { src_path: src_path.clone(), dest_path: dest_path.clone() })
-            },
-            ScriptStep::Delete { path } => {
-                Ok(CommandPayload::DeleteFile { path: path.clone() })
+pub async fn list_webhooks(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match webhooks::list(&state.db).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list webhooks: {}", e)).into_response(),
+    }
+}
+
+pub async fn create_webhook(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<WebhookRequest>,
+) -> impl IntoResponse {
+    save_webhook(&state, Uuid::new_v4(), None, payload, StatusCode::CREATED).await
+}
+
+pub async fn update_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<WebhookRequest>,
+) -> impl IntoResponse {
+    match webhooks::get(&state.db, id).await {
+        Ok(Some(existing)) => save_webhook(&state, id, existing.secret, payload, StatusCode::OK).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load webhook: {}", e)).into_response(),
+    }
+}
+
+async fn save_webhook(state: &AppState, id: Uuid, current_secret: Option<String>, payload: WebhookRequest, success: StatusCode) -> axum::response::Response {
+    if !payload.url.starts_with("http://") && !payload.url.starts_with("https://") {
+        return (StatusCode::BAD_REQUEST, "Webhook URL must start with http:// or https://").into_response();
+    }
+    if let Err(e) = webhooks::validate_events(&payload.events) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let secret = match payload.secret.as_deref() {
+        None => current_secret,
+        Some("") => None,
+        Some(plain) => match state.secret_cipher.encrypt(plain) {
+            Ok(v) => Some(v),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        },
+    };
+
+    let webhook = webhooks::Webhook {
+        id,
+        name: payload.name,
+        url: payload.url,
+        events: payload.events,
+        has_secret: secret.is_some(),
+        secret,
+        payload_template: payload.payload_template,
+        enabled: payload.enabled.unwrap_or(true),
+        max_retries: payload.max_retries.unwrap_or(3).min(10),
+        created_at: None,
+    };
+    if let Err(e) = webhooks::save(&state.db, &webhook).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save webhook: {}", e)).into_response();
+    }
+
+    match webhooks::get(&state.db, id).await {
+        Ok(Some(saved)) => (success, Json(saved)).into_response(),
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "Webhook disappeared after saving").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load webhook: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM webhooks WHERE id = ?").bind(id.to_string()).execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Webhook deleted").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete webhook: {}", e)).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeliveryParams {
+    pub limit: Option<i64>,
+}
+
+// API: Delivery log of a webhook, newest first
+pub async fn list_webhook_deliveries(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DeliveryParams>,
+) -> impl IntoResponse {
+    match webhooks::list_deliveries(&state.db, id, params.limit.unwrap_or(50).clamp(1, 500)).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list deliveries: {}", e)).into_response(),
+    }
+}
+
+// API: Send a test event once (no retries) and return its delivery record
+pub async fn test_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let hook = match webhooks::get(&state.db, id).await {
+        Ok(Some(hook)) => hook,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load webhook: {}", e)).into_response(),
+    };
+    let hook = webhooks::Webhook { max_retries: 0, ..hook };
+    let delivery_id = webhooks::deliver(state.clone(), hook, "test".to_string(), serde_json::json!({ "message": "Test event from Roam" })).await;
+    match webhooks::list_deliveries(&state.db, id, 500).await {
+        Ok(list) => match list.into_iter().find(|d| d.id == delivery_id) {
+            Some(delivery) => Json(delivery).into_response(),
+            None => (StatusCode::INTERNAL_SERVER_ERROR, "Delivery was not recorded").into_response(),
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load delivery: {}", e)).into_response(),
+    }
+}
+
+// API: Notification rules (mailed over SMTP)
+#[derive(serde::Deserialize)]
+pub struct NotificationRuleRequest {
+    pub name: String,
+    pub kind: String,
+    pub script_id: Option<Uuid>,
+    pub client_id: Option<Uuid>,
+    pub offline_minutes: Option<u32>,
+    pub recipients: Vec<String>,
+    pub enabled: Option<bool>,
+}
+
+pub async fn list_notification_rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match notifications::list(&state.db).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list notification rules: {}", e)).into_response(),
+    }
+}
+
+pub async fn create_notification_rule(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<NotificationRuleRequest>,
+) -> impl IntoResponse {
+    save_notification_rule(&state, Uuid::new_v4(), payload, StatusCode::CREATED).await
+}
+
+pub async fn update_notification_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<NotificationRuleRequest>,
+) -> impl IntoResponse {
+    match notifications::get(&state.db, id).await {
+        Ok(Some(_)) => save_notification_rule(&state, id, payload, StatusCode::OK).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "Notification rule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load notification rule: {}", e)).into_response(),
+    }
+}
+
+async fn save_notification_rule(state: &AppState, id: Uuid, payload: NotificationRuleRequest, success: StatusCode) -> axum::response::Response {
+    if let Err(e) = notifications::validate(&payload.kind, &payload.recipients) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if payload.offline_minutes == Some(0) {
+        return (StatusCode::BAD_REQUEST, "offline_minutes must be at least 1").into_response();
+    }
+
+    let rule = notifications::Rule {
+        id,
+        name: payload.name,
+        kind: payload.kind,
+        script_id: payload.script_id,
+        client_id: payload.client_id,
+        offline_minutes: payload.offline_minutes.unwrap_or(10),
+        recipients: payload.recipients,
+        enabled: payload.enabled.unwrap_or(true),
+        created_at: None,
+    };
+    if let Err(e) = notifications::save(&state.db, &rule).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save notification rule: {}", e)).into_response();
+    }
+
+    match notifications::get(&state.db, id).await {
+        Ok(Some(saved)) => (success, Json(saved)).into_response(),
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "Notification rule disappeared after saving").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load notification rule: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_notification_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM notification_rules WHERE id = ?").bind(id.to_string()).execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Notification rule not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Notification rule deleted").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete notification rule: {}", e)).into_response(),
+    }
+}
+
+// API: Notifications sent for a rule, newest first
+pub async fn list_notification_log(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DeliveryParams>,
+) -> impl IntoResponse {
+    match notifications::list_log(&state.db, id, params.limit.unwrap_or(50).clamp(1, 500)).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list notifications: {}", e)).into_response(),
+    }
+}
+
+// API: Mail a test message to the rule's recipients and return the log entry
+pub async fn test_notification_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match notifications::get(&state.db, id).await {
+        Ok(Some(rule)) => {
+            let body = format!("This is a test message for the notification rule \"{}\" ({}).\n", rule.name, rule.kind);
+            Json(notifications::send(&state, &rule, "[Roam] Test notification", &body, None).await).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Notification rule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load notification rule: {}", e)).into_response(),
+    }
+}
+
+// API: Threshold alert rules on client metrics
+#[derive(serde::Deserialize)]
+pub struct AlertRuleRequest {
+    pub name: String,
+    pub metric: String,
+    pub mount_point: Option<String>,
+    pub operator: Option<String>,
+    pub threshold: f64,
+    pub duration_sec: Option<u64>,
+    pub client_id: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    pub remediation_script_id: Option<Uuid>,
+    #[serde(default)]
+    pub remediation_params: HashMap<String, serde_json::Value>,
+    pub enabled: Option<bool>,
+}
+
+pub async fn list_alert_rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match alerts::list_rules(&state.db).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list alert rules: {}", e)).into_response(),
+    }
+}
+
+pub async fn create_alert_rule(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AlertRuleRequest>,
+) -> impl IntoResponse {
+    save_alert_rule(&state, Uuid::new_v4(), payload, StatusCode::CREATED).await
+}
+
+pub async fn update_alert_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<AlertRuleRequest>,
+) -> impl IntoResponse {
+    match alerts::get_rule(&state.db, id).await {
+        Ok(Some(_)) => save_alert_rule(&state, id, payload, StatusCode::OK).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "Alert rule not found").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load alert rule: {}", e)).into_response(),
+    }
+}
+
+async fn save_alert_rule(state: &AppState, id: Uuid, payload: AlertRuleRequest, success: StatusCode) -> axum::response::Response {
+    let rule = alerts::Rule {
+        id,
+        name: payload.name,
+        metric: payload.metric,
+        mount_point: payload.mount_point.filter(|m| !m.is_empty()),
+        operator: payload.operator.unwrap_or_else(|| "above".to_string()),
+        threshold: payload.threshold,
+        duration_sec: payload.duration_sec.unwrap_or(0),
+        client_id: payload.client_id,
+        group_id: payload.group_id,
+        remediation_script_id: payload.remediation_script_id,
+        remediation_params: payload.remediation_params,
+        enabled: payload.enabled.unwrap_or(true),
+        created_at: None,
+    };
+    if let Err(e) = alerts::validate(&rule) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if let Some(script_id) = rule.remediation_script_id {
+        match fetch_script(state, &script_id.to_string()).await {
+            Ok(Some(script)) => {
+                if let Err(e) = template::resolve_params(&script.params, &rule.remediation_params) {
+                    return (StatusCode::BAD_REQUEST, e).into_response();
+                }
+            }
+            Ok(None) => return (StatusCode::BAD_REQUEST, "Remediation script not found").into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load script: {}", e)).into_response(),
+        }
+    }
+    if let Err(e) = alerts::save_rule(&state.db, &rule).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save alert rule: {}", e)).into_response();
+    }
+
+    match alerts::get_rule(&state.db, id).await {
+        Ok(Some(saved)) => (success, Json(saved)).into_response(),
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "Alert rule disappeared after saving").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load alert rule: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_alert_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM alert_rules WHERE id = ?").bind(id.to_string()).execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Alert rule not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Alert rule deleted").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete alert rule: {}", e)).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AlertParams {
+    // 'pending', 'firing' or 'resolved'
+    pub state: Option<String>,
+    pub client_id: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+// API: Alerts, newest first
+pub async fn list_alerts(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AlertParams>,
+) -> impl IntoResponse {
+    if let Some(s) = params.state.as_deref().filter(|s| ![alerts::PENDING, alerts::FIRING, alerts::RESOLVED].contains(s)) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown state '{}', expected pending, firing or resolved", s)).into_response();
+    }
+    match alerts::list_alerts(&state.db, params.state.as_deref(), params.client_id, params.limit.unwrap_or(100).clamp(1, 1000)).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list alerts: {}", e)).into_response(),
+    }
+}
+
+// API: Secrets (values are write-only, never returned)
+#[derive(serde::Serialize)]
+pub struct SecretItem {
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub async fn list_secrets(State(state): State<Arc<AppState>>) -> Json<Vec<SecretItem>> {
+    let rows = sqlx::query("SELECT name, description, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at, strftime('%Y-%m-%dT%H:%M:%SZ', updated_at) as updated_at FROM secrets ORDER BY name ASC")
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+
+    Json(rows.into_iter().map(|r| SecretItem {
+        name: r.get("name"),
+        description: r.get("description"),
+        created_at: r.get::<Option<String>, _>("created_at").unwrap_or_default(),
+        updated_at: r.get::<Option<String>, _>("updated_at").unwrap_or_default(),
+    }).collect())
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateSecretRequest {
+    pub name: String,
+    pub value: String,
+    pub description: Option<String>,
+}
+
+pub async fn create_secret(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateSecretRequest>,
+) -> impl IntoResponse {
+    if !secrets::is_valid_name(&payload.name) {
+        return (StatusCode::BAD_REQUEST, "Secret name may only contain letters, digits, '_', '.' and '-'").into_response();
+    }
+    let encrypted = match state.secret_cipher.encrypt(&payload.value) {
+        Ok(v) => v,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    match sqlx::query("INSERT INTO secrets (name, value, description) VALUES (?, ?, ?)")
+        .bind(&payload.name)
+        .bind(encrypted)
+        .bind(&payload.description)
+        .execute(&state.db).await {
+        Ok(_) => (StatusCode::CREATED, Json(serde_json::json!({ "name": payload.name }))).into_response(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            (StatusCode::CONFLICT, "Secret already exists").into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create secret: {}", e)).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateSecretRequest {
+    pub value: Option<String>,
+    pub description: Option<String>,
+}
+
+pub async fn update_secret(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(payload): Json<UpdateSecretRequest>,
+) -> impl IntoResponse {
+    let encrypted = match payload.value.as_deref().map(|v| state.secret_cipher.encrypt(v)).transpose() {
+        Ok(v) => v,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    match sqlx::query("UPDATE secrets SET value = COALESCE(?, value), description = COALESCE(?, description), updated_at = CURRENT_TIMESTAMP WHERE name = ?")
+        .bind(encrypted)
+        .bind(&payload.description)
+        .bind(&name)
+        .execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Secret not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Secret updated").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update secret: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_secret(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM secrets WHERE name = ?").bind(&name).execute(&state.db).await {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Secret not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Secret deleted").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete secret: {}", e)).into_response(),
+    }
+}
+
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+
+fn zip_directory(src_dir: &str, dst_file: &str) -> anyhow::Result<()> {
+    if !std::path::Path::new(src_dir).is_dir() {
+        return Err(anyhow::anyhow!("Source is not a directory"));
+    }
+
+    let file = std::fs::File::create(dst_file)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o755);
+
+    let walkdir = WalkDir::new(src_dir);
+    let it = walkdir.into_iter();
+
+    for entry in it {
+        let entry = entry?;
+        let path = entry.path();
+        let name = path.strip_prefix(std::path::Path::new(src_dir))?;
+        let path_as_string = name
+            .to_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow::anyhow!("Invalid path"))?;
+
+        if path.is_file() {
+            zip.start_file(path_as_string, options)?;
+            let mut f = std::fs::File::open(path)?;
+            std::io::copy(&mut f, &mut zip)?;
+        } else if !name.as_os_str().is_empty() {
+            zip.add_directory(path_as_string, options)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn push_log(state: &AppState, history_id: Uuid, logs: &mut Vec<String>, secret_values: &[String], msg: String) {
+    let msg = secrets::mask(&msg, secret_values);
+    logs.push(msg.clone());
+    if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
+        progress.logs.push(msg.clone());
+    }
+    events::execution_updated(state, history_id, Some(msg));
+}
+
+// Text used for output capture and `when` output checks: stdout for shell steps,
+// the payload for other results
+fn result_text(result: &CommandResult) -> String {
+    match result {
+        CommandResult::ShellOutput { stdout, .. } => stdout.clone(),
+        CommandResult::FileContent { content } => content.clone(),
+        CommandResult::FoundFiles { files, .. } => files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>().join("\n"),
+        CommandResult::ContentMatches { matches, .. } => matches.iter()
+            .map(|m| format!("{}:{}:{}", m.path, m.line_number, m.line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        CommandResult::Success(msg) | CommandResult::Error(msg) | CommandResult::PolicyDenied(msg) => msg.clone(),
+        other => serde_json::to_value(other).ok()
+            .and_then(|v| v.get("data").map(|d| d.to_string()))
+            .unwrap_or_default(),
+    }
+}
+
+// Persists the step cursor so a restart can resume from the step about to run
+async fn checkpoint(state: &AppState, history_id: Uuid, step: usize, logs: &[String], outputs: &HashMap<String, String>, secret_values: &[String]) {
+    let logs_json = serde_json::to_string(logs).unwrap_or("[]".to_string());
+    // Without a masked copy nothing is stored in the clear; the encrypted copy still allows resuming
+    let masked = secrets::mask_value(outputs, secret_values).unwrap_or_default();
+    let outputs_json = serde_json::to_string(&masked).unwrap_or("{}".to_string());
+    // A resumed run needs the real values; they are only kept, encrypted, when masking changed them
+    let resume_outputs = if &masked != outputs {
+        match serde_json::to_string(outputs).map_err(anyhow::Error::from).and_then(|json| state.secret_cipher.encrypt(&json)) {
+            Ok(encrypted) => Some(encrypted),
+            Err(e) => {
+                error!("Failed to encrypt outputs of execution {}: {}", history_id, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if let Err(e) = sqlx::query("UPDATE execution_history SET current_step = ?, logs = ?, outputs = ?, resume_outputs = ? WHERE id = ?")
+        .bind(step as i64)
+        .bind(logs_json)
+        .bind(outputs_json)
+        .bind(resume_outputs)
+        .bind(history_id.to_string())
+        .execute(&state.db).await {
+        error!("Failed to checkpoint execution {}: {}", history_id, e);
+    }
+}
+
+// Outcome of a finished step, stored in execution_steps and referenced by later `when` conditions
+struct StepRecord {
+    index: usize,
+    step_type: String,
+    status: StepStatus,
+    attempts: u32,
+    started_at: chrono::DateTime<chrono::Utc>,
+    exit_code: Option<i32>,
+    stdout: String,
+    stderr: String,
+    error: Option<String>,
+}
+
+impl StepRecord {
+    fn new(index: usize, step: &ScriptStep, started_at: chrono::DateTime<chrono::Utc>) -> Self {
+        // The serde tag, e.g. "Shell" or "UploadDir"
+        let step_type = serde_json::to_value(step).ok()
+            .and_then(|v| v.get("type").and_then(|t| t.as_str()).map(str::to_string))
+            .unwrap_or_default();
+        Self {
+            index,
+            step_type,
+            status: StepStatus::Failed,
+            attempts: 0,
+            started_at,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+        }
+    }
+
+    fn failed(index: usize, step: &ScriptStep, started_at: chrono::DateTime<chrono::Utc>, error: String) -> Self {
+        Self { error: Some(error), ..Self::new(index, step, started_at) }
+    }
+}
+
+async fn finish_step(state: &AppState, history_id: Uuid, secret_values: &[String], records: &mut Vec<StepRecord>, record: StepRecord) {
+    let status = serde_json::to_value(record.status).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+    if let Err(e) = sqlx::query(
+        "INSERT OR REPLACE INTO execution_steps (execution_id, step_index, step_type, status, attempts, started_at, completed_at, exit_code, stdout, stderr, error) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+        .bind(history_id.to_string())
+        .bind(record.index as i64)
+        .bind(&record.step_type)
+        .bind(status)
+        .bind(record.attempts as i64)
+        .bind(record.started_at)
+        .bind(chrono::Utc::now())
+        .bind(record.exit_code)
+        .bind(secrets::mask(&record.stdout, secret_values))
+        .bind(secrets::mask(&record.stderr, secret_values))
+        .bind(record.error.as_deref().map(|e| secrets::mask(e, secret_values)))
+        .execute(&state.db).await {
+        error!("Failed to record step {} of execution {}: {}", record.index, history_id, e);
+    }
+    records.push(record);
+}
+
+/// Returns `Ok(None)` when the condition holds, otherwise the reason the step is skipped.
+fn evaluate_condition(cond: &StepCondition, client_os: &str, index: usize, records: &[StepRecord]) -> Result<Option<String>, String> {
+    if let Some(os_list) = &cond.os {
+        if !os_list.iter().any(|os| os.eq_ignore_ascii_case(client_os)) {
+            return Ok(Some(format!("client OS '{}' not in {:?}", client_os, os_list)));
+        }
+    }
+
+    if cond.status.is_none() && cond.exit_code.is_none() && cond.output_contains.is_none() && cond.output_matches.is_none() {
+        return Ok(None);
+    }
+
+    let step_no = cond.step.unwrap_or(index);
+    if step_no == 0 || step_no > index {
+        return Err(format!("condition refers to step {}, only earlier steps can be referenced", step_no));
+    }
+    let record = &records[step_no - 1];
+
+    if let Some(status) = cond.status {
+        if record.status != status {
+            return Ok(Some(format!("step {} status is {:?}, expected {:?}", step_no, record.status, status)));
+        }
+    }
+    if let Some(codes) = &cond.exit_code {
+        if !record.exit_code.is_some_and(|c| codes.contains(&c)) {
+            return Ok(Some(format!("step {} exit code {:?} not in {:?}", step_no, record.exit_code, codes)));
+        }
+    }
+    if let Some(needle) = &cond.output_contains {
+        if !record.stdout.contains(needle.as_str()) {
+            return Ok(Some(format!("step {} output does not contain '{}'", step_no, needle)));
+        }
+    }
+    if let Some(pattern) = &cond.output_matches {
+        let re = regex::Regex::new(pattern).map_err(|e| format!("invalid output_matches pattern: {}", e))?;
+        if !re.is_match(&record.stdout) {
+            return Ok(Some(format!("step {} output does not match /{}/", step_no, pattern)));
+        }
+    }
+    Ok(None)
+}
+
+// Outputs of a skipped or failed step resolve to "" so later references still render
+fn set_empty_outputs(step: &StepDefinition, vars: &mut HashMap<String, String>, outputs: &mut HashMap<String, String>) {
+    for output in &step.outputs {
+        vars.insert(format!("outputs.{}", output.name), String::new());
+        outputs.insert(output.name.clone(), String::new());
+    }
+}
+
+fn describe_options(options: &StepOptions) -> String {
+    let mut parts = Vec::new();
+    if let Some(retries) = options.retries.filter(|r| *r > 0) {
+        parts.push(format!("retries={}", retries));
+    }
+    if options.continue_on_error {
+        parts.push("continue_on_error".to_string());
+    }
+    if let Some(codes) = &options.expected_exit_codes {
+        parts.push(format!("expected_exit_codes={:?}", codes));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", parts.join(", "))
+    }
+}
+
+// Sends one command to the client and waits for its result.
+// Err(None) means the client is gone, Err(Some(msg)) is a failed attempt that may be retried.
+async fn dispatch_command(state: &AppState, client_id: Uuid, cmd: CommandPayload, secret_values: &[String]) -> Result<CommandResult, Option<String>> {
+    let tx = match state.clients.get(&client_id) {
+        Some(client) => client.tx.clone(),
+        None => return Err(None),
+    };
+
+    let cmd_id = Uuid::new_v4();
+    let (wait_tx, wait_rx) = tokio::sync::oneshot::channel();
+    state.waiters.insert(cmd_id, wait_tx);
+    if !secret_values.is_empty() {
+        state.command_secrets.insert(cmd_id, (secret_values.to_vec(), None));
+    }
+
+    if let Err(e) = tx.send(Message::Command { id: cmd_id, cmd }).await {
+        state.waiters.remove(&cmd_id);
+        state.command_secrets.remove(&cmd_id);
+        return Err(Some(format!("Failed to send command: {}", e)));
+    }
+
+    match tokio::time::timeout(tokio::time::Duration::from_secs(300), wait_rx).await {
+        Ok(Ok(r)) => Ok(r),
+        Ok(Err(_)) => Err(Some("Timed out or failed".to_string())),
+        Err(_) => {
+            state.waiters.remove(&cmd_id);
+            // A late response is still masked before it is stored
+            if let Some(mut entry) = state.command_secrets.get_mut(&cmd_id) {
+                entry.1 = Some(std::time::Instant::now());
+            }
+            Err(Some("Timed out or failed".to_string()))
+        }
+    }
+}
+
+/// Where an interrupted execution picks up again: the step cursor and everything recorded before it.
+pub(crate) struct ResumePoint {
+    step: usize,
+    logs: Vec<String>,
+    outputs: HashMap<String, String>,
+    records: Vec<StepRecord>,
+}
+
+/// Loads the persisted cursor of an execution. The step that was in flight when the
+/// server stopped is run again.
+pub(crate) async fn load_resume_point(state: &AppState, history_id: Uuid) -> Result<ResumePoint, String> {
+    let row = sqlx::query("SELECT current_step, logs, outputs, resume_outputs FROM execution_history WHERE id = ?")
+        .bind(history_id.to_string())
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| e.to_string())?;
+    let step = row.get::<Option<i64>, _>("current_step").unwrap_or(0).max(0) as usize;
+    let logs = row.get::<Option<String>, _>("logs").as_deref().and_then(|l| serde_json::from_str(l).ok()).unwrap_or_default();
+    // Outputs that contained a secret are stored masked; the encrypted copy has the real values
+    let outputs = match row.get::<Option<String>, _>("resume_outputs") {
+        Some(encrypted) => {
+            let json = state.secret_cipher.decrypt(&encrypted).map_err(|e| format!("outputs unreadable: {}", e))?;
+            serde_json::from_str(&json).map_err(|e| format!("outputs unreadable: {}", e))?
+        }
+        None => row.get::<Option<String>, _>("outputs").as_deref().and_then(|o| serde_json::from_str(o).ok()).unwrap_or_default(),
+    };
+
+    let step_rows = sqlx::query("SELECT step_index, step_type, status, attempts, started_at, exit_code, stdout, stderr, error FROM execution_steps WHERE execution_id = ? AND step_index <= ? ORDER BY step_index")
+        .bind(history_id.to_string())
+        .bind(step as i64)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| e.to_string())?;
+    let records = step_rows.iter().map(|r| StepRecord {
+        index: r.get::<i64, _>("step_index") as usize,
+        step_type: r.get("step_type"),
+        status: serde_json::from_value(serde_json::Value::String(r.get("status"))).unwrap_or(StepStatus::Failed),
+        attempts: r.get::<i64, _>("attempts") as u32,
+        started_at: r.get::<Option<chrono::DateTime<chrono::Utc>>, _>("started_at").unwrap_or_else(chrono::Utc::now),
+        exit_code: r.get::<Option<i64>, _>("exit_code").map(|c| c as i32),
+        stdout: r.get::<Option<String>, _>("stdout").unwrap_or_default(),
+        stderr: r.get::<Option<String>, _>("stderr").unwrap_or_default(),
+        error: r.get("error"),
+    }).collect::<Vec<_>>();
+
+    // Without a record for every earlier step, `when` conditions cannot be evaluated; restart from
+    // the first missing one
+    let step = records.iter().enumerate().take_while(|(i, r)| r.index == i + 1).count().min(step);
+    let records = records.into_iter().take(step).collect();
+    Ok(ResumePoint { step, logs, outputs, records })
+}
+
+pub(crate) async fn run_script_task(
+    state: Arc<AppState>,
+    client_id: Uuid,
+    script: ScriptGroup,
+    params: HashMap<String, String>,
+    history_id: Uuid,
+    server_host: String,
+    resume: Option<ResumePoint>,
+) -> bool {
+    match &resume {
+        Some(point) => info!("Resuming script {} on client {} at step {}", script.name, client_id, point.step + 1),
+        None => info!("Starting script {} on client {}", script.name, client_id),
+    }
+    
+    // Get client hostname for progress
+    let (client_hostname, client_os) = if let Some(c) = state.clients.get(&client_id) {
+        (c.hostname.clone(), c.os.clone())
+    } else {
+        ("Unknown".to_string(), String::new())
+    };
+
+    // Variables available as {{name}} in step fields: script parameters plus client built-ins
+    let mut vars = params;
+    vars.insert("client.id".to_string(), client_id.to_string());
+    if let Some(c) = state.clients.get(&client_id) {
+        vars.insert("client.hostname".to_string(), c.hostname.clone());
+        vars.insert("client.alias".to_string(), c.alias.clone().unwrap_or_default());
+        vars.insert("client.os".to_string(), c.os.clone());
+        vars.insert("client.ip".to_string(), c.ip.clone());
+    }
+
+    let ResumePoint { step: start_step, mut logs, mut outputs, mut records } = resume.unwrap_or(ResumePoint {
+        step: 0,
+        logs: Vec::new(),
+        outputs: HashMap::new(),
+        records: Vec::new(),
+    });
+    for (name, value) in &outputs {
+        vars.insert(format!("outputs.{}", name), value.clone());
+    }
+    let mut success = true;
+    // Plaintext of every secret substituted so far, masked in logs and results
+    let mut secret_values: Vec<String> = Vec::new();
+    let total_steps = script.steps.len();
+    // Scripts without parameters may contain literal `{{word}}` text (Go, Jinja, Helm templates)
+    let strict = !script.params.is_empty();
+
+    // Initialize Active Execution
+    state.active_executions.insert(history_id, ExecutionProgress {
+        execution_id: history_id,
+        script_name: script.name.clone(),
+        client_hostname: client_hostname.clone(),
+        status: "running".to_string(),
+        logs: logs.clone(),
+        current_step: start_step,
+        total_steps,
+    });
+    events::execution_updated(&state, history_id, None);
+    if start_step > 0 {
+        // Secrets of the steps that already ran still have to be masked in what follows
+        match secrets::load(&state.db, &state.secret_cipher, &script.steps[..start_step.min(total_steps)].to_vec()).await {
+            Ok(plaintexts) => secret_values.extend(plaintexts.into_values().filter(|v| !v.is_empty())),
+            Err(e) => warn!("Failed to load secrets of completed steps of execution {}: {}", history_id, e),
+        }
+        let log_resume = format!("Resumed at step {} after server restart", start_step + 1);
+        push_log(&state, history_id, &mut logs, &secret_values, log_resume);
+    }
+
+    for (i, template_step) in script.steps.iter().enumerate().skip(start_step) {
+        // Update Progress
+        if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
+            progress.current_step = i + 1;
+        }
+        events::execution_updated(&state, history_id, None);
+        checkpoint(&state, history_id, i, &logs, &outputs, &secret_values).await;
+        let step_started = chrono::Utc::now();
+
+        // Secrets are loaded from the stored step and substituted in the same pass as the
+        // variables, so a parameter or captured output can never expand to a secret. Plaintext
+        // only exists in the dispatched copy and never reaches the database.
+        let rendered = match secrets::load(&state.db, &state.secret_cipher, template_step).await {
+            Ok(plaintexts) => template::render(template_step, &vars, None, strict)
+                .and_then(|shown| Ok((shown, template::render(template_step, &vars, Some(&plaintexts), strict)?, plaintexts))),
+            Err(e) => Err(e),
+        };
+        let (rendered_step, step_def) = match rendered {
+            Ok((shown, step, plaintexts)) => {
+                for value in plaintexts.into_values().filter(|v| !v.is_empty()) {
+                    if !secret_values.contains(&value) {
+                        secret_values.push(value);
+                    }
+                }
+                (shown, step)
+            }
+            Err(e) => {
+                let log_err = format!("Step {}: Setup failed: {}", i + 1, e);
+                push_log(&state, history_id, &mut logs, &secret_values, log_err);
+                finish_step(&state, history_id, &secret_values, &mut records, StepRecord::failed(i + 1, &template_step.step, step_started, e)).await;
+                success = false;
+                break;
             }
         };
-        
-        let step_desc = match step {
+        let step = &step_def.step;
+        let options = &step_def.options;
+
+        let step_desc = match &rendered_step.step {
             ScriptStep::Shell { cmd, args } => format!("Shell: {} {}", cmd, args.join(" ")),
             ScriptStep::Upload { local_path, remote_path } => format!("Upload: {} -> {}", local_path, remote_path),
             ScriptStep::Download { remote_path, .. } => format!("Download: {}", remote_path),
@@ -581,209 +1668,575 @@ async fn run_script_task(state: Arc<AppState>, client_id: Uuid, script: ScriptGr
             ScriptStep::Copy { src_path, dest_path } => format!("Copy: {} -> {}", src_path, dest_path),
             ScriptStep::Move { src_path, dest_path } => format!("Move: {} -> {}", src_path, dest_path),
             ScriptStep::Delete { path } => format!("Delete: {}", path),
+            ScriptStep::Service { name, action } => format!("Service: {} {}", action, name),
+            ScriptStep::FindFiles(query) => format!("Find: {} in {}", query.pattern.as_deref().unwrap_or("*"), query.path),
+            ScriptStep::SearchContent(query) => format!("Search: /{}/ in {}", query.pattern, query.path),
         };
-        
-        let log_start = format!("Step {}: Started - {}", i + 1, step_desc);
-        logs.push(log_start.clone());
-        if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-            progress.logs.push(log_start);
-        }
 
-        if let Err(e) = cmd_payload_result {
-             let log_err = format!("Step {}: Setup failed: {}", i + 1, e);
-             logs.push(log_err.clone());
-             if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                 progress.logs.push(log_err);
-             }
-             success = false;
-             break;
+        if let Some(cond) = &options.when {
+            match evaluate_condition(cond, &client_os, i, &records) {
+                Ok(None) => {}
+                Ok(Some(reason)) => {
+                    let log_skip = format!("Step {}: Skipped - {} (condition not met: {})", i + 1, step_desc, reason);
+                    push_log(&state, history_id, &mut logs, &secret_values, log_skip);
+                    let record = StepRecord { status: StepStatus::Skipped, ..StepRecord::new(i + 1, step, step_started) };
+                    finish_step(&state, history_id, &secret_values, &mut records, record).await;
+                    set_empty_outputs(&step_def, &mut vars, &mut outputs);
+                    continue;
+                }
+                Err(e) => {
+                    let log_err = format!("Step {}: Setup failed: {}", i + 1, e);
+                    push_log(&state, history_id, &mut logs, &secret_values, log_err);
+                    finish_step(&state, history_id, &secret_values, &mut records, StepRecord::failed(i + 1, step, step_started, e)).await;
+                    success = false;
+                    break;
+                }
+            }
         }
-        let cmd_payload = cmd_payload_result.unwrap();
         
-        // Send command
-        if let Some(client) = state.clients.get(&client_id) {
-            let cmd_id = Uuid::new_v4();
-            let (wait_tx, wait_rx) = tokio::sync::oneshot::channel();
-            state.waiters.insert(cmd_id, wait_tx);
-            let msg = Message::Command {
-                id: cmd_id,
-                cmd: cmd_payload,
-            };
-            
-            if let Err(e) = client.tx.send(msg).await {
-                state.waiters.remove(&cmd_id);
-                let log_err = format!("Step {}: Failed to send command: {}", i + 1, e);
-                logs.push(log_err.clone());
-                if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                    progress.logs.push(log_err);
+        let base_url = get_download_base_url(&state, Some(client_id), Some(&server_host));
+
+        let cmd_payload_result = match step {
+            ScriptStep::Shell { cmd, args } => Ok(CommandPayload::ShellExec { cmd: cmd.clone(), args: args.clone() }),
+            ScriptStep::Upload { local_path, remote_path } => {
+                match storage::resolve_in(storage::STAGING_DIR, local_path) {
+                    Ok(path) if path.is_file() => {
+                        let download_url = format!("{}/api/files/download/staging/{}", base_url, storage::url_path(local_path));
+                        Ok(CommandPayload::DownloadFile { url: download_url, dest_path: remote_path.clone() })
+                    }
+                    Ok(_) => Err(format!("Staging file not found: {}", local_path)),
+                    Err(e) => Err(e.to_string()),
+                }
+            },
+            ScriptStep::Download { remote_path, browser_download } => {
+                let upload_id = Uuid::new_v4();
+                let upload_url = format!("{}/api/files/client-upload/{}", base_url, upload_id);
+                
+                if browser_download.unwrap_or(false) {
+                    let file_name = std::path::Path::new(remote_path).file_name().unwrap_or_default().to_string_lossy();
+                    let download_link = format!("{}/api/files/download/client_data/{}/{}", base_url, upload_id, storage::url_path(&file_name));
+                    let log_msg = format!("BROWSER_DOWNLOAD: {}", download_link);
+                    push_log(&state, history_id, &mut logs, &secret_values, log_msg);
+                }
+                
+                Ok(CommandPayload::UploadFile { src_path: remote_path.clone(), upload_url })
+            },
+            ScriptStep::UploadDir { local_path, remote_path } => {
+                // Zip the directory first
+                let zip_name = format!("{}.zip", local_path.trim_end_matches(['/', '\\']));
+                match (storage::resolve_in(storage::STAGING_DIR, local_path), storage::resolve_in(storage::STAGING_DIR, &zip_name)) {
+                    (Ok(src_dir), Ok(dst_zip)) => {
+                        match zip_directory(&src_dir.to_string_lossy(), &dst_zip.to_string_lossy()) {
+                            Ok(_) => {
+                                let download_url = format!("{}/api/files/download/staging/{}", base_url, storage::url_path(&zip_name));
+                                Ok(CommandPayload::DownloadAndUnzip { url: download_url, dest_path: remote_path.clone() })
+                            },
+                            Err(e) => Err(format!("Failed to zip directory: {}", e))
+                        }
+                    }
+                    (Err(e), _) | (_, Err(e)) => Err(e.to_string()),
+                }
+            },
+            ScriptStep::DownloadDir { remote_path, browser_download } => {
+                let upload_id = Uuid::new_v4();
+                // Client will upload a zip file, server receives it as generic file upload
+                let upload_url = format!("{}/api/files/client-upload/{}", base_url, upload_id);
+                
+                if browser_download.unwrap_or(false) {
+                    let file_name = format!("{}.zip", std::path::Path::new(remote_path).file_name().unwrap_or_default().to_string_lossy());
+                    let download_link = format!("{}/api/files/download/client_data/{}/{}", base_url, upload_id, storage::url_path(&file_name));
+                    let log_msg = format!("BROWSER_DOWNLOAD: {}", download_link);
+                    push_log(&state, history_id, &mut logs, &secret_values, log_msg);
+                }
+
+                Ok(CommandPayload::ZipAndUpload { src_path: remote_path.clone(), upload_url })
+            },
+            ScriptStep::Copy { src_path, dest_path } => {
+                Ok(CommandPayload::CopyFile { src_path: src_path.clone(), dest_path: dest_path.clone() })
+            },
+            ScriptStep::Move { src_path, dest_path } => {
+                Ok(CommandPayload::MoveFile { src_path: src_path.clone(), dest_path: dest_path.clone() })
+            },
+            ScriptStep::Delete { path } => {
+                Ok(CommandPayload::DeleteFile { path: path.clone() })
+            }
+            ScriptStep::Service { name, action } => {
+                Ok(CommandPayload::ControlService { name: name.clone(), action: *action })
+            }
+            ScriptStep::FindFiles(query) => Ok(CommandPayload::FindFiles(query.clone())),
+            ScriptStep::SearchContent(query) => Ok(CommandPayload::SearchContent(query.clone())),
+        };
+        
+        let log_start = format!("Step {}: Started - {}{}", i + 1, step_desc, describe_options(options));
+        push_log(&state, history_id, &mut logs, &secret_values, log_start);
+
+        let cmd_payload = match cmd_payload_result {
+            Ok(p) => p,
+            Err(e) => {
+                let log_err = format!("Step {}: Setup failed: {}", i + 1, e);
+                push_log(&state, history_id, &mut logs, &secret_values, log_err);
+                finish_step(&state, history_id, &secret_values, &mut records, StepRecord::failed(i + 1, step, step_started, e)).await;
+                set_empty_outputs(&step_def, &mut vars, &mut outputs);
+                if options.continue_on_error {
+                    let log_cont = format!("Step {}: Failed, continuing (continue_on_error)", i + 1);
+                    push_log(&state, history_id, &mut logs, &secret_values, log_cont);
+                    continue;
                 }
                 success = false;
                 break;
             }
-            
-            // Wait for result
-            let mut step_success = false;
-            let result = match tokio::time::timeout(tokio::time::Duration::from_secs(300), wait_rx).await {
-                Ok(Ok(r)) => Some(r),
-                Ok(Err(_)) => None,
-                Err(_) => {
-                    state.waiters.remove(&cmd_id);
-                    None
+        };
+
+        let expected_codes = options.expected_exit_codes.clone().unwrap_or_else(|| vec![0]);
+        let retries = options.retries.unwrap_or(0);
+        let mut record = StepRecord::new(i + 1, step, step_started);
+        let mut captured = Vec::new();
+        let mut disconnected = false;
+
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                // Exponential backoff, capped at 10 minutes
+                let delay = options.retry_delay_sec.unwrap_or(5).saturating_mul(1 << (attempt - 1).min(16)).min(600);
+                let log_retry = format!("Step {}: Retrying in {}s (attempt {}/{})", i + 1, delay, attempt + 1, retries + 1);
+                push_log(&state, history_id, &mut logs, &secret_values, log_retry);
+                tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
+            }
+            record.attempts = attempt + 1;
+            record.exit_code = None;
+            record.stdout.clear();
+            record.stderr.clear();
+            record.error = None;
+
+            let mut retryable = true;
+            let log_res = match dispatch_command(&state, client_id, cmd_payload.clone(), &secret_values).await {
+                Err(None) => {
+                    disconnected = true;
+                    record.error = Some("Client disconnected".to_string());
+                    "Client disconnected".to_string()
+                }
+                Err(Some(e)) => {
+                    let msg = format!("Step {}: {}", i + 1, e);
+                    record.error = Some(e);
+                    msg
+                }
+                Ok(CommandResult::Error(e)) => {
+                    let msg = format!("Step {}: Failed: {}", i + 1, e);
+                    record.error = Some(e);
+                    msg
+                }
+                Ok(CommandResult::PolicyDenied(reason)) => {
+                    // The client policy answers the same way every time
+                    retryable = false;
+                    let msg = format!("Step {}: Denied by client policy: {}", i + 1, reason);
+                    record.error = Some(format!("Denied by client policy: {}", reason));
+                    msg
+                }
+                Ok(CommandResult::ShellOutput { stdout, stderr, exit_code, .. }) => {
+                    record.exit_code = Some(exit_code);
+                    let msg = if !expected_codes.contains(&exit_code) {
+                        record.error = Some(format!("Unexpected exit code {}", exit_code));
+                        format!("Step {}: Shell command failed (Exit Code: {}, expected {:?}). Stderr: {}", i + 1, exit_code, expected_codes, stderr)
+                    } else if exit_code != 0 {
+                        record.status = StepStatus::Succeeded;
+                        format!("Step {}: Completed (Exit Code: {} accepted). Output: {}", i + 1, exit_code, stdout)
+                    } else {
+                        record.status = StepStatus::Succeeded;
+                        format!("Step {}: Completed. Output: {}", i + 1, stdout)
+                    };
+                    record.stdout = stdout;
+                    record.stderr = stderr;
+                    msg
+                }
+                Ok(res) => {
+                    record.status = StepStatus::Succeeded;
+                    record.stdout = result_text(&res);
+                    format!("Step {}: Completed. Result: {:?}", i + 1, res)
                 }
             };
-
-            if let Some(result) = result {
-                let log_res = match result {
-                    CommandResult::Error(e) => {
-                        format!("Step {}: Failed: {}", i + 1, e)
-                    }
-                    CommandResult::ShellOutput { stdout, stderr, exit_code, .. } => {
-                        if exit_code != 0 {
-                            format!("Step {}: Shell command failed (Exit Code: {}). Stderr: {}", i + 1, exit_code, stderr)
-                        } else {
-                            step_success = true;
-                            format!("Step {}: Completed. Output: {}", i + 1, stdout)
+            push_log(&state, history_id, &mut logs, &secret_values, log_res);
+
+            if record.status == StepStatus::Succeeded {
+                captured.clear();
+                for output in &step_def.outputs {
+                    match template::capture_output(output, &record.stdout) {
+                        Ok(value) => {
+                            let log_out = format!("Step {}: Output {} = {}", i + 1, output.name, value);
+                            push_log(&state, history_id, &mut logs, &secret_values, log_out);
+                            captured.push((output.name.clone(), value));
+                        }
+                        Err(e) => {
+                            let log_err = format!("Step {}: Output {} not captured: {}", i + 1, output.name, e);
+                            push_log(&state, history_id, &mut logs, &secret_values, log_err);
+                            record.status = StepStatus::Failed;
+                            record.error = Some(format!("Output {} not captured: {}", output.name, e));
+                            break;
                         }
                     }
-                    res => {
-                        step_success = true;
-                        format!("Step {}: Completed. Result: {:?}", i + 1, res)
-                    }
-                };
-
-                logs.push(log_res.clone());
-                if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                    progress.logs.push(log_res);
                 }
             }
-            
-            if !step_success {
-                let log_timeout = format!("Step {}: Timed out or failed", i + 1);
-                logs.push(log_timeout.clone());
-                if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                    progress.logs.push(log_timeout);
-                }
-                success = false;
+
+            if record.status == StepStatus::Succeeded || disconnected || !retryable {
                 break;
             }
-            
+        }
+
+        let failed = record.status == StepStatus::Failed;
+        finish_step(&state, history_id, &secret_values, &mut records, record).await;
+        if failed {
+            set_empty_outputs(&step_def, &mut vars, &mut outputs);
         } else {
-            let log_disc = "Client disconnected".to_string();
-            logs.push(log_disc.clone());
-            if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-                progress.logs.push(log_disc);
+            for (name, value) in captured {
+                vars.insert(format!("outputs.{}", name), value.clone());
+                outputs.insert(name, value);
             }
+        }
+
+        if disconnected {
             success = false;
             break;
         }
+        if failed {
+            if options.continue_on_error {
+                let log_cont = format!("Step {}: Failed, continuing (continue_on_error)", i + 1);
+                push_log(&state, history_id, &mut logs, &secret_values, log_cont);
+            } else {
+                success = false;
+                break;
+            }
+        }
+    }
+    
+    let status = if success { "completed" } else { "failed" };
+    
+    // Update Active Execution Status
+    if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
+        progress.status = status.to_string();
+    }
+    events::execution_updated(&state, history_id, None);
+    
+    let logs_json = serde_json::to_string(&logs).unwrap_or("[]".to_string());
+    let masked_outputs = secrets::mask_value(&outputs, &secret_values).unwrap_or_default();
+    let outputs_json = serde_json::to_string(&masked_outputs).unwrap_or("{}".to_string());
+    
+    // Update history
+    let _ = sqlx::query("UPDATE execution_history SET status = ?, completed_at = CURRENT_TIMESTAMP, logs = ?, outputs = ?, resume_outputs = NULL, remaining_runs = NULL WHERE id = ?")
+        .bind(status)
+        .bind(logs_json)
+        .bind(outputs_json)
+        .bind(history_id.to_string())
+        .execute(&state.db).await;
+    
+    info!("Script {} finished on client {} with status {}", script.name, client_id, status);
+
+    let failed_step = records.iter().rev().find(|r| r.status == StepStatus::Failed).map(|r| serde_json::json!({
+        "index": r.index,
+        "step_type": r.step_type,
+        "exit_code": r.exit_code,
+        "error": r.error.as_deref().map(|e| secrets::mask(e, &secret_values)),
+    }));
+    webhooks::emit(&state, if success { webhooks::EXECUTION_COMPLETED } else { webhooks::EXECUTION_FAILED }, serde_json::json!({
+        "execution_id": history_id,
+        "script_id": script.id,
+        "script_name": script.name,
+        "client_id": client_id,
+        "client_hostname": client_hostname,
+        "status": status,
+        "outputs": masked_outputs,
+        "failed_step": failed_step,
+    }));
+    let failure = records.iter().rev().find(|r| r.status == StepStatus::Failed).map(|r| {
+        let detail = r.error.as_deref().map(|e| secrets::mask(e, &secret_values))
+            .or(r.exit_code.map(|c| format!("exit code {}", c)))
+            .unwrap_or_default();
+        format!("Step {} ({}) failed: {}", r.index, r.step_type, detail)
+    });
+    notifications::execution_finished(&state, history_id, &script, client_id, client_hostname.clone(), success, failure);
+    
+    // Keep in active_executions for a bit? Or remove?
+    // If we remove immediately, the frontend might miss the final status if it's polling.
+    // Let's remove it after a short delay (e.g. 5 seconds) to allow the frontend to catch the completion.
+    tokio::spawn(async move {
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        state.active_executions.remove(&history_id);
+    });
+    success
+}
+
+// API: Get Execution History
+#[derive(serde::Serialize)]
+pub struct ExecutionHistoryItem {
+    pub id: Uuid,
+    pub script_id: Uuid,
+    pub client_id: Uuid,
+    pub script_name: String,
+    pub client_hostname: String,
+    pub client_alias: Option<String>,
+    pub status: String,
+    pub started_at: String,
+    pub completed_at: Option<String>,
+    pub logs: Vec<String>,
+    pub params: HashMap<String, String>,
+    pub outputs: HashMap<String, String>,
+    pub schedule_id: Option<Uuid>,
+    pub steps: Vec<ExecutionStepItem>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PaginatedHistory {
+    pub history: Vec<ExecutionHistoryItem>,
+    pub total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExecutionStepItem {
+    pub step_index: i64,
+    pub step_type: String,
+    pub status: String,
+    pub attempts: i64,
+    pub started_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub exit_code: Option<i64>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct HistoryParams {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub status: Option<String>,
+    pub script_id: Option<Uuid>,
+    pub client_id: Option<Uuid>,
+    pub schedule_id: Option<Uuid>,
+    // Executions having at least one step matching all of these
+    pub step_status: Option<String>,
+    pub step_type: Option<String>,
+    pub exit_code: Option<i64>,
+}
+
+pub async fn get_script_history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+) -> Json<PaginatedHistory> {
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(50).max(1);
+    let offset = (page - 1) * limit;
+
+    // Build WHERE clause
+    let mut where_clause = "1=1".to_string();
+    let mut binds: Vec<String> = Vec::new();
+    if let Some(status) = &params.status {
+        where_clause.push_str(" AND h.status = ?");
+        binds.push(status.clone());
+    }
+    if let Some(script_id) = params.script_id {
+        where_clause.push_str(" AND h.script_id = ?");
+        binds.push(script_id.to_string());
+    }
+    if let Some(client_id) = params.client_id {
+        where_clause.push_str(" AND h.client_id = ?");
+        binds.push(client_id.to_string());
+    }
+    if let Some(schedule_id) = params.schedule_id {
+        where_clause.push_str(" AND h.schedule_id = ?");
+        binds.push(schedule_id.to_string());
+    }
+    if params.step_status.is_some() || params.step_type.is_some() || params.exit_code.is_some() {
+        where_clause.push_str(" AND EXISTS (SELECT 1 FROM execution_steps st WHERE st.execution_id = h.id");
+        if let Some(step_status) = &params.step_status {
+            where_clause.push_str(" AND st.status = ?");
+            binds.push(step_status.clone());
+        }
+        if let Some(step_type) = &params.step_type {
+            where_clause.push_str(" AND st.step_type = ?");
+            binds.push(step_type.clone());
+        }
+        if let Some(exit_code) = params.exit_code {
+            where_clause.push_str(" AND st.exit_code = CAST(? AS INTEGER)");
+            binds.push(exit_code.to_string());
+        }
+        where_clause.push(')');
+    }
+
+    let count_query = format!(
+        "SELECT COUNT(*) FROM execution_history h JOIN scripts s ON h.script_id = s.id WHERE {}",
+        where_clause
+    );
+    let mut count = sqlx::query_scalar::<_, i64>(&count_query);
+    for b in &binds {
+        count = count.bind(b);
+    }
+    let total = count.fetch_one(&state.db).await.unwrap_or(0);
+
+    let query = format!(
+        r#"
+        SELECT h.id, h.script_id, h.client_id, s.name as script_name, c.hostname as client_hostname, c.alias as client_alias, h.status, CAST(h.started_at AS TEXT) as started_at, CAST(h.completed_at AS TEXT) as completed_at, h.logs, h.params, h.outputs, h.schedule_id
+        FROM execution_history h
+        JOIN scripts s ON h.script_id = s.id
+        LEFT JOIN clients c ON h.client_id = c.id
+        WHERE {}
+        ORDER BY h.started_at DESC
+        LIMIT ? OFFSET ?
+        "#,
+        where_clause
+    );
+    let mut rows_query = sqlx::query(&query);
+    for b in &binds {
+        rows_query = rows_query.bind(b);
+    }
+    let rows = rows_query
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+
+    let mut history: Vec<ExecutionHistoryItem> = rows.into_iter().map(|r| {
+        let logs: Vec<String> = r.get::<Option<String>, _>("logs").as_deref().and_then(|l| serde_json::from_str(l).ok()).unwrap_or_default();
+        ExecutionHistoryItem {
+            id: Uuid::parse_str(r.get::<Option<String>, _>("id").as_deref().unwrap_or("")).unwrap_or_default(),
+            script_id: Uuid::parse_str(r.get::<Option<String>, _>("script_id").as_deref().unwrap_or("")).unwrap_or_default(),
+            client_id: Uuid::parse_str(r.get::<Option<String>, _>("client_id").as_deref().unwrap_or("")).unwrap_or_default(),
+            script_name: r.get("script_name"),
+            client_hostname: r.get::<Option<String>, _>("client_hostname").unwrap_or("Unknown".to_string()),
+            client_alias: r.get("client_alias"),
+            status: r.get("status"),
+            started_at: r.get::<Option<String>, _>("started_at").unwrap_or_default(),
+            completed_at: r.get("completed_at"),
+            logs,
+            params: r.get::<Option<String>, _>("params").as_deref().and_then(|p| serde_json::from_str(p).ok()).unwrap_or_default(),
+            outputs: r.get::<Option<String>, _>("outputs").as_deref().and_then(|o| serde_json::from_str(o).ok()).unwrap_or_default(),
+            schedule_id: r.get::<Option<String>, _>("schedule_id").and_then(|id| Uuid::parse_str(&id).ok()),
+            steps: Vec::new(),
+        }
+    }).collect();
+
+    // Attach step results for the executions on this page
+    if !history.is_empty() {
+        let placeholders = vec!["?"; history.len()].join(", ");
+        let steps_query = format!(
+            r#"
+            SELECT execution_id, step_index, step_type, status, attempts,
+                   strftime('%Y-%m-%dT%H:%M:%SZ', started_at) as started_at,
+                   strftime('%Y-%m-%dT%H:%M:%SZ', completed_at) as completed_at,
+                   CAST((julianday(completed_at) - julianday(started_at)) * 86400000 AS INTEGER) as duration_ms,
+                   exit_code, stdout, stderr, error
+            FROM execution_steps
+            WHERE execution_id IN ({})
+            ORDER BY step_index ASC
+            "#,
+            placeholders
+        );
+        let mut q = sqlx::query(&steps_query);
+        for item in &history {
+            q = q.bind(item.id.to_string());
+        }
+        let step_rows = q.fetch_all(&state.db).await.unwrap_or_default();
+
+        let mut by_execution: HashMap<String, Vec<ExecutionStepItem>> = HashMap::new();
+        for r in step_rows {
+            by_execution.entry(r.get("execution_id")).or_default().push(ExecutionStepItem {
+                step_index: r.get("step_index"),
+                step_type: r.get("step_type"),
+                status: r.get("status"),
+                attempts: r.get("attempts"),
+                started_at: r.get("started_at"),
+                completed_at: r.get("completed_at"),
+                duration_ms: r.get("duration_ms"),
+                exit_code: r.get("exit_code"),
+                stdout: r.get("stdout"),
+                stderr: r.get("stderr"),
+                error: r.get("error"),
+            });
+        }
+        for item in &mut history {
+            item.steps = by_execution.remove(&item.id.to_string()).unwrap_or_default();
+        }
     }
     
-    let status = if success { "completed" } else { "failed" };
-    
-    // Update Active Execution Status
-    if let Some(mut progress) = state.active_executions.get_mut(&history_id) {
-        progress.status = status.to_string();
-    }
-    
-    let logs_json = serde_json::to_string(&logs).unwrap_or("[]".to_string());
-    let history_id_str = history_id.to_string();
-    
-    // Update history
-    let _ = sqlx::query!(
-        "UPDATE execution_history SET status = ?, completed_at = CURRENT_TIMESTAMP, logs = ? WHERE id = ?",
-        status, logs_json, history_id_str
-    ).execute(&state.db).await;
-    
-    info!("Script {} finished on client {} with status {}", script.name, client_id, status);
-    
-    // Keep in active_executions for a bit? Or remove?
-    // If we remove immediately, the frontend might miss the final status if it's polling.
-    // Let's remove it after a short delay (e.g. 5 seconds) to allow the frontend to catch the completion.
-    tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-        state.active_executions.remove(&history_id);
-    });
+    Json(PaginatedHistory { history, total })
 }
 
-// API: Get Execution History
+// API: Client Policy Violations
 #[derive(serde::Serialize)]
-pub struct ExecutionHistoryItem {
+pub struct PolicyViolationItem {
     pub id: Uuid,
-    pub script_id: Uuid,
     pub client_id: Uuid,
-    pub script_name: String,
     pub client_hostname: String,
-    pub client_alias: Option<String>,
-    pub status: String,
-    pub started_at: String,
-    pub completed_at: Option<String>,
-    pub logs: Vec<String>,
+    pub command_id: Uuid,
+    pub reason: String,
+    pub created_at: String,
 }
 
 #[derive(serde::Serialize)]
-pub struct PaginatedHistory {
-    pub history: Vec<ExecutionHistoryItem>,
+pub struct PaginatedPolicyViolations {
+    pub violations: Vec<PolicyViolationItem>,
     pub total: i64,
 }
 
 #[derive(serde::Deserialize)]
-pub struct HistoryParams {
+pub struct PolicyViolationParams {
+    pub client_id: Option<Uuid>,
     pub page: Option<i64>,
     pub limit: Option<i64>,
 }
 
-pub async fn get_script_history(
+pub async fn list_policy_violations(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<HistoryParams>,
-) -> Json<PaginatedHistory> {
+    Query(params): Query<PolicyViolationParams>,
+) -> Json<PaginatedPolicyViolations> {
     let page = params.page.unwrap_or(1).max(1);
     let limit = params.limit.unwrap_or(50).max(1);
     let offset = (page - 1) * limit;
+    let client_filter = params.client_id.map(|id| id.to_string());
 
-    let total = sqlx::query("SELECT COUNT(*) as count FROM execution_history")
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM policy_violations WHERE (? IS NULL OR client_id = ?)")
+        .bind(&client_filter)
+        .bind(&client_filter)
         .fetch_one(&state.db)
         .await
-        .map(|r| r.try_get::<i64, _>("count").unwrap_or(0))
         .unwrap_or(0);
 
     let rows = sqlx::query(
         r#"
-        SELECT h.id, h.script_id, h.client_id, s.name as script_name, c.hostname as client_hostname, c.alias as client_alias, h.status, CAST(h.started_at AS TEXT) as started_at, CAST(h.completed_at AS TEXT) as completed_at, h.logs
-        FROM execution_history h
-        JOIN scripts s ON h.script_id = s.id
-        LEFT JOIN clients c ON h.client_id = c.id
-        ORDER BY h.started_at DESC
+        SELECT v.id, v.client_id, c.hostname as client_hostname, v.command_id, v.reason, strftime('%Y-%m-%dT%H:%M:%SZ', v.created_at) as created_at
+        FROM policy_violations v
+        LEFT JOIN clients c ON v.client_id = c.id
+        WHERE (? IS NULL OR v.client_id = ?)
+        ORDER BY v.created_at DESC
         LIMIT ? OFFSET ?
         "#)
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(&state.db)
-    .await
-    .unwrap_or_default();
+        .bind(&client_filter)
+        .bind(&client_filter)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
 
-    let history: Vec<ExecutionHistoryItem> = rows.into_iter().map(|r| {
-        let logs: Vec<String> = r.get::<Option<String>, _>("logs").as_deref().and_then(|l| serde_json::from_str(l).ok()).unwrap_or_default();
-        ExecutionHistoryItem {
-            id: Uuid::parse_str(r.get::<Option<String>, _>("id").as_deref().unwrap_or("")).unwrap_or_default(),
-            script_id: Uuid::parse_str(r.get::<Option<String>, _>("script_id").as_deref().unwrap_or("")).unwrap_or_default(),
-            client_id: Uuid::parse_str(r.get::<Option<String>, _>("client_id").as_deref().unwrap_or("")).unwrap_or_default(),
-            script_name: r.get("script_name"),
+    let violations = rows.into_iter().map(|r| {
+        PolicyViolationItem {
+            id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+            client_id: Uuid::parse_str(&r.get::<String, _>("client_id")).unwrap_or_default(),
             client_hostname: r.get::<Option<String>, _>("client_hostname").unwrap_or("Unknown".to_string()),
-            client_alias: r.get("client_alias"),
-            status: r.get("status"),
-            started_at: r.get::<Option<String>, _>("started_at").unwrap_or_default(),
-            completed_at: r.get("completed_at"),
-            logs,
+            command_id: Uuid::parse_str(&r.get::<String, _>("command_id")).unwrap_or_default(),
+            reason: r.get("reason"),
+            created_at: r.get::<Option<String>, _>("created_at").unwrap_or_default(),
         }
     }).collect();
-    
-    Json(PaginatedHistory { history, total })
+
+    Json(PaginatedPolicyViolations { violations, total })
 }
 
 // API: Clear Execution History
 pub async fn clear_script_history(
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
+    if let Err(e) = sqlx::query("DELETE FROM execution_steps").execute(&state.db).await {
+         return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to clear history: {}", e)).into_response();
+    }
     if let Err(e) = sqlx::query!("DELETE FROM execution_history").execute(&state.db).await {
          return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to clear history: {}", e)).into_response();
     }
@@ -801,21 +2254,24 @@ pub async fn upload_file_admin(
         None => return (StatusCode::BAD_REQUEST, "No file provided").into_response(),
     };
 
-    let file_name = field
-        .file_name()
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "uploaded_file".to_string());
+    let file_name = match storage::sanitize_file_name(field.file_name().unwrap_or("uploaded_file")) {
+        Ok(name) => name,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
     let data = match field.bytes().await {
         Ok(d) => d,
         Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read bytes: {}", e)).into_response(),
     };
 
-    let dir_path = "uploads/staging";
+    let dir_path = storage::STAGING_DIR;
     if let Err(e) = tokio::fs::create_dir_all(dir_path).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create directory: {}", e)).into_response();
     }
 
-    let path = format!("{}/{}", dir_path, file_name);
+    let path = match storage::resolve_in(dir_path, &file_name) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
     if let Err(e) = File::create(&path).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create file: {}", e)).into_response();
     }
@@ -825,9 +2281,42 @@ pub async fn upload_file_admin(
 
     let host_header = headers.get("host").and_then(|h| h.to_str().ok());
     let base_url = get_download_base_url(&state, None, host_header);
-    let url = format!("{}/api/files/download/staging/{}", base_url, file_name);
+    let url = format!("{}/api/files/download/staging/{}", base_url, storage::url_path(&file_name));
+
+    (StatusCode::OK, Json(serde_json::json!({ "url": url, "path": file_name }))).into_response()
+}
 
-    (StatusCode::OK, Json(serde_json::json!({ "url": url }))).into_response()
+// API: Staging File Store
+fn storage_error_response(e: StorageError) -> axum::response::Response {
+    match e {
+        StorageError::InvalidPath(_) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        StorageError::NotFound(_) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        StorageError::Io(_) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+pub async fn list_staging_files() -> impl IntoResponse {
+    match tokio::task::spawn_blocking(storage::list_staging).await {
+        Ok(Ok(entries)) => (StatusCode::OK, Json(entries)).into_response(),
+        Ok(Err(e)) => storage_error_response(e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Join error: {}", e)).into_response(),
+    }
+}
+
+pub async fn get_staging_file(Path(path): Path<String>) -> impl IntoResponse {
+    match tokio::task::spawn_blocking(move || storage::staging_metadata(&path)).await {
+        Ok(Ok(metadata)) => (StatusCode::OK, Json(metadata)).into_response(),
+        Ok(Err(e)) => storage_error_response(e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Join error: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_staging_file(Path(path): Path<String>) -> impl IntoResponse {
+    match tokio::task::spawn_blocking(move || storage::delete_staging(&path)).await {
+        Ok(Ok(())) => (StatusCode::OK, "Staging file deleted").into_response(),
+        Ok(Err(e)) => storage_error_response(e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Join error: {}", e)).into_response(),
+    }
 }
 
 // API: Client uploads file (Result of UploadFile command)
@@ -840,24 +2329,27 @@ pub async fn upload_file_client(
         None => return (StatusCode::BAD_REQUEST, "No file provided").into_response(),
     };
 
-    let file_name = field
-        .file_name()
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "client_upload".to_string());
+    let file_name = match storage::sanitize_file_name(field.file_name().unwrap_or("client_upload")) {
+        Ok(name) => name,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
     let data = match field.bytes().await {
         Ok(d) => d,
         Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read bytes: {}", e)).into_response(),
     };
 
-    let dir_path = format!("uploads/client_data/{}", id);
+    let dir_path = format!("{}/{}", storage::CLIENT_DATA_DIR, id);
     let _ = tokio::fs::create_dir_all(&dir_path).await;
     
-    let file_path = format!("{}/{}", dir_path, file_name);
+    let file_path = match storage::resolve_in(&dir_path, &file_name) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
     if let Err(e) = tokio::fs::write(&file_path, &data).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file: {}", e)).into_response();
     }
     
-    info!("File uploaded by client for command {}: {}", id, file_path);
+    info!("File uploaded by client for command {}: {}", id, file_path.display());
     
     (StatusCode::OK, "Upload successful").into_response()
 }
@@ -903,6 +2395,10 @@ pub struct ClientSummary {
     pub remark: Option<String>,
     pub working_directory: Option<String>,
     pub display_ip: Option<String>,
+    // Why the last connection ended; only set while offline
+    pub disconnect_reason: Option<String>,
+    // Threshold alerts currently firing on this client
+    pub alerts: Vec<alerts::Alert>,
 }
 
 pub async fn list_clients(
@@ -980,7 +2476,7 @@ pub async fn list_clients(
     // Note: status in DB is 'connected' or 'disconnected'.
     // If we want 'connected' first, 'connected' < 'disconnected', so ASC is correct.
     let query = format!(
-        "SELECT id, hostname, os, alias, ip, ips, version, status, last_seen, started_at, remark, working_directory, display_ip 
+        "SELECT id, hostname, os, alias, ip, ips, version, status, last_seen, started_at, remark, working_directory, display_ip, disconnect_reason 
          FROM clients 
          WHERE {} 
          ORDER BY status ASC, hostname ASC 
@@ -1026,6 +2522,7 @@ pub async fn list_clients(
         let db_remark: Option<String> = r.get("remark");
         let db_working_directory: Option<String> = r.get("working_directory");
         let db_display_ip: Option<String> = r.get("display_ip");
+        let db_disconnect_reason: Option<String> = r.get("disconnect_reason");
         
         let last_seen = db_last_seen.map(|d| format!("{}Z", d.format("%Y-%m-%dT%H:%M:%S")));
         let parsed_db_ips: Vec<String> = db_ips.as_deref().and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default();
@@ -1081,9 +2578,21 @@ pub async fn list_clients(
             remark: db_remark,
             working_directory: db_working_directory,
             display_ip: db_display_ip,
+            disconnect_reason: if is_connected { None } else { db_disconnect_reason },
+            alerts: Vec::new(),
         }
     }).collect();
 
+    let ids: Vec<Uuid> = clients.iter().map(|c| c.id).collect();
+    match alerts::firing_by_client(&state.db, &ids).await {
+        Ok(mut firing) => {
+            for client in clients.iter_mut() {
+                client.alerts = firing.remove(&client.id).unwrap_or_default();
+            }
+        }
+        Err(e) => error!("Failed to load firing alerts: {}", e),
+    }
+
     // Sort: Online first, then Hostname ASC (Applied to current page only, which is acceptable but not perfect. 
     // Ideally we sort in DB, but 'online' status is in memory.
     // For strict sorting, we'd need to sync status to DB perfectly or fetch all IDs and paginate in memory (bad for scalability).
@@ -1185,6 +2694,240 @@ pub async fn update_client_working_directory(
     (StatusCode::OK, "Client working directory updated").into_response()
 }
 
+// API: Connection history and availability
+#[derive(serde::Deserialize)]
+pub struct UptimeParams {
+    // RFC 3339; the window defaults to the last 7 days and never extends past now
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// Parses an RFC 3339 `from`/`to` pair; `to` defaults to now and `from` to `default_span` before it
+fn time_window(from: &Option<String>, to: &Option<String>, default_span: chrono::TimeDelta) -> Result<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>), String> {
+    let parse = |name: &str, value: &Option<String>| -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+        value.as_deref()
+            .map(|v| chrono::DateTime::parse_from_rfc3339(v).map(|t| t.with_timezone(&chrono::Utc)).map_err(|e| format!("Invalid {} '{}': {}", name, v, e)))
+            .transpose()
+    };
+    let now = chrono::Utc::now();
+    let to = parse("to", to)?.unwrap_or(now).min(now);
+    let from = parse("from", from)?.unwrap_or(to - default_span);
+    if from >= to {
+        return Err("from must be before to (and in the past)".to_string());
+    }
+    Ok((from, to))
+}
+
+pub async fn list_client_sessions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let (from, to) = match time_window(&params.from, &params.to, chrono::TimeDelta::days(7)) {
+        Ok(w) => w,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match sessions::list(&state.db, id, from, to, Some(params.limit.unwrap_or(100).clamp(1, 1000))).await {
+        Ok(list) => Json(list).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list sessions: {}", e)).into_response(),
+    }
+}
+
+pub async fn get_client_uptime(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let (from, to) = match time_window(&params.from, &params.to, chrono::TimeDelta::days(7)) {
+        Ok(w) => w,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match sessions::uptime(&state.db, id, from, to).await {
+        Ok(uptime) => Json(uptime).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compute uptime: {}", e)).into_response(),
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct GroupMemberUptime {
+    pub hostname: String,
+    pub alias: Option<String>,
+    #[serde(flatten)]
+    pub uptime: sessions::Uptime,
+}
+
+#[derive(serde::Serialize)]
+pub struct GroupUptime {
+    pub group_id: Uuid,
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    // Mean availability of the members
+    pub availability_percent: f64,
+    pub disconnects: usize,
+    pub clients: Vec<GroupMemberUptime>,
+}
+
+pub async fn get_group_uptime(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let (from, to) = match time_window(&params.from, &params.to, chrono::TimeDelta::days(7)) {
+        Ok(w) => w,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let name: String = match sqlx::query_scalar("SELECT name FROM client_groups WHERE id = ?").bind(id.to_string()).fetch_optional(&state.db).await {
+        Ok(Some(name)) => name,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load group: {}", e)).into_response(),
+    };
+    let members = match sqlx::query(
+        "SELECT c.id, c.hostname, c.alias FROM client_group_members m JOIN clients c ON c.id = m.client_id WHERE m.group_id = ? ORDER BY c.hostname"
+    )
+        .bind(id.to_string())
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load group members: {}", e)).into_response(),
+    };
+
+    let mut clients = Vec::with_capacity(members.len());
+    for row in members {
+        let Ok(client_id) = Uuid::parse_str(&row.get::<String, _>("id")) else { continue };
+        match sessions::uptime(&state.db, client_id, from, to).await {
+            Ok(uptime) => clients.push(GroupMemberUptime { hostname: row.get("hostname"), alias: row.get("alias"), uptime }),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compute uptime: {}", e)).into_response(),
+        }
+    }
+
+    let availability_percent = if clients.is_empty() {
+        0.0
+    } else {
+        (clients.iter().map(|c| c.uptime.availability_percent).sum::<f64>() / clients.len() as f64 * 100.0).round() / 100.0
+    };
+    Json(GroupUptime {
+        group_id: id,
+        name,
+        from: from.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        to: to.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        availability_percent,
+        disconnects: clients.iter().map(|c| c.uptime.disconnects).sum(),
+        clients,
+    }).into_response()
+}
+
+// API: Metrics pushed by a client, averaged per minute or hour
+#[derive(serde::Deserialize)]
+pub struct MetricsParams {
+    // RFC 3339; the window defaults to the last hour
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // "minute" or "hour"; by default minutes for windows up to 6 hours
+    pub resolution: Option<String>,
+    // Include per-core CPU, disks and interfaces
+    #[serde(default)]
+    pub detail: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct MetricsSeries {
+    pub client_id: Uuid,
+    pub resolution: i64,
+    pub from: i64,
+    pub to: i64,
+    pub points: Vec<metrics::Point>,
+}
+
+pub async fn get_client_metrics(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<MetricsParams>,
+) -> impl IntoResponse {
+    let (from, to) = match time_window(&params.from, &params.to, chrono::TimeDelta::hours(1)) {
+        Ok(w) => w,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let resolution = match params.resolution.as_deref() {
+        Some("minute") => metrics::MINUTE,
+        Some("hour") => metrics::HOUR,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("Unknown resolution '{}', expected minute or hour", other)).into_response(),
+        None if to - from <= chrono::TimeDelta::hours(6) => metrics::MINUTE,
+        None => metrics::HOUR,
+    };
+    match metrics::query(&state.db, id, resolution, from.timestamp(), to.timestamp(), params.detail).await {
+        Ok(points) => Json(MetricsSeries { client_id: id, resolution, from: from.timestamp(), to: to.timestamp(), points }).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query metrics: {}", e)).into_response(),
+    }
+}
+
+// API: Last inventory reported by a client, available while it is offline
+pub async fn get_client_inventory(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match inventory::get(&state.db, id).await {
+        Ok(Some(record)) => Json(record).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No inventory recorded for this client").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load inventory: {}", e)).into_response(),
+    }
+}
+
+// API: Ask an online client for its inventory now; the reply is stored like any other report
+pub async fn refresh_client_inventory(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match dispatch_command(&state, id, CommandPayload::GetHardwareInfo, &[]).await {
+        Ok(CommandResult::HardwareInfo(_)) => get_client_inventory(State(state), Path(id)).await.into_response(),
+        Ok(CommandResult::Error(e)) | Ok(CommandResult::PolicyDenied(e)) => (StatusCode::BAD_GATEWAY, format!("Failed to collect inventory: {}", e)).into_response(),
+        Ok(_) => (StatusCode::BAD_GATEWAY, "Unexpected response from client").into_response(),
+        Err(None) => (StatusCode::NOT_FOUND, "Client not connected").into_response(),
+        Err(Some(e)) => (StatusCode::GATEWAY_TIMEOUT, format!("Failed to collect inventory: {}", e)).into_response(),
+    }
+}
+
+// API: Search stored inventories
+pub async fn search_inventory(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<inventory::Filter>,
+) -> impl IntoResponse {
+    match inventory::search(&state.db, &filter).await {
+        Ok(clients) => Json(clients).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to search inventory: {}", e)).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct CompareParams {
+    // Comma-separated client ids
+    pub ids: String,
+}
+
+// API: Compare the stored inventories of several clients
+pub async fn compare_inventory(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CompareParams>,
+) -> impl IntoResponse {
+    let mut ids = Vec::new();
+    for part in params.ids.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match Uuid::parse_str(part) {
+            Ok(id) if !ids.contains(&id) => ids.push(id),
+            Ok(_) => {}
+            Err(_) => return (StatusCode::BAD_REQUEST, format!("Invalid client id '{}'", part)).into_response(),
+        }
+    }
+    if ids.len() < 2 {
+        return (StatusCode::BAD_REQUEST, "At least two client ids are required").into_response();
+    }
+    match inventory::compare(&state.db, &ids).await {
+        Ok(comparison) => Json(comparison).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compare inventory: {}", e)).into_response(),
+    }
+}
+
 // API: Delete Client (Remove from DB and disconnect)
 pub async fn delete_client(
     State(state): State<Arc<AppState>>,
@@ -1202,6 +2945,19 @@ pub async fn delete_client(
          error!("Failed to remove client from groups: {}", e);
     }
     
+    if let Err(e) = sqlx::query("DELETE FROM client_sessions WHERE client_id = ?").bind(&id_str).execute(&state.db).await {
+         error!("Failed to remove client sessions: {}", e);
+    }
+    if let Err(e) = sqlx::query("DELETE FROM client_metrics WHERE client_id = ?").bind(&id_str).execute(&state.db).await {
+         error!("Failed to remove client metrics: {}", e);
+    }
+    if let Err(e) = sqlx::query("DELETE FROM client_inventory WHERE client_id = ?").bind(&id_str).execute(&state.db).await {
+         error!("Failed to remove client inventory: {}", e);
+    }
+    if let Err(e) = sqlx::query("DELETE FROM alerts WHERE client_id = ?").bind(&id_str).execute(&state.db).await {
+         error!("Failed to remove client alerts: {}", e);
+    }
+
     // 3. Remove from clients table
     if let Err(e) = sqlx::query!("DELETE FROM clients WHERE id = ?", id_str).execute(&state.db).await {
          return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete client from DB: {}", e)).into_response();
@@ -1236,13 +2992,40 @@ pub async fn get_command_result(
     State(state): State<Arc<AppState>>,
     Path(cmd_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    if let Some(result) = state.results.get(&cmd_id) {
-        (StatusCode::OK, Json(result.clone())).into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "Result not ready or invalid ID").into_response()
+    match state.results.get(&cmd_id) {
+        Some(entry) if !events::result_expired(&state, entry.1) => (StatusCode::OK, Json(entry.0.clone())).into_response(),
+        _ => (StatusCode::NOT_FOUND, "Result not ready or invalid ID").into_response(),
     }
 }
 
+// API: Issue a single-use ticket for opening the event stream
+pub async fn create_event_ticket(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    events::issue_ticket(&state)
+}
+
+// API: Event stream for the web console (command results, execution progress, client connections)
+pub async fn event_stream(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let rx = state.events.subscribe();
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let data = serde_json::to_string(&event).unwrap_or_default();
+                    return Some((Ok(Event::default().data(data)), rx));
+                }
+                // A slow browser misses events rather than holding up the server
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => warn!("Event stream lagged, {} events dropped", n),
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 
 // API: Client Update Management
 
@@ -1323,8 +3106,9 @@ pub async fn delete_update(
     }
     
     // Delete file
-    let path = format!("uploads/updates/{}", row.filename);
-    let _ = tokio::fs::remove_file(path).await;
+    if let Ok(path) = storage::resolve_in(storage::UPDATES_DIR, &row.filename) {
+        let _ = tokio::fs::remove_file(path).await;
+    }
     
     (StatusCode::OK, "Update deleted").into_response()
 }
@@ -1346,13 +3130,16 @@ pub async fn upload_update(
         } else if name == "platform" {
             platform = field.text().await.unwrap_or_default();
         } else if name == "file" {
-            let file_name = field.file_name().map(|s| s.to_string()).unwrap_or_else(|| "client_update".to_string());
+            let file_name = match storage::sanitize_file_name(field.file_name().unwrap_or("client_update")) {
+                Ok(name) => name,
+                Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            };
             let data = match field.bytes().await {
                 Ok(d) => d,
                 Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read bytes: {}", e)).into_response(),
             };
             
-            let dir_path = "uploads/updates";
+            let dir_path = storage::UPDATES_DIR;
             if let Err(e) = tokio::fs::create_dir_all(dir_path).await {
                  return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create directory: {}", e)).into_response();
             }
@@ -1413,7 +3200,7 @@ pub async fn trigger_update_clients(
     for client_id in payload.client_ids {
         if let Some(client) = state.clients.get(&client_id) {
              let base_url = get_download_base_url(&state, Some(client_id), host_header);
-             let download_url = format!("{}/api/files/download/updates/{}", base_url, update.filename);
+             let download_url = format!("{}/api/files/download/updates/{}", base_url, storage::url_path(&update.filename));
              
              let cmd_id = Uuid::new_v4();
              let msg = Message::Command {
@@ -1454,6 +3241,7 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
     let version: String;
     let ips: Vec<String>;
     let started_at: Option<chrono::DateTime<chrono::Utc>>;
+    let previous_version: Option<String>;
 
     // We can't really read "first message" easily without consuming the stream.
     // So we'll enter a loop but expect registration first.
@@ -1461,13 +3249,20 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
     // For simplicity, let's just assume the first message is Register.
     // In a real app, we might want a timeout here.
     
-    let msg = match receiver.next().await {
-        Some(Ok(msg)) => msg,
-        Some(Err(e)) => {
+    let heartbeat_interval = std::time::Duration::from_secs(state.config.heartbeat_interval_sec.max(1));
+    let heartbeat_timeout = heartbeat_interval * state.config.heartbeat_max_missed.max(1);
+
+    let msg = match tokio::time::timeout(heartbeat_timeout, receiver.next()).await {
+        Ok(Some(Ok(msg))) => msg,
+        Ok(Some(Err(e))) => {
             error!("Error receiving registration: {}", e);
             return;
         }
-        None => return,
+        Ok(None) => return,
+        Err(_) => {
+            warn!("No registration from {} within {}s, closing", addr, heartbeat_timeout.as_secs());
+            return;
+        }
     };
 
     match parse_message(msg) {
@@ -1493,6 +3288,13 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
             let ip_str = addr.ip().to_string();
             let ips_json = serde_json::to_string(&ips).unwrap_or("[]".to_string());
             let started_at_naive = started_at.map(|d| d.naive_utc());
+            previous_version = sqlx::query_scalar::<_, Option<String>>("SELECT version FROM clients WHERE id = ?")
+                .bind(&client_id_str)
+                .fetch_optional(&state.db)
+                .await
+                .ok()
+                .flatten()
+                .flatten();
             
             if let Err(e) = sqlx::query(
                 "INSERT INTO clients (id, hostname, os, last_seen, status, alias, ip, ips, version, started_at) VALUES (?, ?, ?, CURRENT_TIMESTAMP, ?, ?, ?, ?, ?, ?)
@@ -1523,7 +3325,8 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
     let (tx, mut rx) = mpsc::channel::<Message>(100);
 
     // Add to state
-    state.clients.insert(client_id, ClientConnection {
+    let connection_id = Uuid::new_v4();
+    let replaced = state.clients.insert(client_id, ClientConnection {
         tx,
         hostname: hostname.clone(),
         os: os.clone(),
@@ -1533,23 +3336,94 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
         version: version.clone(),
         started_at,
         server_host,
+        connection_id,
+        last_heartbeat: std::time::Instant::now(),
     });
+    if replaced.is_some() {
+        info!("Client {} reconnected, dropping its previous connection", client_id);
+    }
+    sessions::open(&state.db, connection_id, client_id, &addr.ip().to_string(), &version).await;
 
-    // Spawn task to send messages FROM channel TO websocket
-    let mut send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            let json = serde_json::to_string(&msg).unwrap();
-            if sender.send(WsMessage::Text(json)).await.is_err() {
-                break;
+    // Spawn task to send messages FROM channel TO websocket. It also pings the client every
+    // heartbeat interval and gives up once nothing was received for heartbeat_max_missed intervals,
+    // which catches half-open connections the read loop would wait on forever.
+    let mut send_task = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut ping = tokio::time::interval(heartbeat_interval);
+            ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ping.tick().await;
+            loop {
+                tokio::select! {
+                    msg = rx.recv() => {
+                        let Some(msg) = msg else { return sessions::REPLACED.to_string() };
+                        let json = serde_json::to_string(&msg).unwrap();
+                        if let Err(e) = send_with_timeout(&mut sender, WsMessage::Text(json), heartbeat_timeout).await {
+                            return e;
+                        }
+                    }
+                    _ = ping.tick() => {
+                        let silent = match state.clients.get(&client_id) {
+                            Some(c) if c.connection_id == connection_id => c.last_heartbeat.elapsed(),
+                            _ => return sessions::REPLACED.to_string(),
+                        };
+                        if silent > heartbeat_timeout {
+                            let _ = send_with_timeout(&mut sender, WsMessage::Close(None), heartbeat_interval).await;
+                            return format!(
+                                "heartbeat timeout: nothing received for {}s ({} missed intervals)",
+                                silent.as_secs(),
+                                silent.as_secs() / heartbeat_interval.as_secs()
+                            );
+                        }
+                        if let Err(e) = send_with_timeout(&mut sender, WsMessage::Ping(Vec::new()), heartbeat_timeout).await {
+                            return e;
+                        }
+                    }
+                }
             }
-        }
-    });
+        })
+    };
+
+    events::publish(&state, ServerEvent::ClientConnected { client_id, hostname: hostname.clone() });
+    webhooks::emit(&state, webhooks::CLIENT_CONNECTED, serde_json::json!({
+        "client_id": client_id,
+        "hostname": hostname,
+        "alias": alias,
+        "ip": addr.ip().to_string(),
+        "version": version,
+    }));
+    if let Some(previous) = previous_version.filter(|v| *v != version) {
+        webhooks::emit(&state, webhooks::UPDATE_FINISHED, serde_json::json!({
+            "client_id": client_id,
+            "hostname": hostname,
+            "previous_version": previous,
+            "version": version,
+        }));
+    }
+
+    // Pick up runs queued while offline and executions interrupted by a server restart
+    tokio::spawn(crate::executions::dispatch_for_client(state.clone(), client_id));
 
     // Handle incoming messages FROM websocket
     let mut recv_task = {
         let state = state.clone();
         tokio::spawn(async move {
-            while let Some(Ok(msg)) = receiver.next().await {
+            loop {
+                let msg = match receiver.next().await {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => return format!("connection error: {}", e),
+                    None => return "connection closed".to_string(),
+                };
+                if let Some(mut conn) = state.clients.get_mut(&client_id) {
+                    if conn.connection_id == connection_id {
+                        conn.last_heartbeat = std::time::Instant::now();
+                    }
+                }
+                match msg {
+                    WsMessage::Close(_) => return "closed by client".to_string(),
+                    WsMessage::Ping(_) | WsMessage::Pong(_) => continue,
+                    _ => {}
+                }
                 match parse_message(msg) {
                     Ok(parsed_msg) => {
                         match parsed_msg {
@@ -1560,11 +3434,45 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
                                     .bind(&client_id_str)
                                     .execute(&state.db).await;
                             }
+                            Message::Metrics(sample) => {
+                                if let Err(e) = metrics::record(&state.db, client_id, &sample).await {
+                                    error!("Failed to store metrics of client {}: {}", client_id, e);
+                                }
+                                if let Err(e) = alerts::evaluate(&state, client_id, &sample).await {
+                                    error!("Failed to evaluate alert rules for client {}: {}", client_id, e);
+                                }
+                            }
+                            Message::Inventory(hardware) => {
+                                if let Err(e) = inventory::save(&state.db, client_id, &hardware).await {
+                                    error!("Failed to store inventory of client {}: {}", client_id, e);
+                                }
+                            }
+                            Message::CommandOutput { id, lines, notice } => {
+                                events::publish(&state, ServerEvent::CommandOutput { command_id: id, client_id, lines, notice });
+                            }
                             Message::Response { id, result } => {
+                                if let CommandResult::HardwareInfo(hardware) = &result {
+                                    if let Err(e) = inventory::save(&state.db, client_id, hardware).await {
+                                        error!("Failed to store inventory of client {}: {}", client_id, e);
+                                    }
+                                }
+                                // Mask secrets substituted into this command before the result is logged or stored
+                                let result = match state.command_secrets.remove(&id) {
+                                    Some((_, (values, _))) => secrets::mask_value(&result, &values).unwrap_or_else(|e| {
+                                        CommandResult::Error(format!("Result withheld, secrets could not be masked: {}", e))
+                                    }),
+                                    None => result,
+                                };
                                 info!("Received response for command {}: {:?}", id, result);
-                                state.results.insert(id, result.clone());
+                                if let CommandResult::PolicyDenied(reason) = &result {
+                                    record_policy_violation(&state, client_id, id, reason).await;
+                                }
+                                // Script steps are awaited by their execution; only interactive commands are kept and pushed
                                 if let Some((_, waiter)) = state.waiters.remove(&id) {
                                     let _ = waiter.send(result);
+                                } else {
+                                    events::store_result(&state, id, result.clone());
+                                    events::publish(&state, ServerEvent::CommandResult { command_id: id, client_id, result });
                                 }
                             }
                             _ => {}
@@ -1575,20 +3483,71 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, addr: SocketAddr
                     }
                 }
             }
-            // Cleanup
-            state.clients.remove(&client_id);
-            let client_id_str = client_id.to_string();
-            let _ = sqlx::query("UPDATE clients SET status = 'disconnected' WHERE id = ?")
-                .bind(&client_id_str)
-                .execute(&state.db).await;
-            info!("Client disconnected: {}", client_id);
         })
     };
 
-    // Wait for either task to finish
-    tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
+    // Wait for either task to finish; each returns why the connection ended
+    let reason = tokio::select! {
+        r = (&mut send_task) => {
+            recv_task.abort();
+            r.unwrap_or_else(|e| format!("send task failed: {}", e))
+        }
+        r = (&mut recv_task) => {
+            send_task.abort();
+            r.unwrap_or_else(|e| format!("receive task failed: {}", e))
+        }
+    };
+
+    // Cleanup (after both tasks, so removing the sender can't abort it half way). If the client
+    // already reconnected, the new connection owns the entry and the status.
+    sessions::close(&state.db, connection_id, &reason).await;
+    if state.clients.remove_if(&client_id, |_, c| c.connection_id == connection_id).is_none() {
+        info!("Previous connection of client {} closed: {}", client_id, reason);
+        return;
+    }
+    let client_id_str = client_id.to_string();
+    let _ = sqlx::query("UPDATE clients SET status = 'disconnected', last_seen = CURRENT_TIMESTAMP, disconnect_reason = ? WHERE id = ?")
+        .bind(&reason)
+        .bind(&client_id_str)
+        .execute(&state.db).await;
+    if let Err(e) = alerts::client_disconnected(&state, client_id).await {
+        error!("Failed to close alerts of client {}: {}", client_id, e);
+    }
+    if reason.starts_with("heartbeat timeout") {
+        warn!("Client {} evicted: {}", client_id, reason);
+    } else {
+        info!("Client disconnected: {} ({})", client_id, reason);
+    }
+    webhooks::emit(&state, webhooks::CLIENT_DISCONNECTED, serde_json::json!({
+        "client_id": client_id,
+        "hostname": hostname,
+        "reason": reason,
+    }));
+    events::publish(&state, ServerEvent::ClientDisconnected { client_id, hostname, reason });
+}
+
+// Sends one frame, treating a peer that stops reading (full TCP buffer) like a failed send
+async fn send_with_timeout(
+    sender: &mut futures::stream::SplitSink<WebSocket, WsMessage>,
+    msg: WsMessage,
+    timeout: std::time::Duration,
+) -> Result<(), String> {
+    match tokio::time::timeout(timeout, sender.send(msg)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("send failed: {}", e)),
+        Err(_) => Err(format!("send timed out after {}s", timeout.as_secs())),
+    }
+}
+
+async fn record_policy_violation(state: &AppState, client_id: Uuid, command_id: Uuid, reason: &str) {
+    warn!("Client {} rejected command {} by policy: {}", client_id, command_id, reason);
+    if let Err(e) = sqlx::query("INSERT INTO policy_violations (id, client_id, command_id, reason) VALUES (?, ?, ?, ?)")
+        .bind(Uuid::new_v4().to_string())
+        .bind(client_id.to_string())
+        .bind(command_id.to_string())
+        .bind(reason)
+        .execute(&state.db).await {
+        error!("Failed to record policy violation: {}", e);
     }
 }
 
@@ -1619,6 +3578,7 @@ pub struct LoginResponse {
 
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(payload): Json<LoginRequest>,
 ) -> impl IntoResponse {
     if !state.config.web_auth_enabled {
@@ -1652,6 +3612,11 @@ pub async fn login(
         }
     }
 
+    warn!("Failed login for user '{}' from {}", payload.username, addr.ip());
+    webhooks::emit(&state, webhooks::LOGIN_FAILED, serde_json::json!({
+        "username": payload.username,
+        "ip": addr.ip().to_string(),
+    }));
     (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response()
 }
 
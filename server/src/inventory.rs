@@ -0,0 +1,188 @@
+use common::HardwareInfo;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, QueryBuilder, Row, Sqlite};
+use uuid::Uuid;
+
+// Latest inventory of each client, replaced whenever the client reports it (on connect and on
+// every GetHardwareInfo). The commonly filtered fields are copied into columns; the rest is
+// searched in the JSON.
+
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub client_id: Uuid,
+    pub collected_at: String,
+    #[serde(flatten)]
+    pub hardware: HardwareInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub client_id: Uuid,
+    pub hostname: String,
+    pub alias: Option<String>,
+    pub status: String,
+    pub collected_at: String,
+    pub cpu_model: String,
+    pub cpu_cores: i64,
+    pub total_memory: i64,
+    pub os_name: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct Filter {
+    // Substring of any inventory value: hostname, CPU, OS, filesystems, addresses, users...
+    pub q: Option<String>,
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    pub cpu: Option<String>,
+    pub kernel: Option<String>,
+    // Exact logged-in user name
+    pub user: Option<String>,
+    pub mac: Option<String>,
+    pub min_memory: Option<i64>,
+    pub min_cores: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub clients: Vec<Record>,
+    // Clients without a stored inventory
+    pub missing: Vec<Uuid>,
+    // Top-level fields whose values are not the same on all clients
+    pub differences: Vec<String>,
+}
+
+// Readings that change from one report to the next; comparing them says nothing about the hosts
+const VOLATILE_FIELDS: &[&str] = &["cpu_usage", "used_memory", "uptime_sec", "boot_time"];
+
+pub async fn save(db: &Pool<Sqlite>, client_id: Uuid, hardware: &HardwareInfo) -> Result<(), sqlx::Error> {
+    let data = serde_json::to_string(hardware).unwrap_or_default();
+    sqlx::query(
+        r#"INSERT OR REPLACE INTO client_inventory (client_id, collected_at, cpu_model, cpu_cores, total_memory, os_name, os_version, kernel_version, arch, data)
+           VALUES (?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), ?, ?, ?, ?, ?, ?, ?, ?)"#
+    )
+        .bind(client_id.to_string())
+        .bind(&hardware.cpu_model)
+        .bind(hardware.cpu_cores as i64)
+        .bind(hardware.total_memory as i64)
+        .bind(&hardware.os_name)
+        .bind(&hardware.os_version)
+        .bind(&hardware.kernel_version)
+        .bind(&hardware.arch)
+        .bind(data)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn get(db: &Pool<Sqlite>, client_id: Uuid) -> Result<Option<Record>, sqlx::Error> {
+    let row = sqlx::query("SELECT collected_at, data FROM client_inventory WHERE client_id = ?")
+        .bind(client_id.to_string())
+        .fetch_optional(db)
+        .await?;
+    Ok(row.map(|r| Record {
+        client_id,
+        collected_at: r.get("collected_at"),
+        hardware: serde_json::from_str(&r.get::<String, _>("data")).unwrap_or_default(),
+    }))
+}
+
+/// Clients whose stored inventory matches every given filter, by hostname.
+pub async fn search(db: &Pool<Sqlite>, filter: &Filter) -> Result<Vec<Summary>, sqlx::Error> {
+    let mut qb = QueryBuilder::<Sqlite>::new(
+        "SELECT i.client_id, c.hostname, c.alias, c.status, i.collected_at, i.cpu_model, i.cpu_cores, i.total_memory,
+                i.os_name, i.os_version, i.kernel_version, i.arch
+         FROM client_inventory i JOIN clients c ON c.id = i.client_id WHERE 1 = 1"
+    );
+    let like = |s: &str| format!("%{}%", s.trim());
+    if let Some(q) = filter.q.as_deref().filter(|s| !s.trim().is_empty()) {
+        qb.push(" AND (i.data LIKE ").push_bind(like(q))
+            .push(" OR c.hostname LIKE ").push_bind(like(q))
+            .push(" OR c.alias LIKE ").push_bind(like(q)).push(")");
+    }
+    if let Some(os) = filter.os.as_deref() {
+        qb.push(" AND (i.os_name LIKE ").push_bind(like(os))
+            .push(" OR i.os_version LIKE ").push_bind(like(os)).push(")");
+    }
+    if let Some(arch) = filter.arch.as_deref() {
+        qb.push(" AND i.arch = ").push_bind(arch.trim().to_string());
+    }
+    if let Some(cpu) = filter.cpu.as_deref() {
+        qb.push(" AND i.cpu_model LIKE ").push_bind(like(cpu));
+    }
+    if let Some(kernel) = filter.kernel.as_deref() {
+        qb.push(" AND i.kernel_version LIKE ").push_bind(like(kernel));
+    }
+    if let Some(user) = filter.user.as_deref() {
+        qb.push(" AND EXISTS (SELECT 1 FROM json_each(i.data, '$.logged_in_users') WHERE value = ").push_bind(user.trim().to_string()).push(")");
+    }
+    if let Some(mac) = filter.mac.as_deref() {
+        qb.push(" AND EXISTS (SELECT 1 FROM json_each(i.data, '$.network_interfaces') WHERE json_extract(value, '$.mac_address') LIKE ")
+            .push_bind(like(&mac.replace('-', ":"))).push(")");
+    }
+    if let Some(min) = filter.min_memory {
+        qb.push(" AND i.total_memory >= ").push_bind(min);
+    }
+    if let Some(min) = filter.min_cores {
+        qb.push(" AND i.cpu_cores >= ").push_bind(min);
+    }
+    qb.push(" ORDER BY c.hostname");
+
+    let rows = qb.build().fetch_all(db).await?;
+    Ok(rows.iter().map(|r| Summary {
+        client_id: Uuid::parse_str(&r.get::<String, _>("client_id")).unwrap_or_default(),
+        hostname: r.get("hostname"),
+        alias: r.get("alias"),
+        status: r.get("status"),
+        collected_at: r.get("collected_at"),
+        cpu_model: r.get("cpu_model"),
+        cpu_cores: r.get("cpu_cores"),
+        total_memory: r.get("total_memory"),
+        os_name: r.get("os_name"),
+        os_version: r.get("os_version"),
+        kernel_version: r.get("kernel_version"),
+        arch: r.get("arch"),
+    }).collect())
+}
+
+/// Stored inventories of the given clients side by side, with the fields that differ.
+pub async fn compare(db: &Pool<Sqlite>, client_ids: &[Uuid]) -> Result<Comparison, sqlx::Error> {
+    let mut clients = Vec::new();
+    let mut missing = Vec::new();
+    for id in client_ids {
+        match get(db, *id).await? {
+            Some(record) => clients.push(record),
+            None => missing.push(*id),
+        }
+    }
+
+    let values: Vec<serde_json::Value> = clients.iter().map(|c| comparable(&c.hardware)).collect();
+    let mut differences = Vec::new();
+    if let Some(serde_json::Value::Object(first)) = values.first() {
+        for key in first.keys() {
+            if VOLATILE_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            if values.iter().any(|v| v.get(key) != first.get(key)) {
+                differences.push(key.clone());
+            }
+        }
+    }
+    Ok(Comparison { clients, missing, differences })
+}
+
+// Free disk space moves constantly, so disks are compared by layout and size only
+fn comparable(hardware: &HardwareInfo) -> serde_json::Value {
+    let mut value = serde_json::to_value(hardware).unwrap_or_default();
+    if let Some(disks) = value.get_mut("disks").and_then(|d| d.as_array_mut()) {
+        for disk in disks {
+            if let Some(disk) = disk.as_object_mut() {
+                disk.remove("available_space");
+            }
+        }
+    }
+    value
+}
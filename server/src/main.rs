@@ -5,6 +5,20 @@ pub mod config;
 pub mod service;
 pub mod assets;
 pub mod app;
+pub mod tls;
+pub mod storage;
+pub mod template;
+pub mod secrets;
+pub mod scheduler;
+pub mod executions;
+pub mod rollout;
+pub mod events;
+pub mod webhooks;
+pub mod notifications;
+pub mod sessions;
+pub mod metrics;
+pub mod inventory;
+pub mod alerts;
 
 use clap::{Parser, Subcommand};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -31,33 +45,20 @@ enum Commands {
         cert_out: String,
         #[arg(short, long, default_value = "key.pem")]
         key_out: String,
+        /// CA certificate used to sign the leaf, created if missing
+        #[arg(long, default_value = "ca.pem")]
+        ca_cert: String,
+        #[arg(long, default_value = "ca-key.pem")]
+        ca_key: String,
+        /// Validity of the leaf certificate in days
+        #[arg(long, default_value_t = 365)]
+        days: u32,
+        /// Validity of a newly created CA in days
+        #[arg(long, default_value_t = 3650)]
+        ca_days: u32,
     },
 }
 
-fn generate_cert(san: Vec<String>, cert_out: String, key_out: String) -> anyhow::Result<()> {
-    let mut subject_alt_names = san;
-    if subject_alt_names.is_empty() {
-        subject_alt_names = vec![
-            "localhost".to_string(),
-            "127.0.0.1".to_string(),
-            "0.0.0.0".to_string(),
-            "::1".to_string(),
-        ];
-    }
-    
-    println!("Generating certificate for SANs: {:?}", subject_alt_names);
-
-    let cert = rcgen::generate_simple_self_signed(subject_alt_names)?;
-    let pem_serialized = cert.cert.pem();
-    let key_serialized = cert.signing_key.serialize_pem();
-    std::fs::write(&cert_out, pem_serialized)?;
-    std::fs::write(&key_out, key_serialized)?;
-    
-    println!("Certificate generated: {}", cert_out);
-    println!("Private key generated: {}", key_out);
-    Ok(())
-}
-
 fn main() -> anyhow::Result<()> {
     // Install default crypto provider if possible (ignore if already installed)
     let _ = rustls::crypto::ring::default_provider().install_default();
@@ -147,13 +148,15 @@ fn main() -> anyhow::Result<()> {
     };
 
     match cli.command {
-        Some(Commands::Install) => return service::install_service(),
-        Some(Commands::Uninstall) => return service::uninstall_service(),
-        Some(Commands::Start) => return service::start_service(),
-        Some(Commands::Stop) => return service::stop_service(),
+        Some(Commands::Install) => service::install_service(),
+        Some(Commands::Uninstall) => service::uninstall_service(),
+        Some(Commands::Start) => service::start_service(),
+        Some(Commands::Stop) => service::stop_service(),
         #[cfg(windows)]
-        Some(Commands::RunService) => return service::run_windows_service(),
-        Some(Commands::GenCert { san, cert_out, key_out }) => return generate_cert(san, cert_out, key_out),
+        Some(Commands::RunService) => service::run_windows_service(),
+        Some(Commands::GenCert { san, cert_out, key_out, ca_cert, ca_key, days, ca_days }) => {
+            tls::generate_cert(tls::GenCertOptions { san, cert_out, key_out, ca_cert, ca_key, days, ca_days })
+        }
         None => {
             let rt = tokio::runtime::Runtime::new()?;
             rt.block_on(app::run(async {
@@ -0,0 +1,339 @@
+use common::MetricsSample;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+// Samples are averaged into buckets of each resolution as they arrive; minute buckets are kept
+// for metrics_minute_retention_hours, hourly ones for metrics_hour_retention_days.
+pub const MINUTE: i64 = 60;
+pub const HOUR: i64 = 3600;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Detail {
+    pub cpu_per_core: Vec<f64>,
+    pub disks: Vec<DiskPoint>,
+    pub network: Vec<NetworkPoint>,
+}
+
+// Disks and interfaces come and go (removable drives, VPN links), so each keeps the number of
+// samples it was in. Buckets stored before the count existed read as one sample.
+fn one() -> i64 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskPoint {
+    pub mount_point: String,
+    #[serde(default = "one")]
+    pub samples: i64,
+    pub total_space: f64,
+    pub used_space: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkPoint {
+    pub interface: String,
+    #[serde(default = "one")]
+    pub samples: i64,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+}
+
+/// Averages over one bucket.
+#[derive(Debug, Serialize, Clone)]
+pub struct Point {
+    // Bucket start, unix seconds
+    pub timestamp: i64,
+    pub samples: i64,
+    pub cpu_usage: f64,
+    pub memory_used: f64,
+    pub memory_total: f64,
+    pub swap_used: f64,
+    pub swap_total: f64,
+    pub load_1: Option<f64>,
+    pub load_5: Option<f64>,
+    pub load_15: Option<f64>,
+    pub process_count: f64,
+    // Summed over all interfaces except loopback
+    pub net_rx_bytes_per_sec: f64,
+    pub net_tx_bytes_per_sec: f64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Detail>,
+}
+
+pub(crate) fn is_loopback(interface: &str) -> bool {
+    interface == "lo" || interface == "lo0" || interface.starts_with("Loopback")
+}
+
+impl Point {
+    fn from_sample(timestamp: i64, s: &MetricsSample) -> Self {
+        let external = s.network.iter().filter(|n| !is_loopback(&n.interface));
+        Point {
+            timestamp,
+            samples: 1,
+            cpu_usage: s.cpu_usage as f64,
+            memory_used: s.used_memory as f64,
+            memory_total: s.total_memory as f64,
+            swap_used: s.used_swap as f64,
+            swap_total: s.total_swap as f64,
+            load_1: s.load_average.map(|l| l.one),
+            load_5: s.load_average.map(|l| l.five),
+            load_15: s.load_average.map(|l| l.fifteen),
+            process_count: s.process_count as f64,
+            net_rx_bytes_per_sec: external.clone().map(|n| n.rx_bytes_per_sec as f64).sum(),
+            net_tx_bytes_per_sec: external.map(|n| n.tx_bytes_per_sec as f64).sum(),
+            detail: Some(Detail {
+                cpu_per_core: s.cpu_per_core.iter().map(|c| *c as f64).collect(),
+                disks: s.disks.iter().map(|d| DiskPoint {
+                    mount_point: d.mount_point.clone(),
+                    samples: 1,
+                    total_space: d.total_space as f64,
+                    used_space: d.used_space as f64,
+                }).collect(),
+                network: s.network.iter().map(|n| NetworkPoint {
+                    interface: n.interface.clone(),
+                    samples: 1,
+                    rx_bytes_per_sec: n.rx_bytes_per_sec as f64,
+                    tx_bytes_per_sec: n.tx_bytes_per_sec as f64,
+                }).collect(),
+            }),
+        }
+    }
+
+    // Folds one more sample into the running averages. Cores, disks and interfaces that are new
+    // in this sample start from its value; disks and interfaces missing from it keep theirs.
+    fn add(&mut self, s: &Point) {
+        let n = self.samples as f64 + 1.0;
+        let avg = |old: f64, new: f64| old + (new - old) / n;
+        let avg_opt = |old: Option<f64>, new: Option<f64>| match (old, new) {
+            (Some(o), Some(v)) => Some(avg(o, v)),
+            (o, v) => v.or(o),
+        };
+        self.cpu_usage = avg(self.cpu_usage, s.cpu_usage);
+        self.memory_used = avg(self.memory_used, s.memory_used);
+        self.memory_total = avg(self.memory_total, s.memory_total);
+        self.swap_used = avg(self.swap_used, s.swap_used);
+        self.swap_total = avg(self.swap_total, s.swap_total);
+        self.load_1 = avg_opt(self.load_1, s.load_1);
+        self.load_5 = avg_opt(self.load_5, s.load_5);
+        self.load_15 = avg_opt(self.load_15, s.load_15);
+        self.process_count = avg(self.process_count, s.process_count);
+        self.net_rx_bytes_per_sec = avg(self.net_rx_bytes_per_sec, s.net_rx_bytes_per_sec);
+        self.net_tx_bytes_per_sec = avg(self.net_tx_bytes_per_sec, s.net_tx_bytes_per_sec);
+
+        let new = s.detail.clone().unwrap_or_default();
+        let old = self.detail.get_or_insert_with(Detail::default);
+        old.cpu_per_core = new.cpu_per_core.iter().enumerate()
+            .map(|(i, v)| old.cpu_per_core.get(i).map_or(*v, |o| avg(*o, *v)))
+            .collect();
+        let avg_of = |count: i64, old: f64, new: f64| old + (new - old) / (count as f64 + 1.0);
+        for d in new.disks {
+            match old.disks.iter_mut().find(|o| o.mount_point == d.mount_point) {
+                Some(o) => {
+                    o.total_space = avg_of(o.samples, o.total_space, d.total_space);
+                    o.used_space = avg_of(o.samples, o.used_space, d.used_space);
+                    o.samples += 1;
+                }
+                None => old.disks.push(d),
+            }
+        }
+        for i in new.network {
+            match old.network.iter_mut().find(|o| o.interface == i.interface) {
+                Some(o) => {
+                    o.rx_bytes_per_sec = avg_of(o.samples, o.rx_bytes_per_sec, i.rx_bytes_per_sec);
+                    o.tx_bytes_per_sec = avg_of(o.samples, o.tx_bytes_per_sec, i.tx_bytes_per_sec);
+                    o.samples += 1;
+                }
+                None => old.network.push(i),
+            }
+        }
+        self.samples += 1;
+    }
+}
+
+const SELECT_POINTS: &str = "SELECT bucket, samples, cpu_usage, memory_used, memory_total, swap_used, swap_total, load_1, load_5, load_15, process_count, net_rx_bps, net_tx_bps, detail FROM client_metrics";
+
+fn point_from_row(r: &sqlx::sqlite::SqliteRow, with_detail: bool) -> Point {
+    Point {
+        timestamp: r.get("bucket"),
+        samples: r.get("samples"),
+        cpu_usage: r.get("cpu_usage"),
+        memory_used: r.get("memory_used"),
+        memory_total: r.get("memory_total"),
+        swap_used: r.get("swap_used"),
+        swap_total: r.get("swap_total"),
+        load_1: r.get("load_1"),
+        load_5: r.get("load_5"),
+        load_15: r.get("load_15"),
+        process_count: r.get("process_count"),
+        net_rx_bytes_per_sec: r.get("net_rx_bps"),
+        net_tx_bytes_per_sec: r.get("net_tx_bps"),
+        detail: if with_detail {
+            r.get::<Option<String>, _>("detail").as_deref().and_then(|d| serde_json::from_str(d).ok())
+        } else {
+            None
+        },
+    }
+}
+
+/// Adds a sample received now to the minute and hour buckets of the client. Buckets follow
+/// the server's clock, since client clocks may be off.
+pub async fn record(db: &Pool<Sqlite>, client_id: Uuid, sample: &MetricsSample) -> Result<(), sqlx::Error> {
+    record_at(db, client_id, sample, chrono::Utc::now().timestamp()).await
+}
+
+async fn record_at(db: &Pool<Sqlite>, client_id: Uuid, sample: &MetricsSample, now: i64) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    for resolution in [MINUTE, HOUR] {
+        let bucket = now - now.rem_euclid(resolution);
+        let new = Point::from_sample(bucket, sample);
+        let existing = sqlx::query(&format!("{} WHERE client_id = ? AND resolution = ? AND bucket = ?", SELECT_POINTS))
+            .bind(client_id.to_string())
+            .bind(resolution)
+            .bind(bucket)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let point = match existing {
+            Some(row) => {
+                let mut point = point_from_row(&row, true);
+                point.add(&new);
+                point
+            }
+            None => new,
+        };
+
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO client_metrics (client_id, resolution, bucket, samples, cpu_usage, memory_used, memory_total,
+                   swap_used, swap_total, load_1, load_5, load_15, process_count, net_rx_bps, net_tx_bps, detail)
+               VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#
+        )
+            .bind(client_id.to_string())
+            .bind(resolution)
+            .bind(bucket)
+            .bind(point.samples)
+            .bind(point.cpu_usage)
+            .bind(point.memory_used)
+            .bind(point.memory_total)
+            .bind(point.swap_used)
+            .bind(point.swap_total)
+            .bind(point.load_1)
+            .bind(point.load_5)
+            .bind(point.load_15)
+            .bind(point.process_count)
+            .bind(point.net_rx_bytes_per_sec)
+            .bind(point.net_tx_bytes_per_sec)
+            .bind(point.detail.as_ref().and_then(|d| serde_json::to_string(d).ok()))
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Buckets of one resolution with `from <= timestamp <= to` (unix seconds), oldest first.
+pub async fn query(db: &Pool<Sqlite>, client_id: Uuid, resolution: i64, from: i64, to: i64, with_detail: bool) -> Result<Vec<Point>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{} WHERE client_id = ? AND resolution = ? AND bucket >= ? AND bucket <= ? ORDER BY bucket", SELECT_POINTS))
+        .bind(client_id.to_string())
+        .bind(resolution)
+        .bind(from - from.rem_euclid(resolution))
+        .bind(to)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.iter().map(|r| point_from_row(r, with_detail)).collect())
+}
+
+/// Drops buckets past their retention, hourly.
+pub async fn run_retention(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        let now = chrono::Utc::now().timestamp();
+        let limits = [
+            (MINUTE, state.config.metrics_minute_retention_hours as i64 * 3600),
+            (HOUR, state.config.metrics_hour_retention_days as i64 * 86400),
+        ];
+        for (resolution, keep_sec) in limits {
+            match sqlx::query("DELETE FROM client_metrics WHERE resolution = ? AND bucket < ?")
+                .bind(resolution)
+                .bind(now - keep_sec)
+                .execute(&state.db)
+                .await
+            {
+                Ok(r) if r.rows_affected() > 0 => info!("Pruned {} metric buckets of {}s", r.rows_affected(), resolution),
+                Ok(_) => {}
+                Err(e) => error!("Failed to prune metrics: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{DiskUsage, NetworkUsage};
+
+    fn sample(cpu: f32, disks: &[(&str, u64)], network: &[(&str, u64)]) -> MetricsSample {
+        MetricsSample {
+            cpu_usage: cpu,
+            cpu_per_core: vec![cpu],
+            total_memory: 1000,
+            used_memory: 500,
+            total_swap: 0,
+            used_swap: 0,
+            disks: disks.iter().map(|(m, used)| DiskUsage { mount_point: m.to_string(), total_space: 100, used_space: *used }).collect(),
+            network: network.iter().map(|(i, rx)| NetworkUsage { interface: i.to_string(), rx_bytes_per_sec: *rx, tx_bytes_per_sec: 0 }).collect(),
+            load_average: None,
+            process_count: 10,
+        }
+    }
+
+    #[test]
+    fn disks_and_interfaces_average_over_their_own_samples() {
+        let mut point = Point::from_sample(0, &sample(10.0, &[("/", 10), ("/mnt/usb", 80)], &[("eth0", 100)]));
+        point.add(&Point::from_sample(0, &sample(20.0, &[("/", 20)], &[("eth0", 300), ("wg0", 50)])));
+        point.add(&Point::from_sample(0, &sample(30.0, &[("/", 30), ("/mnt/usb", 90)], &[("eth0", 200)])));
+
+        assert_eq!((point.samples, point.cpu_usage), (3, 20.0));
+        let detail = point.detail.unwrap();
+        let disk = |m: &str| detail.disks.iter().find(|d| d.mount_point == m).map(|d| (d.samples, d.used_space));
+        assert_eq!(disk("/"), Some((3, 20.0)));
+        // Missing from the middle sample, so averaged over two
+        assert_eq!(disk("/mnt/usb"), Some((2, 85.0)));
+        let interface = |i: &str| detail.network.iter().find(|n| n.interface == i).map(|n| (n.samples, n.rx_bytes_per_sec));
+        assert_eq!(interface("eth0"), Some((3, 200.0)));
+        assert_eq!(interface("wg0"), Some((1, 50.0)));
+    }
+
+    #[test]
+    fn stored_detail_without_counts_reads_as_one_sample() {
+        let detail: Detail = serde_json::from_str(r#"{"cpu_per_core":[],"disks":[{"mount_point":"/","total_space":1,"used_space":1}],"network":[]}"#).unwrap();
+        assert_eq!(detail.disks[0].samples, 1);
+    }
+
+    #[tokio::test]
+    async fn samples_fold_into_minute_and_hour_buckets() {
+        let dir = std::env::temp_dir().join(format!("roam-metrics-{}", Uuid::new_v4()));
+        let state = AppState::for_tests(&dir, crate::config::ServerConfig::new().unwrap()).await;
+        let client_id = Uuid::new_v4();
+        let hour = 10 * HOUR;
+        for (offset, cpu) in [(5, 10.0), (50, 30.0), (70, 80.0)] {
+            record_at(&state.db, client_id, &sample(cpu, &[("/", 50)], &[]), hour + offset).await.unwrap();
+        }
+
+        let minutes = query(&state.db, client_id, MINUTE, hour, hour + HOUR, false).await.unwrap();
+        let summary: Vec<_> = minutes.iter().map(|p| (p.timestamp, p.samples, p.cpu_usage)).collect();
+        assert_eq!(summary, vec![(hour, 2, 20.0), (hour + MINUTE, 1, 80.0)]);
+        assert!(minutes[0].detail.is_none());
+
+        let hours = query(&state.db, client_id, HOUR, hour, hour, true).await.unwrap();
+        assert_eq!((hours.len(), hours[0].timestamp, hours[0].samples, hours[0].cpu_usage), (1, hour, 3, 40.0));
+        assert_eq!(hours[0].detail.as_ref().unwrap().disks[0].samples, 3);
+        // `from` inside a bucket still returns that bucket
+        assert_eq!(query(&state.db, client_id, MINUTE, hour + 30, hour + 30, false).await.unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
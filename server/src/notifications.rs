@@ -0,0 +1,398 @@
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use sqlx::{Pool, Row, Sqlite};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::config::ServerConfig;
+use crate::state::{AppState, ScriptGroup};
+
+pub const EXECUTION_FAILED: &str = "execution_failed";
+pub const EXECUTION_COMPLETED: &str = "execution_completed";
+// A client has been disconnected for `offline_minutes`
+pub const CLIENT_OFFLINE: &str = "client_offline";
+
+pub const KINDS: &[&str] = &[EXECUTION_FAILED, EXECUTION_COMPLETED, CLIENT_OFFLINE];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Rule {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: String,
+    // Only notify for this script / client; None matches all
+    pub script_id: Option<Uuid>,
+    pub client_id: Option<Uuid>,
+    pub offline_minutes: u32,
+    pub recipients: Vec<String>,
+    pub enabled: bool,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    pub id: Uuid,
+    pub subject: String,
+    pub recipients: Vec<String>,
+    pub status: String, // 'sent', 'failed'
+    pub error: Option<String>,
+    pub reference: Option<String>,
+    pub created_at: Option<String>,
+}
+
+pub fn validate(kind: &str, recipients: &[String]) -> Result<(), String> {
+    if !KINDS.contains(&kind) {
+        return Err(format!("Unknown kind '{}', expected one of: {}", kind, KINDS.join(", ")));
+    }
+    if recipients.is_empty() {
+        return Err("At least one recipient is required".to_string());
+    }
+    match recipients.iter().find(|r| r.parse::<Mailbox>().is_err()) {
+        Some(r) => Err(format!("Invalid recipient address '{}'", r)),
+        None => Ok(()),
+    }
+}
+
+const SELECT_RULES: &str = "SELECT id, name, kind, script_id, client_id, offline_minutes, recipients, enabled, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at FROM notification_rules";
+
+fn rule_from_row(r: &sqlx::sqlite::SqliteRow) -> Rule {
+    let parse_id = |col: &str| r.get::<Option<String>, _>(col).and_then(|s| Uuid::parse_str(&s).ok());
+    Rule {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        name: r.get("name"),
+        kind: r.get("kind"),
+        script_id: parse_id("script_id"),
+        client_id: parse_id("client_id"),
+        offline_minutes: r.get::<i64, _>("offline_minutes").max(1) as u32,
+        recipients: serde_json::from_str(&r.get::<String, _>("recipients")).unwrap_or_default(),
+        enabled: r.get::<i64, _>("enabled") != 0,
+        created_at: r.get("created_at"),
+    }
+}
+
+pub async fn list(db: &Pool<Sqlite>) -> Result<Vec<Rule>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{} ORDER BY created_at DESC", SELECT_RULES)).fetch_all(db).await?;
+    Ok(rows.iter().map(rule_from_row).collect())
+}
+
+pub async fn get(db: &Pool<Sqlite>, id: Uuid) -> Result<Option<Rule>, sqlx::Error> {
+    let row = sqlx::query(&format!("{} WHERE id = ?", SELECT_RULES))
+        .bind(id.to_string())
+        .fetch_optional(db)
+        .await?;
+    Ok(row.as_ref().map(rule_from_row))
+}
+
+pub async fn save(db: &Pool<Sqlite>, rule: &Rule) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO notification_rules (id, name, kind, script_id, client_id, offline_minutes, recipients, enabled)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT(id) DO UPDATE SET name = excluded.name, kind = excluded.kind, script_id = excluded.script_id,
+               client_id = excluded.client_id, offline_minutes = excluded.offline_minutes,
+               recipients = excluded.recipients, enabled = excluded.enabled"#
+    )
+        .bind(rule.id.to_string())
+        .bind(&rule.name)
+        .bind(&rule.kind)
+        .bind(rule.script_id.map(|id| id.to_string()))
+        .bind(rule.client_id.map(|id| id.to_string()))
+        .bind(rule.offline_minutes as i64)
+        .bind(serde_json::to_string(&rule.recipients).unwrap_or("[]".to_string()))
+        .bind(rule.enabled as i64)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_log(db: &Pool<Sqlite>, rule_id: Uuid, limit: i64) -> Result<Vec<LogEntry>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT id, subject, recipients, status, error, reference, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at FROM notification_log WHERE rule_id = ? ORDER BY created_at DESC LIMIT ?"
+    )
+        .bind(rule_id.to_string())
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.iter().map(|r| LogEntry {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        subject: r.get("subject"),
+        recipients: serde_json::from_str(&r.get::<String, _>("recipients")).unwrap_or_default(),
+        status: r.get("status"),
+        error: r.get("error"),
+        reference: r.get("reference"),
+        created_at: r.get("created_at"),
+    }).collect())
+}
+
+fn matches_execution(rule: &Rule, kind: &str, script_id: Uuid, client_id: Uuid) -> bool {
+    rule.enabled && rule.kind == kind
+        && rule.script_id.is_none_or(|id| id == script_id)
+        && rule.client_id.is_none_or(|id| id == client_id)
+}
+
+/// Notifies the rules matching a finished execution, in the background.
+pub fn execution_finished(
+    state: &Arc<AppState>,
+    execution_id: Uuid,
+    script: &ScriptGroup,
+    client_id: Uuid,
+    client_hostname: String,
+    success: bool,
+    failure: Option<String>,
+) -> tokio::task::JoinHandle<()> {
+    let state = state.clone();
+    let kind = if success { EXECUTION_COMPLETED } else { EXECUTION_FAILED };
+    let (script_id, script_name) = (script.id, script.name.clone());
+    tokio::spawn(async move {
+        let rules = match list(&state.db).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Failed to load notification rules: {}", e);
+                return;
+            }
+        };
+        for rule in rules.into_iter().filter(|r| matches_execution(r, kind, script_id, client_id)) {
+            let outcome = if success { "completed" } else { "failed" };
+            let subject = format!("[Roam] {} {} on {}", script_name, outcome, client_hostname);
+            let mut body = format!(
+                "Script: {}\nClient: {} ({})\nStatus: {}\nExecution: {}\n",
+                script_name, client_hostname, client_id, outcome, execution_id
+            );
+            if let Some(failure) = &failure {
+                body.push_str(&format!("\n{}\n", failure));
+            }
+            send(&state, &rule, &subject, &body, Some(&execution_id.to_string())).await;
+        }
+    })
+}
+
+/// Checks `client_offline` rules once a minute. A client is reported once per disconnect, only
+/// if it went offline after the rule was created, and never for downtime of the server itself.
+pub async fn run_offline_checks(state: Arc<AppState>) {
+    // Same format as CURRENT_TIMESTAMP, which last_seen is written with
+    let started = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        check_offline_clients(&state, &started).await;
+    }
+}
+
+// One pass over the `client_offline` rules; `started` is when the server came up
+async fn check_offline_clients(state: &AppState, started: &str) {
+    let rules = match list(&state.db).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            error!("Failed to load notification rules: {}", e);
+            return;
+        }
+    };
+    for rule in rules.into_iter().filter(|r| r.enabled && r.kind == CLIENT_OFFLINE) {
+        let rows = sqlx::query(
+            r#"SELECT c.id, c.hostname, c.alias, CAST(c.last_seen AS TEXT) as last_seen FROM clients c, notification_rules r
+               WHERE r.id = ? AND c.status != 'connected' AND (? IS NULL OR c.id = ?)
+                 AND c.last_seen >= r.created_at
+                 AND MAX(c.last_seen, ?) <= datetime('now', ?)
+                 AND NOT EXISTS (SELECT 1 FROM notification_log l WHERE l.rule_id = r.id AND l.reference = c.id || '@' || c.last_seen)"#
+        )
+            .bind(rule.id.to_string())
+            .bind(rule.client_id.map(|id| id.to_string()))
+            .bind(rule.client_id.map(|id| id.to_string()))
+            .bind(started)
+            .bind(format!("-{} minutes", rule.offline_minutes))
+            .fetch_all(&state.db)
+            .await;
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Failed to check offline clients for rule {}: {}", rule.name, e);
+                continue;
+            }
+        };
+        for row in rows {
+            let id: String = row.get("id");
+            let hostname: String = row.get("hostname");
+            let name = row.get::<Option<String>, _>("alias").filter(|a| !a.is_empty()).unwrap_or(hostname.clone());
+            let last_seen: String = row.get("last_seen");
+            let subject = format!("[Roam] {} offline for {} minutes", name, rule.offline_minutes);
+            let body = format!(
+                "Client: {} ({})\nHostname: {}\nLast seen: {} UTC\n",
+                name, id, hostname, last_seen
+            );
+            send(state, &rule, &subject, &body, Some(&format!("{}@{}", id, last_seen))).await;
+        }
+    }
+}
+
+/// Mails the rule's recipients and records the attempt in the notification log.
+pub async fn send(state: &AppState, rule: &Rule, subject: &str, body: &str, reference: Option<&str>) -> LogEntry {
+    let result = deliver(&state.config, &rule.recipients, subject, body).await;
+    let entry = LogEntry {
+        id: Uuid::new_v4(),
+        subject: subject.to_string(),
+        recipients: rule.recipients.clone(),
+        status: if result.is_ok() { "sent" } else { "failed" }.to_string(),
+        error: result.err(),
+        reference: reference.map(str::to_string),
+        created_at: Some(chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+    };
+    match &entry.error {
+        None => info!("Notification '{}' sent to {}", subject, rule.recipients.join(", ")),
+        Some(e) => warn!("Notification rule {}: failed to send '{}': {}", rule.name, subject, e),
+    }
+
+    if let Err(e) = sqlx::query("INSERT INTO notification_log (id, rule_id, subject, recipients, status, error, reference) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(entry.id.to_string())
+        .bind(rule.id.to_string())
+        .bind(&entry.subject)
+        .bind(serde_json::to_string(&entry.recipients).unwrap_or("[]".to_string()))
+        .bind(&entry.status)
+        .bind(&entry.error)
+        .bind(&entry.reference)
+        .execute(&state.db).await {
+        error!("Failed to record notification: {}", e);
+    }
+    entry
+}
+
+async fn deliver(config: &ServerConfig, recipients: &[String], subject: &str, body: &str) -> Result<(), String> {
+    let from: Mailbox = config.smtp_from.parse().map_err(|e| format!("Invalid smtp_from '{}': {}", config.smtp_from, e))?;
+    let mut builder = Message::builder().from(from).subject(subject).header(ContentType::TEXT_PLAIN);
+    for recipient in recipients {
+        builder = builder.to(recipient.parse().map_err(|e| format!("Invalid recipient '{}': {}", recipient, e))?);
+    }
+    let message = builder.body(body.to_string()).map_err(|e| e.to_string())?;
+
+    if let Some(dir) = &config.smtp_outbox_dir {
+        tokio::fs::create_dir_all(dir).await.map_err(|e| format!("Failed to create outbox {}: {}", dir, e))?;
+        return AsyncFileTransport::<Tokio1Executor>::new(dir).send(message).await.map(|_| ()).map_err(|e| e.to_string());
+    }
+
+    let Some(host) = &config.smtp_host else {
+        return Err("SMTP is not configured (set smtp_host or smtp_outbox_dir)".to_string());
+    };
+    let builder = match config.smtp_tls.to_ascii_lowercase().as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(|e| e.to_string())?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(|e| e.to_string())?,
+    };
+    let mut builder = builder.port(config.smtp_port).timeout(Some(Duration::from_secs(30)));
+    if let (Some(user), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+        builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+    }
+    builder.build().send(message).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    async fn test_state() -> (Arc<AppState>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("roam-notifications-{}", Uuid::new_v4()));
+        let mut config = ServerConfig::new().unwrap();
+        config.smtp_outbox_dir = Some(dir.join("outbox").to_string_lossy().to_string());
+        (AppState::for_tests(&dir, config).await, dir)
+    }
+
+    fn rule(kind: &str, script_id: Option<Uuid>, client_id: Option<Uuid>, to: &str) -> Rule {
+        Rule {
+            id: Uuid::new_v4(),
+            name: format!("{} rule", kind),
+            kind: kind.to_string(),
+            script_id,
+            client_id,
+            offline_minutes: 10,
+            recipients: vec![to.to_string()],
+            enabled: true,
+            created_at: None,
+        }
+    }
+
+    fn script(name: &str) -> ScriptGroup {
+        ScriptGroup { id: Uuid::new_v4(), name: name.to_string(), steps: Vec::new(), params: Vec::new() }
+    }
+
+    // Messages written by the file transport, as raw text
+    fn outbox(dir: &Path) -> Vec<String> {
+        match std::fs::read_dir(dir.join("outbox")) {
+            Ok(entries) => entries.map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn execution_rules_filter_by_status_script_and_client() {
+        let (script_id, client_id) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(matches_execution(&rule(EXECUTION_FAILED, None, None, "a@example.com"), EXECUTION_FAILED, script_id, client_id));
+        assert!(!matches_execution(&rule(EXECUTION_COMPLETED, None, None, "a@example.com"), EXECUTION_FAILED, script_id, client_id));
+        assert!(matches_execution(&rule(EXECUTION_FAILED, Some(script_id), Some(client_id), "a@example.com"), EXECUTION_FAILED, script_id, client_id));
+        assert!(!matches_execution(&rule(EXECUTION_FAILED, Some(Uuid::new_v4()), None, "a@example.com"), EXECUTION_FAILED, script_id, client_id));
+        assert!(!matches_execution(&rule(EXECUTION_FAILED, None, Some(Uuid::new_v4()), "a@example.com"), EXECUTION_FAILED, script_id, client_id));
+        let mut disabled = rule(EXECUTION_FAILED, None, None, "a@example.com");
+        disabled.enabled = false;
+        assert!(!matches_execution(&disabled, EXECUTION_FAILED, script_id, client_id));
+    }
+
+    #[tokio::test]
+    async fn finished_executions_mail_the_matching_rules() {
+        let (state, dir) = test_state().await;
+        let (backup, deploy) = (script("Backup"), script("Deploy"));
+        let client_id = Uuid::new_v4();
+        save(&state.db, &rule(EXECUTION_FAILED, Some(backup.id), None, "ops@example.com")).await.unwrap();
+        save(&state.db, &rule(EXECUTION_COMPLETED, Some(backup.id), None, "done@example.com")).await.unwrap();
+        save(&state.db, &rule(EXECUTION_FAILED, Some(deploy.id), None, "deploy@example.com")).await.unwrap();
+        save(&state.db, &rule(EXECUTION_FAILED, None, Some(Uuid::new_v4()), "other@example.com")).await.unwrap();
+
+        let execution_id = Uuid::new_v4();
+        let failure = Some("Step 1 (Command) failed: exit code 2".to_string());
+        execution_finished(&state, execution_id, &backup, client_id, "web-1".to_string(), false, failure).await.unwrap();
+
+        let mails = outbox(&dir);
+        assert_eq!(mails.len(), 1);
+        let mail = &mails[0];
+        assert!(mail.contains("To: ops@example.com"));
+        assert!(mail.contains("Subject: [Roam] Backup failed on web-1"));
+        assert!(mail.contains(&format!("Execution: {}", execution_id)));
+        assert!(mail.contains("Step 1 (Command) failed: exit code 2"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_clients_are_reported_once() {
+        let (state, dir) = test_state().await;
+        let (watched, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let offline = rule(CLIENT_OFFLINE, None, Some(watched), "ops@example.com");
+        save(&state.db, &offline).await.unwrap();
+        sqlx::query("UPDATE notification_rules SET created_at = datetime('now', '-2 hours')").execute(&state.db).await.unwrap();
+        for (id, name, status, seen) in [
+            (watched, "db-1", "disconnected", "-1 hours"),
+            (other, "db-2", "disconnected", "-1 hours"),
+            (Uuid::new_v4(), "db-3", "connected", "-1 hours"),
+        ] {
+            sqlx::query("INSERT INTO clients (id, hostname, os, last_seen, status) VALUES (?, ?, 'linux', datetime('now', ?), ?)")
+                .bind(id.to_string()).bind(name).bind(seen).bind(status)
+                .execute(&state.db).await.unwrap();
+        }
+
+        let started = "2000-01-01 00:00:00";
+        check_offline_clients(&state, started).await;
+        check_offline_clients(&state, started).await;
+
+        let mails = outbox(&dir);
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("To: ops@example.com"));
+        assert!(mails[0].contains("Subject: [Roam] db-1 offline for 10 minutes"));
+        assert!(mails[0].contains(&format!("Client: db-1 ({})", watched)));
+        assert_eq!(list_log(&state.db, offline.id, 10).await.unwrap().len(), 1);
+
+        // Downtime of the server itself doesn't count
+        sqlx::query("DELETE FROM notification_log").execute(&state.db).await.unwrap();
+        check_offline_clients(&state, &chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()).await;
+        assert_eq!(outbox(&dir).len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
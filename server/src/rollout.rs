@@ -0,0 +1,221 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{events, handlers};
+use crate::state::{AppState, ExecutionProgress, ScriptGroup};
+
+/// Batching options for a rolling group run. Without them every member starts at once.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RolloutOptions {
+    // Members per batch, or a percentage of the online members (rounded up)
+    pub batch_size: Option<usize>,
+    pub batch_percent: Option<u32>,
+    #[serde(default)]
+    pub batch_pause_sec: u64,
+    // The remaining batches are aborted once failures exceed either limit; the percentage is of
+    // all members in the run
+    pub max_failures: Option<usize>,
+    pub max_failure_percent: Option<u32>,
+}
+
+impl RolloutOptions {
+    pub fn is_rolling(&self) -> bool {
+        self.batch_size.is_some() || self.batch_percent.is_some()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.batch_size.is_some() && self.batch_percent.is_some() {
+            return Err("Specify either batch_size or batch_percent, not both".to_string());
+        }
+        if self.batch_size == Some(0) {
+            return Err("batch_size must be at least 1".to_string());
+        }
+        if self.batch_percent.is_some_and(|p| p == 0 || p > 100) {
+            return Err("batch_percent must be between 1 and 100".to_string());
+        }
+        if self.max_failure_percent.is_some_and(|p| p > 100) {
+            return Err("max_failure_percent must be between 0 and 100".to_string());
+        }
+        if !self.is_rolling() && (self.batch_pause_sec > 0 || self.max_failures.is_some() || self.max_failure_percent.is_some()) {
+            return Err("batch_pause_sec, max_failures and max_failure_percent require batch_size or batch_percent".to_string());
+        }
+        Ok(())
+    }
+
+    fn batch_len(&self, members: usize) -> usize {
+        match (self.batch_size, self.batch_percent) {
+            (Some(size), _) => size,
+            (None, Some(percent)) => (members * percent as usize).div_ceil(100),
+            (None, None) => members,
+        }
+        .max(1)
+    }
+
+    // Reason to abort, if the failures so far exceed a limit
+    fn exceeded(&self, failed: usize, members: usize) -> Option<String> {
+        if let Some(max) = self.max_failures {
+            if failed > max {
+                return Some(format!("{} failures exceed max_failures {}", failed, max));
+            }
+        }
+        if let Some(max) = self.max_failure_percent {
+            if failed * 100 > max as usize * members {
+                return Some(format!("{} of {} clients failed, above max_failure_percent {}", failed, members, max));
+            }
+        }
+        None
+    }
+}
+
+/// Runs the group's scripts on `members` in batches, waiting for each batch to finish before
+/// starting the next. Progress is published in `active_executions` under the returned id.
+/// The rollout is kept in memory only: a server restart ends it without starting later batches,
+/// and members queued while offline or resumed after a restart run outside the batches.
+pub fn start(
+    state: Arc<AppState>,
+    group_name: String,
+    members: Vec<Uuid>,
+    runs: Arc<Vec<(ScriptGroup, HashMap<String, String>)>>,
+    host: String,
+    options: RolloutOptions,
+) -> Uuid {
+    let rollout_id = Uuid::new_v4();
+    let member_count = members.len();
+    let batches: Vec<Vec<Uuid>> = members.chunks(options.batch_len(member_count)).map(|c| c.to_vec()).collect();
+
+    state.active_executions.insert(rollout_id, ExecutionProgress {
+        execution_id: rollout_id,
+        script_name: format!("Rolling run: {}", group_name),
+        client_hostname: format!("{} clients", member_count),
+        status: "running".to_string(),
+        logs: Vec::new(),
+        current_step: 0,
+        total_steps: batches.len(),
+    });
+    events::execution_updated(&state, rollout_id, None);
+
+    tokio::spawn(async move {
+        let total = batches.len();
+        let (mut succeeded, mut failed) = (0, 0);
+        let mut status = "completed";
+
+        for (i, batch) in batches.iter().enumerate() {
+            if i > 0 && options.batch_pause_sec > 0 {
+                log(&state, rollout_id, format!("Pausing {}s before batch {}/{}", options.batch_pause_sec, i + 1, total));
+                tokio::time::sleep(tokio::time::Duration::from_secs(options.batch_pause_sec)).await;
+            }
+            if let Some(mut progress) = state.active_executions.get_mut(&rollout_id) {
+                progress.current_step = i + 1;
+            }
+            events::execution_updated(&state, rollout_id, None);
+
+            let hostnames: Vec<String> = batch.iter()
+                .map(|id| state.clients.get(id).map(|c| c.hostname.clone()).unwrap_or_else(|| id.to_string()))
+                .collect();
+            log(&state, rollout_id, format!("Batch {}/{}: starting on {}", i + 1, total, hostnames.join(", ")));
+
+            let results = futures::future::join_all(
+                batch.iter().map(|id| handlers::run_group_on_client(state.clone(), *id, runs.clone(), host.clone(), None)),
+            ).await;
+            let batch_failed = results.iter().filter(|ok| !**ok).count();
+            succeeded += results.len() - batch_failed;
+            failed += batch_failed;
+            log(&state, rollout_id, format!("Batch {}/{}: {} succeeded, {} failed", i + 1, total, results.len() - batch_failed, batch_failed));
+
+            if let Some(reason) = options.exceeded(failed, member_count) {
+                let remaining: usize = batches[i + 1..].iter().map(Vec::len).sum();
+                if remaining > 0 {
+                    warn!("Rolling run {} aborted: {}", rollout_id, reason);
+                    log(&state, rollout_id, format!("Aborted: {}; {} clients in {} batches not run", reason, remaining, total - i - 1));
+                    status = "aborted";
+                    break;
+                }
+            }
+        }
+
+        if status == "completed" && failed > 0 {
+            status = "failed";
+        }
+        info!("Rolling run {} finished: {} succeeded, {} failed, status {}", rollout_id, succeeded, failed, status);
+        if let Some(mut progress) = state.active_executions.get_mut(&rollout_id) {
+            progress.status = status.to_string();
+        }
+        events::execution_updated(&state, rollout_id, None);
+
+        // Same grace period as single executions so the monitor sees the final state
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        state.active_executions.remove(&rollout_id);
+    });
+
+    rollout_id
+}
+
+fn log(state: &AppState, rollout_id: Uuid, msg: String) {
+    if let Some(mut progress) = state.active_executions.get_mut(&rollout_id) {
+        progress.logs.push(msg.clone());
+    }
+    events::execution_updated(state, rollout_id, Some(msg));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(batch_size: Option<usize>, batch_percent: Option<u32>) -> RolloutOptions {
+        RolloutOptions { batch_size, batch_percent, ..Default::default() }
+    }
+
+    #[test]
+    fn batch_percent_rounds_up() {
+        assert_eq!(options(None, Some(25)).batch_len(10), 3);
+        assert_eq!(options(None, Some(50)).batch_len(10), 5);
+        assert_eq!(options(None, Some(1)).batch_len(3), 1);
+        assert_eq!(options(None, Some(100)).batch_len(7), 7);
+        // Never zero, so chunking an empty group doesn't panic
+        assert_eq!(options(None, Some(10)).batch_len(0), 1);
+        assert_eq!(options(None, None).batch_len(0), 1);
+    }
+
+    #[test]
+    fn batch_larger_than_the_group_is_one_batch() {
+        let members: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let len = options(Some(10), None).batch_len(members.len());
+        assert_eq!(members.chunks(len).count(), 1);
+        assert_eq!(members.chunks(options(Some(2), None).batch_len(members.len())).count(), 2);
+    }
+
+    #[test]
+    fn failure_limits_are_exceeded_only_above_the_threshold() {
+        let count = RolloutOptions { max_failures: Some(2), ..options(Some(1), None) };
+        assert!(count.exceeded(2, 10).is_none());
+        assert!(count.exceeded(3, 10).is_some());
+
+        let percent = RolloutOptions { max_failure_percent: Some(20), ..options(Some(1), None) };
+        assert!(percent.exceeded(2, 10).is_none());
+        assert!(percent.exceeded(3, 10).is_some());
+        // 1 of 3 is 33%, above 20
+        assert!(percent.exceeded(1, 3).is_some());
+
+        let strict = RolloutOptions { max_failure_percent: Some(0), ..options(Some(1), None) };
+        assert!(strict.exceeded(0, 10).is_none());
+        assert!(strict.exceeded(1, 10).is_some());
+        assert!(options(Some(1), None).exceeded(10, 10).is_none());
+    }
+
+    #[test]
+    fn validate_rejects_conflicting_or_empty_batches() {
+        assert!(options(Some(2), None).validate().is_ok());
+        assert!(options(None, Some(100)).validate().is_ok());
+        assert!(RolloutOptions::default().validate().is_ok());
+        assert!(options(Some(2), Some(50)).validate().is_err());
+        assert!(options(Some(0), None).validate().is_err());
+        assert!(options(None, Some(0)).validate().is_err());
+        assert!(options(None, Some(101)).validate().is_err());
+        assert!(RolloutOptions { max_failure_percent: Some(101), ..options(Some(1), None) }.validate().is_err());
+        assert!(RolloutOptions { max_failures: Some(1), ..Default::default() }.validate().is_err());
+        assert!(RolloutOptions { batch_pause_sec: 5, ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::handlers;
+use crate::state::AppState;
+use crate::template;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    // Don't start a run while the previous one is still going
+    #[default]
+    Skip,
+    // Start it as soon as the previous one has finished
+    Queue,
+    Parallel,
+}
+
+// What to do with runs that fell due while the server was down
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRunPolicy {
+    #[default]
+    Skip,
+    RunOnce,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ScheduleTarget {
+    Script { script_id: Uuid, client_ids: Vec<Uuid> },
+    Group { group_id: Uuid },
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Schedule {
+    pub id: Uuid,
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub target: ScheduleTarget,
+    pub params: HashMap<String, serde_json::Value>,
+    pub overlap_policy: OverlapPolicy,
+    pub missed_run_policy: MissedRunPolicy,
+    pub enabled: bool,
+    pub last_run_at: Option<String>,
+    pub next_run_at: Option<String>,
+    pub created_at: Option<String>,
+}
+
+/// Parses a cron expression. Standard 5-field expressions are accepted and run at second 0,
+/// 6/7-field expressions add seconds and an optional year. Weekdays count from Sunday in all.
+pub fn parse_cron(expr: &str) -> Result<cron::Schedule, String> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let normalized = match fields[..] {
+        [minute, hour, day, month, weekday] => format!("0 {} {} {} {} {}", minute, hour, day, month, crontab_weekdays(weekday)),
+        [second, minute, hour, day, month, weekday] => format!("{} {} {} {} {} {}", second, minute, hour, day, month, crontab_weekdays(weekday)),
+        [second, minute, hour, day, month, weekday, year] => {
+            format!("{} {} {} {} {} {} {}", second, minute, hour, day, month, crontab_weekdays(weekday), year)
+        }
+        _ => expr.trim().to_string(),
+    };
+    cron::Schedule::from_str(&normalized)
+        .map_err(|e| format!("Invalid cron expression '{}': {}", expr, e.to_string().lines().next().unwrap_or_default()))
+}
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// Crontab numbers weekdays 0-7 from Sunday (0 and 7 both), the cron crate 1-7, so numbers
+// are rewritten as names, which both read the same. Items that aren't plain numbers, ranges
+// or steps are left for the crate to accept or reject.
+fn crontab_weekdays(field: &str) -> String {
+    let items: Vec<String> = field.split(',').map(|item| {
+        let (base, step) = match item.split_once('/') {
+            Some((base, step)) => (base, step.parse::<usize>().ok().filter(|s| *s > 0)),
+            None => (item, Some(1)),
+        };
+        let bounds = match base.split_once('-') {
+            Some((from, to)) => Some((from, to)),
+            None if base == "*" && item != base => Some(("0", "6")),
+            // "N/step" runs from N to the end of the week
+            None if item != base => Some((base, "6")),
+            None => Some((base, base)),
+        };
+        let days = bounds.zip(step).and_then(|((from, to), step)| {
+            let (from, to) = (from.parse::<usize>().ok()?, to.parse::<usize>().ok()?);
+            (from <= to && to <= 7).then(|| (from..=to).step_by(step).map(|d| WEEKDAYS[d % 7]).collect::<Vec<_>>().join(","))
+        });
+        days.unwrap_or_else(|| item.to_string())
+    }).collect();
+    items.join(",")
+}
+
+pub fn parse_timezone(tz: &str) -> Result<Tz, String> {
+    tz.parse::<Tz>().map_err(|_| format!("Unknown time zone '{}'", tz))
+}
+
+/// Next fire time strictly after `after`, evaluated in the schedule's time zone.
+pub fn next_run(expr: &str, tz: &str, after: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, String> {
+    let schedule = parse_cron(expr)?;
+    let tz = parse_timezone(tz)?;
+    Ok(schedule.after(&after.with_timezone(&tz)).next().map(|t| t.with_timezone(&Utc)))
+}
+
+pub fn format_ts(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn parse_ts(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc))
+}
+
+const MISSED_RUN_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+const SELECT_SCHEDULES: &str = "SELECT id, name, cron, timezone, target_type, target_id, client_ids, params, overlap_policy, missed_run_policy, enabled, last_run_at, next_run_at, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at FROM schedules";
+
+fn schedule_from_row(r: &sqlx::sqlite::SqliteRow) -> Schedule {
+    let target_id = Uuid::parse_str(&r.get::<String, _>("target_id")).unwrap_or_default();
+    let target = if r.get::<String, _>("target_type") == "group" {
+        ScheduleTarget::Group { group_id: target_id }
+    } else {
+        ScheduleTarget::Script {
+            script_id: target_id,
+            client_ids: r.get::<Option<String>, _>("client_ids").as_deref().and_then(|c| serde_json::from_str(c).ok()).unwrap_or_default(),
+        }
+    };
+    let enum_col = |name: &str| serde_json::Value::String(r.get::<Option<String>, _>(name).unwrap_or_default());
+    Schedule {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        name: r.get("name"),
+        cron: r.get("cron"),
+        timezone: r.get("timezone"),
+        target,
+        params: r.get::<Option<String>, _>("params").as_deref().and_then(|p| serde_json::from_str(p).ok()).unwrap_or_default(),
+        overlap_policy: serde_json::from_value(enum_col("overlap_policy")).unwrap_or_default(),
+        missed_run_policy: serde_json::from_value(enum_col("missed_run_policy")).unwrap_or_default(),
+        enabled: r.get::<i64, _>("enabled") != 0,
+        last_run_at: r.get("last_run_at"),
+        next_run_at: r.get("next_run_at"),
+        created_at: r.get("created_at"),
+    }
+}
+
+pub async fn list(db: &Pool<Sqlite>) -> Result<Vec<Schedule>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{} ORDER BY created_at DESC", SELECT_SCHEDULES)).fetch_all(db).await?;
+    Ok(rows.iter().map(schedule_from_row).collect())
+}
+
+pub async fn get(db: &Pool<Sqlite>, id: Uuid) -> Result<Option<Schedule>, sqlx::Error> {
+    let row = sqlx::query(&format!("{} WHERE id = ?", SELECT_SCHEDULES))
+        .bind(id.to_string())
+        .fetch_optional(db)
+        .await?;
+    Ok(row.as_ref().map(schedule_from_row))
+}
+
+/// Inserts or replaces a schedule, recomputing its next run from now.
+pub async fn save(db: &Pool<Sqlite>, schedule: &Schedule) -> Result<(), String> {
+    let next = if schedule.enabled {
+        next_run(&schedule.cron, &schedule.timezone, Utc::now())?.map(format_ts)
+    } else {
+        None
+    };
+    let (target_type, target_id, client_ids) = match &schedule.target {
+        ScheduleTarget::Script { script_id, client_ids } => ("script", script_id, serde_json::to_string(client_ids).ok()),
+        ScheduleTarget::Group { group_id } => ("group", group_id, None),
+    };
+    let policy_str = |v: serde_json::Value| v.as_str().unwrap_or_default().to_string();
+
+    sqlx::query(
+        r#"INSERT INTO schedules (id, name, cron, timezone, target_type, target_id, client_ids, params, overlap_policy, missed_run_policy, enabled, next_run_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT(id) DO UPDATE SET name = excluded.name, cron = excluded.cron, timezone = excluded.timezone,
+               target_type = excluded.target_type, target_id = excluded.target_id, client_ids = excluded.client_ids,
+               params = excluded.params, overlap_policy = excluded.overlap_policy, missed_run_policy = excluded.missed_run_policy,
+               enabled = excluded.enabled, next_run_at = excluded.next_run_at"#
+    )
+        .bind(schedule.id.to_string())
+        .bind(&schedule.name)
+        .bind(&schedule.cron)
+        .bind(&schedule.timezone)
+        .bind(target_type)
+        .bind(target_id.to_string())
+        .bind(client_ids)
+        .bind(serde_json::to_string(&schedule.params).unwrap_or("{}".to_string()))
+        .bind(policy_str(serde_json::to_value(schedule.overlap_policy).unwrap_or_default()))
+        .bind(policy_str(serde_json::to_value(schedule.missed_run_policy).unwrap_or_default()))
+        .bind(schedule.enabled as i64)
+        .bind(next)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Background loop: handles runs missed while the server was down, then fires due schedules.
+pub async fn run(state: Arc<AppState>) {
+    recover_missed_runs(&state).await;
+
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if let Err(e) = tick(&state).await {
+            error!("Scheduler tick failed: {}", e);
+        }
+    }
+}
+
+async fn tick(state: &Arc<AppState>) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let rows = sqlx::query(&format!("{} WHERE enabled = 1 AND next_run_at IS NOT NULL AND next_run_at <= ?", SELECT_SCHEDULES))
+        .bind(format_ts(now))
+        .fetch_all(&state.db)
+        .await?;
+
+    for schedule in rows.iter().map(schedule_from_row) {
+        advance(state, &schedule, now).await?;
+        trigger(state.clone(), schedule);
+    }
+    Ok(())
+}
+
+// Moves next_run_at past `now` and records the run time
+async fn advance(state: &AppState, schedule: &Schedule, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    let next = match next_run(&schedule.cron, &schedule.timezone, now) {
+        Ok(next) => next.map(format_ts),
+        Err(e) => {
+            warn!("Disabling schedule {} ({}): {}", schedule.name, schedule.id, e);
+            None
+        }
+    };
+    sqlx::query("UPDATE schedules SET next_run_at = ?, last_run_at = ? WHERE id = ?")
+        .bind(next)
+        .bind(format_ts(now))
+        .bind(schedule.id.to_string())
+        .execute(&state.db)
+        .await?;
+    Ok(())
+}
+
+async fn recover_missed_runs(state: &Arc<AppState>) {
+    let now = Utc::now();
+    let rows = match sqlx::query(&format!("{} WHERE enabled = 1 AND next_run_at IS NOT NULL AND next_run_at <= ?", SELECT_SCHEDULES))
+        .bind(format_ts(now))
+        .fetch_all(&state.db)
+        .await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to load schedules: {}", e);
+            return;
+        }
+    };
+
+    for schedule in rows.iter().map(schedule_from_row) {
+        let missed = count_missed(&schedule, now);
+        warn!("Schedule {} missed {} run(s) while the server was down (policy: {:?})", schedule.name, missed, schedule.missed_run_policy);
+
+        if schedule.missed_run_policy == MissedRunPolicy::RunOnce {
+            if let Err(e) = advance(state, &schedule, now).await {
+                error!("Failed to update schedule {}: {}", schedule.id, e);
+            }
+            // Give clients time to reconnect after the restart before catching up
+            let state = state.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(MISSED_RUN_DELAY).await;
+                trigger(state, schedule);
+            });
+        } else {
+            let next = next_run(&schedule.cron, &schedule.timezone, now).ok().flatten().map(format_ts);
+            let _ = sqlx::query("UPDATE schedules SET next_run_at = ? WHERE id = ?")
+                .bind(next)
+                .bind(schedule.id.to_string())
+                .execute(&state.db)
+                .await;
+        }
+    }
+}
+
+fn count_missed(schedule: &Schedule, now: DateTime<Utc>) -> usize {
+    let (Some(first), Ok(cron), Ok(tz)) = (
+        schedule.next_run_at.as_deref().and_then(parse_ts),
+        parse_cron(&schedule.cron),
+        parse_timezone(&schedule.timezone),
+    ) else {
+        return 1;
+    };
+    // The stored next run itself is missed, plus every fire time after it up to now (bounded)
+    1 + cron.after(&first.with_timezone(&tz))
+        .take_while(|t| t.with_timezone(&Utc) <= now)
+        .take(10_000)
+        .count()
+}
+
+/// Starts a run of the schedule, honouring its overlap policy.
+pub fn trigger(state: Arc<AppState>, schedule: Schedule) {
+    let lock = state.schedule_locks.entry(schedule.id).or_default().clone();
+
+    match schedule.overlap_policy {
+        OverlapPolicy::Parallel => {
+            tokio::spawn(execute(state, schedule));
+        }
+        OverlapPolicy::Skip => match lock.try_lock_owned() {
+            Ok(guard) => {
+                tokio::spawn(async move {
+                    execute(state, schedule).await;
+                    drop(guard);
+                });
+            }
+            Err(_) => info!("Schedule {} is still running, skipping this run", schedule.name),
+        },
+        OverlapPolicy::Queue => {
+            tokio::spawn(async move {
+                let _guard = lock.lock_owned().await;
+                execute(state, schedule).await;
+            });
+        }
+    }
+}
+
+// Runs the target on every online client and waits until all of them are done
+async fn execute(state: Arc<AppState>, schedule: Schedule) {
+    info!("Running schedule {} ({})", schedule.name, schedule.id);
+    let host = format!("{}:{}", state.config.host, state.config.port);
+
+    match &schedule.target {
+        ScheduleTarget::Script { script_id, client_ids } => {
+            let script = match handlers::fetch_script(&state, &script_id.to_string()).await {
+                Ok(Some(s)) => s,
+                Ok(None) => return warn!("Schedule {}: script {} no longer exists", schedule.name, script_id),
+                Err(e) => return error!("Schedule {}: failed to load script: {}", schedule.name, e),
+            };
+            let params = match template::resolve_params(&script.params, &schedule.params) {
+                Ok(p) => p,
+                Err(e) => return warn!("Schedule {}: {}", schedule.name, e),
+            };
+
+            let runs = client_ids.iter()
+                .filter(|id| state.clients.contains_key(id))
+                .map(|id| handlers::run_script_on_client(state.clone(), *id, script.clone(), params.clone(), host.clone(), Some(schedule.id)));
+            futures::future::join_all(runs).await;
+        }
+        ScheduleTarget::Group { group_id } => {
+            let group_run = match handlers::prepare_group_run(&state, *group_id, &schedule.params).await {
+                Ok(r) => r,
+                Err((_, e)) => return warn!("Schedule {}: {}", schedule.name, e),
+            };
+            let runs = Arc::new(group_run.runs);
+
+            let members = group_run.members.into_iter()
+                .filter(|id| state.clients.contains_key(id))
+                .map(|id| handlers::run_group_on_client(state.clone(), id, runs.clone(), host.clone(), Some(schedule.id)));
+            futures::future::join_all(members).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone, Weekday};
+
+    fn fire_days(expr: &str) -> Vec<Weekday> {
+        // The week from Sunday 2024-01-07, midnight included
+        let start = Utc.with_ymd_and_hms(2024, 1, 6, 23, 59, 59).unwrap();
+        let mut days: Vec<Weekday> = parse_cron(expr).unwrap().after(&start).take(2000)
+            .take_while(|t| *t < start + chrono::Duration::days(7))
+            .map(|t| t.weekday())
+            .collect();
+        days.dedup();
+        days
+    }
+
+    #[test]
+    fn crontab_weekdays_count_from_sunday() {
+        use Weekday::*;
+        assert_eq!(fire_days("0 9 * * 1-5"), vec![Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(fire_days("* * * * 0"), vec![Sun]);
+        assert_eq!(fire_days("0 0 * * 7"), vec![Sun]);
+        assert_eq!(fire_days("0 0 * * 5-7"), vec![Sun, Fri, Sat]);
+        assert_eq!(fire_days("0 0 * * */2"), vec![Sun, Tue, Thu, Sat]);
+        assert_eq!(fire_days("0 0 * * 1,3"), vec![Mon, Wed]);
+        assert_eq!(fire_days("0 0 * * MON-FRI"), vec![Mon, Tue, Wed, Thu, Fri]);
+    }
+
+    #[test]
+    fn weekdays_read_the_same_with_seconds() {
+        use Weekday::*;
+        assert_eq!(fire_days("0 9 * * 1"), vec![Mon]);
+        assert_eq!(fire_days("0 0 9 * * 1"), fire_days("0 9 * * 1"));
+        assert_eq!(fire_days("0 0 9 * * 1 2024"), vec![Mon]);
+        assert_eq!(fire_days("0 0 0 * * 0,7"), vec![Sun]);
+    }
+
+    #[test]
+    fn five_fields_fire_at_second_zero() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 8, 8, 30, 15).unwrap();
+        let next = next_run("0 9 * * 1-5", "UTC", start).unwrap().unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 1, 8, 9, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn six_fields_pass_through() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 8, 0, 0, 0).unwrap();
+        let next = next_run("30 * * * * *", "UTC", start).unwrap().unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 1, 8, 0, 0, 30).unwrap());
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(parse_cron("* * * * 8").is_err());
+        assert!(parse_cron("* * * *").is_err());
+        assert!(parse_cron("61 * * * *").is_err());
+        assert!(parse_timezone("Mars/Olympus").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Row, Sqlite};
+use std::collections::HashMap;
+
+use crate::config::ServerConfig;
+use crate::template;
+
+pub const MASK: &str = "******";
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// Marks values encrypted with an HKDF-derived key; older values used a bare SHA-256 of the passphrase
+const FORMAT_PREFIX: &str = "v2:";
+
+/// AES-256-GCM cipher for values in the `secrets` table (and other encrypted columns).
+/// Each value gets its own key, derived from the secrets key with HKDF-SHA256 and a random salt.
+/// Stored format is "v2:" + hex(salt || nonce || ciphertext).
+pub struct SecretCipher {
+    key: Vec<u8>,
+}
+
+impl SecretCipher {
+    pub fn new(secrets_key: &str) -> Self {
+        Self { key: secrets_key.as_bytes().to_vec() }
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Aes256Gcm {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(salt), &self.key)
+            .expand(b"roam secrets", &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher(&salt).encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to encrypt secret: {}", e))?;
+        let mut out = salt.to_vec();
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", FORMAT_PREFIX, hex::encode(out)))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> anyhow::Result<String> {
+        let Some(encoded) = stored.strip_prefix(FORMAT_PREFIX) else {
+            anyhow::bail!("Stored secret uses the old key derivation and could not be migrated");
+        };
+        let data = hex::decode(encoded)?;
+        if data.len() < SALT_LEN + NONCE_LEN {
+            anyhow::bail!("Stored secret is truncated");
+        }
+        let (salt, rest) = data.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plaintext = self.cipher(salt).decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret (wrong secrets_key?)"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+// Values written before "v2:": AES-256-GCM keyed with SHA-256(passphrase), hex(nonce || ciphertext)
+fn decrypt_legacy(passphrase: &str, stored: &str) -> Option<String> {
+    let digest = Sha256::digest(passphrase.as_bytes());
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&digest));
+    let data = hex::decode(stored).ok().filter(|d| d.len() >= NONCE_LEN)?;
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+/// Returns `secrets_key`, or the key in `secrets_key_file`. Without either, a random key is
+/// generated and written to that file, readable by the owner only.
+pub fn load_key(config: &ServerConfig) -> anyhow::Result<String> {
+    if let Some(key) = config.secrets_key.as_deref().filter(|k| !k.is_empty()) {
+        return Ok(key.to_string());
+    }
+    let path = std::path::Path::new(&config.secrets_key_file);
+    match std::fs::read_to_string(path) {
+        Ok(key) if !key.trim().is_empty() => return Ok(key.trim().to_string()),
+        Ok(_) => anyhow::bail!("Secrets key file {} is empty", path.display()),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            anyhow::bail!("Failed to read secrets key file {}: {}", path.display(), e)
+        }
+        Err(_) => {}
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let key = hex::encode(bytes);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to create secrets key file {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, key.as_bytes())?;
+    tracing::warn!("Generated a new secrets key in {}; back it up, stored secrets can't be decrypted without it", path.display());
+    Ok(key)
+}
+
+/// Re-encrypts values stored with the old SHA-256 key derivation. They were keyed with
+/// `secrets_key` or, when that was unset, `web_jwt_secret`.
+pub async fn migrate_legacy(db: &Pool<Sqlite>, cipher: &SecretCipher, config: &ServerConfig) {
+    let mut passphrases: Vec<&str> = config.secrets_key.as_deref().into_iter().collect();
+    passphrases.push(&config.web_jwt_secret);
+
+    for (table, key, column) in [("secrets", "name", "value"), ("webhooks", "id", "secret")] {
+        let select = format!("SELECT {key}, {column} FROM {table} WHERE {column} IS NOT NULL AND {column} NOT LIKE 'v2:%'");
+        let rows = match sqlx::query(&select).fetch_all(db).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                tracing::error!("Failed to load {} for key migration: {}", table, e);
+                continue;
+            }
+        };
+        for row in rows {
+            let id: String = row.get(0);
+            let stored: String = row.get(1);
+            let migrated = passphrases.iter()
+                .find_map(|p| decrypt_legacy(p, &stored))
+                .and_then(|plaintext| cipher.encrypt(&plaintext).ok());
+            let Some(migrated) = migrated else {
+                tracing::warn!("Could not migrate encrypted {} of {} '{}' to the new key derivation", column, table, id);
+                continue;
+            };
+            let update = format!("UPDATE {table} SET {column} = ? WHERE {key} = ?");
+            if let Err(e) = sqlx::query(&update).bind(migrated).bind(&id).execute(db).await {
+                tracing::error!("Failed to store migrated {} of {} '{}': {}", column, table, id, e);
+            }
+        }
+    }
+}
+
+/// Decrypts every secret referenced as `{{secret:NAME}}` in the given value, by name.
+/// Load them from the stored template, before anything else is substituted into it,
+/// so a parameter or captured output can't pull in a secret.
+pub async fn load<T>(db: &Pool<Sqlite>, cipher: &SecretCipher, value: &T) -> Result<HashMap<String, String>, String>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut plaintexts = HashMap::new();
+    for name in template::secret_references(value)? {
+        if plaintexts.contains_key(&name) {
+            continue;
+        }
+        let row = sqlx::query("SELECT value FROM secrets WHERE name = ?")
+            .bind(&name)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| format!("Failed to load secret '{}': {}", name, e))?
+            .ok_or_else(|| format!("Unknown secret '{}'", name))?;
+        let plaintext = cipher.decrypt(&row.get::<String, _>("value"))
+            .map_err(|e| format!("Secret '{}': {}", name, e))?;
+        plaintexts.insert(name, plaintext);
+    }
+    Ok(plaintexts)
+}
+
+/// Masks every known secret value in a piece of text.
+pub fn mask(text: &str, secrets: &[String]) -> String {
+    let mut sorted: Vec<&String> = secrets.iter().filter(|s| !s.is_empty()).collect();
+    // Longest first so a secret that contains another is masked as a whole
+    sorted.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    let mut out = text.to_string();
+    for secret in sorted {
+        out = out.replace(secret.as_str(), MASK);
+    }
+    out
+}
+
+/// Masks every known secret value in all strings of a serializable value. Fails when the
+/// masked value no longer deserializes; callers must then drop the value, not keep the original.
+pub fn mask_value<T>(value: &T, secrets: &[String]) -> Result<T, String>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    template::map_strings(value, |s| Ok(mask(s, secrets)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::CommandResult;
+
+    #[test]
+    fn mask_replaces_every_occurrence() {
+        let secrets = vec!["hunter2".to_string()];
+        assert_eq!(mask("pw=hunter2, again hunter2", &secrets), "pw=******, again ******");
+        assert_eq!(mask("nothing here", &secrets), "nothing here");
+    }
+
+    #[test]
+    fn mask_prefers_the_longest_secret() {
+        let secrets = vec!["abc".to_string(), "abcdef".to_string(), String::new()];
+        assert_eq!(mask("x abcdef y abc", &secrets), "x ****** y ******");
+    }
+
+    #[test]
+    fn mask_value_covers_nested_strings() {
+        let result = CommandResult::ShellOutput {
+            stdout: "token hunter2".into(),
+            stderr: "bad hunter2".into(),
+            exit_code: 1,
+            cwd: "/tmp".into(),
+        };
+        let masked = mask_value(&result, &["hunter2".to_string()]).unwrap();
+        let CommandResult::ShellOutput { stdout, stderr, exit_code, cwd } = masked else { panic!("variant changed") };
+        assert_eq!((stdout.as_str(), stderr.as_str(), exit_code, cwd.as_str()), ("token ******", "bad ******", 1, "/tmp"));
+    }
+
+    #[test]
+    fn mask_value_fails_instead_of_returning_the_secret() {
+        // Masking turns the number-like secret into text, which no longer fits the field
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Port {
+            port: std::net::SocketAddr,
+        }
+        let value = Port { port: "127.0.0.1:4242".parse().unwrap() };
+        assert!(mask_value(&value, &["4242".to_string()]).is_err());
+    }
+
+    #[test]
+    fn cipher_round_trip() {
+        let cipher = SecretCipher::new("key");
+        let stored = cipher.encrypt("hunter2").unwrap();
+        assert!(stored.starts_with(FORMAT_PREFIX));
+        // Salt and nonce are random, so equal plaintexts don't give equal ciphertexts
+        assert_ne!(stored, cipher.encrypt("hunter2").unwrap());
+        assert_eq!(cipher.decrypt(&stored).unwrap(), "hunter2");
+        assert!(SecretCipher::new("other").decrypt(&stored).is_err());
+        assert!(cipher.decrypt("v2:00").is_err());
+    }
+
+    #[test]
+    fn legacy_values_need_migration() {
+        let digest = Sha256::digest(b"roam-secret-key");
+        let legacy = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&digest));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut data = nonce.to_vec();
+        data.extend(legacy.encrypt(&nonce, b"hunter2".as_ref()).unwrap());
+        let stored = hex::encode(data);
+
+        assert!(SecretCipher::new("roam-secret-key").decrypt(&stored).is_err());
+        assert_eq!(decrypt_legacy("roam-secret-key", &stored).as_deref(), Some("hunter2"));
+        assert_eq!(decrypt_legacy("other", &stored), None);
+    }
+
+    #[test]
+    fn generated_key_is_persisted_privately() {
+        let path = std::env::temp_dir().join(format!("roam-secrets-{}.key", uuid::Uuid::new_v4()));
+        let mut config = crate::config::ServerConfig::new().unwrap();
+        config.secrets_key = None;
+        config.secrets_key_file = path.to_string_lossy().to_string();
+
+        let key = load_key(&config).unwrap();
+        assert_eq!(key.len(), 64);
+        assert_eq!(load_key(&config).unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        config.secrets_key = Some("configured".to_string());
+        assert_eq!(load_key(&config).unwrap(), "configured");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn secret_names() {
+        assert!(is_valid_name("DB_PASSWORD.prod-1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("a b"));
+        assert!(!is_valid_name(&"x".repeat(129)));
+    }
+}
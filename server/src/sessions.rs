@@ -0,0 +1,227 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Row, Sqlite};
+use std::collections::BTreeMap;
+use tracing::error;
+use uuid::Uuid;
+
+// One row per WebSocket connection of a client. Timestamps are UTC, '%Y-%m-%dT%H:%M:%SZ', so
+// they compare as text.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+// Disconnect reason of a socket superseded by a reconnect of the same client
+pub const REPLACED: &str = "replaced by a new connection";
+
+fn format_time(t: DateTime<Utc>) -> String {
+    t.format(TIME_FORMAT).to_string()
+}
+
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    chrono::NaiveDateTime::parse_from_str(s, TIME_FORMAT).ok().map(|t| t.and_utc())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub connected_at: String,
+    // None while the session is still open
+    pub disconnected_at: Option<String>,
+    pub remote_ip: Option<String>,
+    pub client_version: Option<String>,
+    pub disconnect_reason: Option<String>,
+    pub duration_sec: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Uptime {
+    pub client_id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub online_sec: i64,
+    pub offline_sec: i64,
+    // Share of the window the client was connected, 0-100
+    pub availability_percent: f64,
+    pub sessions: usize,
+    pub disconnects: usize,
+    // Counted by the part before ':', e.g. "heartbeat timeout"
+    pub disconnect_reasons: BTreeMap<String, usize>,
+}
+
+pub async fn open(db: &Pool<Sqlite>, session_id: Uuid, client_id: Uuid, remote_ip: &str, client_version: &str) {
+    if let Err(e) = sqlx::query("INSERT INTO client_sessions (id, client_id, connected_at, remote_ip, client_version) VALUES (?, ?, ?, ?, ?)")
+        .bind(session_id.to_string())
+        .bind(client_id.to_string())
+        .bind(format_time(Utc::now()))
+        .bind(remote_ip)
+        .bind(client_version)
+        .execute(db)
+        .await
+    {
+        error!("Failed to record session of client {}: {}", client_id, e);
+    }
+}
+
+pub async fn close(db: &Pool<Sqlite>, session_id: Uuid, reason: &str) {
+    if let Err(e) = sqlx::query("UPDATE client_sessions SET disconnected_at = ?, disconnect_reason = ? WHERE id = ? AND disconnected_at IS NULL")
+        .bind(format_time(Utc::now()))
+        .bind(reason)
+        .bind(session_id.to_string())
+        .execute(db)
+        .await
+    {
+        error!("Failed to close session {}: {}", session_id, e);
+    }
+}
+
+/// Sessions of a client overlapping `[from, to]`, newest first.
+pub async fn list(db: &Pool<Sqlite>, client_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>, limit: Option<i64>) -> Result<Vec<Session>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT id, connected_at, disconnected_at, remote_ip, client_version, disconnect_reason FROM client_sessions
+         WHERE client_id = ? AND connected_at <= ? AND (disconnected_at IS NULL OR disconnected_at >= ?)
+         ORDER BY connected_at DESC LIMIT ?"
+    )
+        .bind(client_id.to_string())
+        .bind(format_time(to))
+        .bind(format_time(from))
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(db)
+        .await?;
+
+    let now = Utc::now();
+    Ok(rows.iter().map(|r| {
+        let connected_at: String = r.get("connected_at");
+        let disconnected_at: Option<String> = r.get("disconnected_at");
+        let start = parse_time(&connected_at).unwrap_or(now);
+        let end = disconnected_at.as_deref().and_then(parse_time).unwrap_or(now);
+        Session {
+            id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+            client_id,
+            connected_at,
+            disconnected_at,
+            remote_ip: r.get("remote_ip"),
+            client_version: r.get("client_version"),
+            disconnect_reason: r.get("disconnect_reason"),
+            duration_sec: (end - start).num_seconds().max(0),
+        }
+    }).collect())
+}
+
+/// Connected time of a client within `[from, to]`. Overlapping sessions (a reconnect before the
+/// old socket was noticed as dead) are counted once.
+pub async fn uptime(db: &Pool<Sqlite>, client_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Uptime, sqlx::Error> {
+    let sessions = list(db, client_id, from, to, None).await?;
+    let now = Utc::now();
+
+    let mut spans: Vec<(DateTime<Utc>, DateTime<Utc>)> = sessions.iter().filter_map(|s| {
+        let start = parse_time(&s.connected_at)?.max(from);
+        let end = s.disconnected_at.as_deref().and_then(parse_time).unwrap_or(now).min(to);
+        (end > start).then_some((start, end))
+    }).collect();
+    spans.sort();
+
+    let mut online_sec = 0;
+    let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    for (start, end) in spans {
+        current = match current {
+            Some((s, e)) if start <= e => Some((s, e.max(end))),
+            Some((s, e)) => {
+                online_sec += (e - s).num_seconds();
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((s, e)) = current {
+        online_sec += (e - s).num_seconds();
+    }
+
+    // Disconnects that happened inside the window; replaced sockets aren't real drops
+    let mut disconnect_reasons = BTreeMap::new();
+    for s in &sessions {
+        let Some(end) = s.disconnected_at.as_deref().and_then(parse_time) else { continue };
+        let reason = s.disconnect_reason.as_deref().unwrap_or("unknown");
+        if end >= from && end <= to && reason != REPLACED {
+            let kind = reason.split(':').next().unwrap_or(reason).trim().to_string();
+            *disconnect_reasons.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    let total_sec = (to - from).num_seconds().max(0);
+    Ok(Uptime {
+        client_id,
+        from: format_time(from),
+        to: format_time(to),
+        online_sec,
+        offline_sec: (total_sec - online_sec).max(0),
+        availability_percent: if total_sec > 0 { (online_sec as f64 * 10000.0 / total_sec as f64).round() / 100.0 } else { 0.0 },
+        sessions: sessions.len(),
+        disconnects: disconnect_reasons.values().sum(),
+        disconnect_reasons,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    async fn test_db() -> (Pool<Sqlite>, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("roam-sessions-{}", Uuid::new_v4()));
+        let state = crate::state::AppState::for_tests(&dir, crate::config::ServerConfig::new().unwrap()).await;
+        (state.db.clone(), dir)
+    }
+
+    async fn insert(db: &Pool<Sqlite>, client_id: Uuid, connected_at: &str, disconnected_at: Option<&str>, reason: Option<&str>) {
+        sqlx::query("INSERT INTO client_sessions (id, client_id, connected_at, disconnected_at, disconnect_reason) VALUES (?, ?, ?, ?, ?)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(client_id.to_string())
+            .bind(connected_at)
+            .bind(disconnected_at)
+            .bind(reason)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, hour, minute, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn uptime_clips_to_the_window_and_counts_overlaps_once() {
+        let (db, dir) = test_db().await;
+        let client_id = Uuid::new_v4();
+        // Before the window
+        insert(&db, client_id, "2024-01-01T08:00:00Z", Some("2024-01-01T09:00:00Z"), Some("heartbeat timeout: 90s")).await;
+        // Straddles `from`, then replaced by a reconnect that overlaps it
+        insert(&db, client_id, "2024-01-01T09:30:00Z", Some("2024-01-01T10:30:00Z"), Some(REPLACED)).await;
+        insert(&db, client_id, "2024-01-01T10:20:00Z", Some("2024-01-01T11:00:00Z"), Some("heartbeat timeout: 90s")).await;
+        // Still open, straddles `to`
+        insert(&db, client_id, "2024-01-01T11:50:00Z", None, None).await;
+
+        let window = uptime(&db, client_id, at(10, 0), at(12, 0)).await.unwrap();
+        assert_eq!((window.online_sec, window.offline_sec), (70 * 60, 50 * 60));
+        assert_eq!(window.availability_percent, 58.33);
+        assert_eq!(window.sessions, 3);
+        assert_eq!(window.disconnects, 1);
+        assert_eq!(window.disconnect_reasons.get("heartbeat timeout"), Some(&1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uptime_of_a_session_covering_the_window() {
+        let (db, dir) = test_db().await;
+        let client_id = Uuid::new_v4();
+        insert(&db, client_id, "2024-01-01T09:00:00Z", None, None).await;
+        insert(&db, Uuid::new_v4(), "2024-01-01T10:00:00Z", None, None).await;
+
+        let covered = uptime(&db, client_id, at(10, 0), at(11, 0)).await.unwrap();
+        assert_eq!((covered.online_sec, covered.offline_sec, covered.availability_percent), (3600, 0, 100.0));
+        assert_eq!((covered.sessions, covered.disconnects), (1, 0));
+
+        let before = uptime(&db, client_id, at(7, 0), at(8, 0)).await.unwrap();
+        assert_eq!((before.online_sec, before.availability_percent, before.sessions), (0, 0.0, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,29 @@
 use dashmap::DashMap;
+use std::sync::Arc;
 use sqlx::{Pool, Sqlite};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 
 use common::{Message, CommandResult};
 use crate::config::ServerConfig;
+use crate::events::ServerEvent;
+use crate::secrets::SecretCipher;
 
 pub struct AppState {
     pub db: Pool<Sqlite>,
     pub clients: DashMap<Uuid, ClientConnection>,
-    pub results: DashMap<Uuid, CommandResult>,
+    pub results: DashMap<Uuid, (CommandResult, std::time::Instant)>, // bounded by result_ttl_sec and result_cache_size
     pub waiters: DashMap<Uuid, oneshot::Sender<CommandResult>>,
     pub active_executions: DashMap<Uuid, ExecutionProgress>,
     pub web_sessions: DashMap<String, String>, // token -> username
+    pub event_tickets: DashMap<String, std::time::Instant>, // single-use /api/events ticket -> issued at
+    // command id -> secret values to mask in its result, and when its wait timed out; kept for a
+    // late response until result_ttl_sec after that
+    pub command_secrets: DashMap<Uuid, (Vec<String>, Option<std::time::Instant>)>,
+    pub schedule_locks: DashMap<Uuid, Arc<tokio::sync::Mutex<()>>>, // schedule id -> held while a run is in progress
+    pub secret_cipher: SecretCipher,
+    pub events: broadcast::Sender<ServerEvent>,
     pub config: ServerConfig,
 }
 
@@ -38,13 +48,113 @@ pub struct ClientConnection {
     pub version: String,
     pub started_at: Option<chrono::DateTime<chrono::Utc>>,
     pub server_host: String,
+    // Identifies the socket, so a stale one can't remove the client after it reconnected
+    pub connection_id: Uuid,
+    // Any message from the client, heartbeat or pong, counts
+    pub last_heartbeat: std::time::Instant,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScriptGroup {
     pub id: Uuid,
     pub name: String,
-    pub steps: Vec<ScriptStep>,
+    pub steps: Vec<StepDefinition>,
+    #[serde(default)]
+    pub params: Vec<ScriptParam>,
+}
+
+// A step together with its execution options. Options are flattened next to
+// "type"/"payload" so steps saved before they existed still load.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StepDefinition {
+    #[serde(flatten)]
+    pub step: ScriptStep,
+    #[serde(flatten)]
+    pub options: StepOptions,
+    // Values captured from the result, referenced by later steps as {{outputs.NAME}}
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<StepOutput>,
+}
+
+// Captures the trimmed stdout by default, or the part selected by `regex`
+// (first capture group, else the whole match) or `json_path` (e.g. "$.items[0].name").
+// For non-shell steps the result data is used instead of stdout.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StepOutput {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StepOptions {
+    // Keep running the following steps when this one fails; the run is not marked failed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continue_on_error: bool,
+    // Extra attempts after a failure, waiting retry_delay_sec * 2^n between them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<StepCondition>,
+    // Shell exit codes treated as success, [0] when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_exit_codes: Option<Vec<i32>>,
+}
+
+// All given fields must match for the step to run
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StepCondition {
+    // Client OS as reported by the client ("linux", "windows", "macos")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<Vec<String>>,
+    // 1-based index of the earlier step the checks below refer to, the previous step by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<StepStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<Vec<i32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_contains: Option<String>,
+    // Regex matched against the referenced step's stdout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_matches: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+// Declared script parameter, referenced as {{name}} in step fields
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScriptParam {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub param_type: ParamType,
+    pub default: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    pub description: Option<String>,
+    // Restricts the value to one of these (any type)
+    pub options: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -58,10 +168,15 @@ pub enum ScriptStep {
     Copy { src_path: String, dest_path: String },
     Move { src_path: String, dest_path: String },
     Delete { path: String },
+    // Portable service control; the client maps it to systemctl, rc-service, sc, launchctl or service
+    Service { name: String, action: common::ServiceAction },
+    // Output is one relative path per line, or grep-style "path:line:text" for content matches
+    FindFiles(common::FindQuery),
+    SearchContent(common::SearchQuery),
 }
 
 impl AppState {
-    pub fn new(db: Pool<Sqlite>, config: ServerConfig) -> Self {
+    pub fn new(db: Pool<Sqlite>, config: ServerConfig, secret_cipher: SecretCipher) -> Self {
         Self {
             db,
             clients: DashMap::new(),
@@ -69,7 +184,20 @@ impl AppState {
             waiters: DashMap::new(),
             active_executions: DashMap::new(),
             web_sessions: DashMap::new(),
+            event_tickets: DashMap::new(),
+            command_secrets: DashMap::new(),
+            schedule_locks: DashMap::new(),
+            secret_cipher,
+            events: broadcast::channel(1024).0,
             config,
         }
     }
+
+    /// State over a fresh database in `dir`, for tests.
+    #[cfg(test)]
+    pub async fn for_tests(dir: &std::path::Path, config: ServerConfig) -> Arc<Self> {
+        std::fs::create_dir_all(dir).unwrap();
+        let db = crate::db::init_db(&format!("sqlite:{}", dir.join("roam.db").display())).await.unwrap();
+        Arc::new(Self::new(db, config, SecretCipher::new("test")))
+    }
 }
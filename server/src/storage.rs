@@ -0,0 +1,301 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+pub const UPLOADS_DIR: &str = "uploads";
+pub const STAGING_DIR: &str = "uploads/staging";
+pub const CLIENT_DATA_DIR: &str = "uploads/client_data";
+pub const UPDATES_DIR: &str = "uploads/updates";
+
+// Characters escaped inside a single URL path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'/').add(b'\\');
+
+#[derive(Debug)]
+pub enum StorageError {
+    InvalidPath(String),
+    NotFound(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::InvalidPath(msg) => write!(f, "Invalid path: {}", msg),
+            StorageError::NotFound(path) => write!(f, "Not found: {}", path),
+            StorageError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+/// Reduces an untrusted file name (e.g. from a multipart upload) to a single safe path component.
+pub fn sanitize_file_name(name: &str) -> Result<String, StorageError> {
+    // Browsers on Windows may send "C:\fakepath\file.txt", keep the last component only
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    if base.is_empty() || base == "." || base == ".." {
+        return Err(StorageError::InvalidPath(format!("'{}' is not a valid file name", name)));
+    }
+    if base.chars().any(|c| c.is_control() || c == ':') {
+        return Err(StorageError::InvalidPath(format!("'{}' contains forbidden characters", name)));
+    }
+    Ok(base.to_string())
+}
+
+/// Validates a user supplied path relative to a storage root.
+/// Only plain components are accepted: no `..`, no absolute paths, no drive prefixes.
+pub fn validate_relative_path(rel: &str) -> Result<PathBuf, StorageError> {
+    let normalized = rel.replace('\\', "/");
+    let trimmed = normalized.trim_matches('/');
+    if trimmed.is_empty() {
+        return Err(StorageError::InvalidPath("empty path".to_string()));
+    }
+    if normalized.starts_with('/') || trimmed.contains(':') || trimmed.contains('\0') {
+        return Err(StorageError::InvalidPath(format!("'{}' must be relative", rel)));
+    }
+
+    let mut path = PathBuf::new();
+    for component in Path::new(trimmed).components() {
+        match component {
+            Component::Normal(c) => path.push(c),
+            Component::CurDir => {}
+            _ => return Err(StorageError::InvalidPath(format!("'{}' escapes the storage directory", rel))),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(StorageError::InvalidPath(format!("'{}' is not a valid path", rel)));
+    }
+    Ok(path)
+}
+
+/// Joins a user supplied relative path onto `base` and makes sure the result stays inside it,
+/// also when an existing component is a symlink pointing elsewhere. Returns the resolved path.
+pub fn resolve_in(base: &str, rel: &str) -> Result<PathBuf, StorageError> {
+    let joined = Path::new(base).join(validate_relative_path(rel)?);
+    let root = match Path::new(base).canonicalize() {
+        Ok(root) => root,
+        // Nothing below a missing base can be a symlink yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(joined),
+        Err(e) => return Err(e.into()),
+    };
+
+    // The deepest existing ancestor decides where the path really leads; the missing
+    // components below it are appended as they are
+    let mut existing = joined.as_path();
+    let mut missing = Vec::new();
+    while existing.symlink_metadata().is_err() {
+        match (existing.file_name(), existing.parent()) {
+            (Some(name), Some(parent)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let escapes = || StorageError::InvalidPath(format!("'{}' escapes the storage directory", rel));
+    // A dangling symlink can't be canonicalized, writing through it would still land outside
+    let mut resolved = existing.canonicalize().map_err(|_| escapes())?;
+    if !resolved.starts_with(&root) {
+        return Err(escapes());
+    }
+    resolved.extend(missing.into_iter().rev());
+    Ok(resolved)
+}
+
+/// Percent-encodes each segment of a relative path for use in a download URL.
+pub fn url_path(rel: &str) -> String {
+    rel.replace('\\', "/")
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| utf8_percent_encode(s, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Relative path with forward slashes, as used in staging URLs and script steps.
+fn to_rel_string(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn modified_rfc3339(metadata: &std::fs::Metadata) -> Option<String> {
+    metadata.modified().ok().map(|t| {
+        chrono::DateTime::<chrono::Utc>::from(t).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+    })
+}
+
+// Staging file store (files uploaded by admins, served to clients)
+
+#[derive(Debug, Serialize)]
+pub struct StagingEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StagingMetadata {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<String>,
+    pub sha256: Option<String>,
+    pub entries: Option<usize>,
+}
+
+pub fn list_staging() -> Result<Vec<StagingEntry>, StorageError> {
+    let root = Path::new(STAGING_DIR);
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(root).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(|e| StorageError::Io(e.into()))?;
+        let metadata = entry.metadata().map_err(|e| StorageError::Io(e.into()))?;
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        entries.push(StagingEntry {
+            path: to_rel_string(rel),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: modified_rfc3339(&metadata),
+        });
+    }
+    Ok(entries)
+}
+
+pub fn staging_metadata(rel: &str) -> Result<StagingMetadata, StorageError> {
+    let path = resolve_in(STAGING_DIR, rel)?;
+    let metadata = std::fs::metadata(&path).map_err(|_| StorageError::NotFound(rel.to_string()))?;
+
+    let (size, sha256, entries) = if metadata.is_dir() {
+        let mut size = 0;
+        let mut count = 0;
+        for entry in WalkDir::new(&path).min_depth(1).into_iter().flatten() {
+            count += 1;
+            if let Ok(m) = entry.metadata() {
+                if m.is_file() {
+                    size += m.len();
+                }
+            }
+        }
+        (size, None, Some(count))
+    } else {
+        (metadata.len(), Some(sha256_file(&path)?), None)
+    };
+
+    Ok(StagingMetadata {
+        path: to_rel_string(&validate_relative_path(rel)?),
+        is_dir: metadata.is_dir(),
+        size,
+        modified: modified_rfc3339(&metadata),
+        sha256,
+        entries,
+    })
+}
+
+pub fn delete_staging(rel: &str) -> Result<(), StorageError> {
+    let path = resolve_in(STAGING_DIR, rel)?;
+    let metadata = std::fs::symlink_metadata(&path).map_err(|_| StorageError::NotFound(rel.to_string()))?;
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(&path)?;
+    } else {
+        std::fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String, StorageError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_keep_the_last_component() {
+        assert_eq!(sanitize_file_name("report.pdf").unwrap(), "report.pdf");
+        assert_eq!(sanitize_file_name("C:\\fakepath\\file.txt").unwrap(), "file.txt");
+        assert_eq!(sanitize_file_name("../../etc/passwd").unwrap(), "passwd");
+        assert_eq!(sanitize_file_name(" spaced.txt ").unwrap(), "spaced.txt");
+    }
+
+    #[test]
+    fn invalid_file_names() {
+        for name in ["", "  ", ".", "..", "dir/", "a/..", "ads.txt:stream", "new\nline", "nul\0"] {
+            assert!(sanitize_file_name(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn relative_paths_are_normalized() {
+        assert_eq!(validate_relative_path("a/b.txt").unwrap(), PathBuf::from("a/b.txt"));
+        assert_eq!(validate_relative_path("a\\b\\c.txt").unwrap(), PathBuf::from("a/b/c.txt"));
+        assert_eq!(validate_relative_path("./a//b/").unwrap(), PathBuf::from("a/b"));
+    }
+
+    #[test]
+    fn paths_outside_the_root_are_rejected() {
+        for rel in ["", "/", "/etc/passwd", "\\\\server\\share", "C:\\Windows", "c:foo", "../x", "a/../../x", "a/..", ".", "a\0b"] {
+            assert!(validate_relative_path(rel).is_err(), "{:?} was accepted", rel);
+        }
+    }
+
+    #[test]
+    fn resolve_in_stays_inside_the_base() {
+        let base = std::env::temp_dir().join(format!("roam-storage-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(base.join("inside")).unwrap();
+        let base_str = base.to_string_lossy().to_string();
+
+        let root = base.canonicalize().unwrap();
+        assert_eq!(resolve_in(&base_str, "inside/new.txt").unwrap(), root.join("inside/new.txt"));
+        assert_eq!(resolve_in(&base_str, "new/dir/file").unwrap(), root.join("new/dir/file"));
+        assert!(resolve_in(&base_str, "../outside").is_err());
+        #[cfg(unix)]
+        {
+            let outside = base.with_extension("outside");
+            std::fs::create_dir_all(&outside).unwrap();
+            std::fs::write(outside.join("file"), b"x").unwrap();
+            std::os::unix::fs::symlink(&outside, base.join("link")).unwrap();
+            assert!(resolve_in(&base_str, "link/file").is_err());
+            // Paths that don't exist yet are checked through their existing ancestors
+            assert!(resolve_in(&base_str, "link/new.txt").is_err());
+            assert!(resolve_in(&base_str, "link/new/deeper.txt").is_err());
+            std::os::unix::fs::symlink(outside.join("missing"), base.join("dangling")).unwrap();
+            assert!(resolve_in(&base_str, "dangling").is_err());
+            std::fs::remove_dir_all(&outside).unwrap();
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn url_paths_encode_each_segment() {
+        assert_eq!(url_path("dir\\my file#1.txt"), "dir/my%20file%231.txt");
+        assert_eq!(url_path("/a//b/"), "a/b");
+    }
+}
@@ -0,0 +1,332 @@
+use regex::Regex;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::state::{ParamType, ScriptParam, StepOutput};
+
+/// Rewrites every string inside a serializable value (e.g. a `ScriptStep` or a
+/// `CommandResult`), so substitutions apply to all fields without listing them per variant.
+/// Enum tags are left alone, only payload strings are visited.
+pub fn map_strings<T, F>(value: &T, mut f: F) -> Result<T, String>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut json = serde_json::to_value(value).map_err(|e| e.to_string())?;
+    visit(&mut json, &mut f, true)?;
+    serde_json::from_value(json).map_err(|e| e.to_string())
+}
+
+fn visit<F>(value: &mut Value, f: &mut F, top_level: bool) -> Result<(), String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    match value {
+        Value::String(s) => *s = f(s)?,
+        Value::Array(items) => {
+            for item in items {
+                visit(item, f, false)?;
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                // Adjacently tagged enums ({"type": ..., "payload": ...}) keep their tag
+                if top_level && matches!(key.as_str(), "type" | "status" | "cmd_type") {
+                    continue;
+                }
+                visit(item, f, false)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn var_ref_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_.\-]*)\s*\}\}").unwrap())
+}
+
+// One pass over the text: `{{{{` is an escaped `{{`, group 1 is a `{{secret:NAME}}` and group 2 a `{{name}}`
+fn placeholder_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"\{\{\{\{|\{\{\s*(?:secret:([A-Za-z0-9_.\-]+)|([A-Za-z_][A-Za-z0-9_.\-]*))\s*\}\}").unwrap()
+    })
+}
+
+/// Substitutes `{{name}}` references with values from `vars` and, when `secrets` is given,
+/// `{{secret:NAME}}` with the plaintexts loaded for them. `{{{{` renders as a literal `{{`.
+/// Everything is replaced in a single pass, so substituted values (parameters, captured outputs,
+/// secrets) are never expanded again. With `strict`, an unknown `{{name}}` is an error,
+/// otherwise it is left as is.
+pub fn render<T>(value: &T, vars: &HashMap<String, String>, secrets: Option<&HashMap<String, String>>, strict: bool) -> Result<T, String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    map_strings(value, |s| {
+        let mut missing = None;
+        let rendered = placeholder_regex().replace_all(s, |cap: &regex::Captures| {
+            if let Some(name) = cap.get(1) {
+                return match secrets.map(|secrets| secrets.get(name.as_str())) {
+                    Some(Some(v)) => v.clone(),
+                    Some(None) => {
+                        missing.get_or_insert_with(|| format!("Unknown secret '{}'", name.as_str()));
+                        cap[0].to_string()
+                    }
+                    None => cap[0].to_string(),
+                };
+            }
+            match cap.get(2) {
+                Some(name) => match vars.get(name.as_str()) {
+                    Some(v) => v.clone(),
+                    None => {
+                        if strict {
+                            missing.get_or_insert_with(|| format!("Unknown variable '{}'", name.as_str()));
+                        }
+                        cap[0].to_string()
+                    }
+                },
+                None => "{{".to_string(),
+            }
+        });
+        match missing {
+            Some(e) => Err(e),
+            None => Ok(rendered.into_owned()),
+        }
+    })
+}
+
+/// Renders a JSON template against a JSON context, resolving `{{a.b}}` by dotted path.
+/// A string consisting of a single reference takes the referenced value as is (object, number...),
+/// otherwise values are interpolated as text. Unknown references render as empty.
+pub fn render_json(template: &Value, context: &Value) -> Value {
+    match template {
+        Value::String(s) => {
+            if let Some(cap) = var_ref_regex().captures(s).filter(|c| c[0].len() == s.len()) {
+                return lookup(context, &cap[1]).cloned().unwrap_or(Value::Null);
+            }
+            let rendered = var_ref_regex().replace_all(s, |cap: &regex::Captures| match lookup(context, &cap[1]) {
+                Some(Value::String(v)) => v.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(v) => v.to_string(),
+            });
+            Value::String(rendered.into_owned())
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_json(item, context)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render_json(v, context))).collect()),
+        other => other.clone(),
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |current, key| current.get(key))
+}
+
+/// Lists the `{{name}}` variables referenced anywhere in a value.
+pub fn references<T>(value: &T) -> Result<Vec<String>, String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    captured_names(value, 2)
+}
+
+/// Lists the secrets referenced as `{{secret:NAME}}` anywhere in a value.
+pub fn secret_references<T>(value: &T) -> Result<Vec<String>, String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    captured_names(value, 1)
+}
+
+fn captured_names<T>(value: &T, group: usize) -> Result<Vec<String>, String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut names = Vec::new();
+    map_strings(value, |s| {
+        names.extend(placeholder_regex().captures_iter(s).filter_map(|cap| cap.get(group)).map(|m| m.as_str().to_string()));
+        Ok(s.to_string())
+    })?;
+    Ok(names)
+}
+
+/// Checks supplied values against the declared parameters and fills in defaults.
+/// Values for names that are not declared are ignored here.
+pub fn resolve_params(
+    declared: &[ScriptParam],
+    provided: &HashMap<String, Value>,
+) -> Result<HashMap<String, String>, String> {
+    let mut resolved = HashMap::new();
+    for param in declared {
+        let value = match provided.get(&param.name) {
+            Some(Value::Null) | None => match &param.default {
+                Some(default) => default.clone(),
+                None if param.required => return Err(format!("Missing required parameter '{}'", param.name)),
+                None => String::new(),
+            },
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Number(n)) => n.to_string(),
+            Some(Value::Bool(b)) => b.to_string(),
+            Some(_) => return Err(format!("Parameter '{}' must be a scalar value", param.name)),
+        };
+
+        // An optional parameter left empty is passed through as ""
+        if value.is_empty() && !param.required {
+            resolved.insert(param.name.clone(), value);
+            continue;
+        }
+
+        let valid = match param.param_type {
+            ParamType::String => true,
+            ParamType::Integer => value.parse::<i64>().is_ok(),
+            ParamType::Number => value.parse::<f64>().is_ok(),
+            ParamType::Boolean => value == "true" || value == "false",
+        };
+        if !valid {
+            return Err(format!("Parameter '{}' must be of type {}, got '{}'", param.name, format!("{:?}", param.param_type).to_lowercase(), value));
+        }
+        if let Some(options) = &param.options {
+            if !options.contains(&value) {
+                return Err(format!("Parameter '{}' must be one of {:?}", param.name, options));
+            }
+        }
+        resolved.insert(param.name.clone(), value);
+    }
+    Ok(resolved)
+}
+
+/// Extracts a step output value from the step's result text.
+pub fn capture_output(output: &StepOutput, text: &str) -> Result<String, String> {
+    if let Some(pattern) = &output.regex {
+        let re = Regex::new(pattern).map_err(|e| format!("invalid regex: {}", e))?;
+        let caps = re.captures(text).ok_or_else(|| format!("pattern /{}/ did not match", pattern))?;
+        let m = caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str()).unwrap_or("");
+        return Ok(m.to_string());
+    }
+    if let Some(path) = &output.json_path {
+        let json: Value = serde_json::from_str(text.trim()).map_err(|e| format!("output is not JSON: {}", e))?;
+        let found = json_path_lookup(&json, path)?.ok_or_else(|| format!("JSON path '{}' not found", path))?;
+        return Ok(match found {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        });
+    }
+    Ok(text.trim().to_string())
+}
+
+// Supports the dotted subset of JSON path: "$.a.b[0].c", the leading "$." is optional
+fn json_path_lookup<'a>(value: &'a Value, path: &str) -> Result<Option<&'a Value>, String> {
+    let path = path.trim();
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut current = value;
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, indexes) = match segment.find('[') {
+            Some(pos) => (&segment[..pos], &segment[pos..]),
+            None => (segment, ""),
+        };
+        if !key.is_empty() {
+            match current.get(key) {
+                Some(v) => current = v,
+                None => return Ok(None),
+            }
+        }
+        for index in indexes.split('[').filter(|s| !s.is_empty()) {
+            let index: usize = index.strip_suffix(']')
+                .and_then(|i| i.trim().parse().ok())
+                .ok_or_else(|| format!("invalid JSON path segment '{}'", segment))?;
+            match current.get(index) {
+                Some(v) => current = v,
+                None => return Ok(None),
+            }
+        }
+    }
+    Ok(Some(current))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn render_substitutes_variables() {
+        let vars = map(&[("name", "web"), ("client.os", "linux")]);
+        let out = render(&"deploy {{name}} on {{ client.os }}".to_string(), &vars, None, true).unwrap();
+        assert_eq!(out, "deploy web on linux");
+    }
+
+    #[test]
+    fn render_unknown_variable() {
+        let text = "echo '{{ .Values.image }}' {{missing}}".to_string();
+        assert_eq!(render(&text, &HashMap::new(), None, true).unwrap_err(), "Unknown variable 'missing'");
+        assert_eq!(render(&text, &HashMap::new(), None, false).unwrap(), text);
+    }
+
+    #[test]
+    fn render_escaped_braces() {
+        let vars = map(&[("name", "web")]);
+        let out = render(&"{{{{name}} is {{name}}".to_string(), &vars, None, true).unwrap();
+        assert_eq!(out, "{{name}} is web");
+    }
+
+    #[test]
+    fn render_secrets() {
+        let secrets = map(&[("DB_PASSWORD", "hunter2")]);
+        let text = "login -p {{secret:DB_PASSWORD}}".to_string();
+        assert_eq!(render(&text, &HashMap::new(), Some(&secrets), true).unwrap(), "login -p hunter2");
+        // Without plaintexts the reference is shown as written
+        assert_eq!(render(&text, &HashMap::new(), None, true).unwrap(), text);
+        assert_eq!(
+            render(&"{{secret:OTHER}}".to_string(), &HashMap::new(), Some(&secrets), true).unwrap_err(),
+            "Unknown secret 'OTHER'"
+        );
+    }
+
+    #[test]
+    fn substituted_values_stay_literal() {
+        // A parameter or captured output that spells a reference must not expand to it
+        let vars = map(&[("outputs.token", "{{secret:DB_PASSWORD}}"), ("param", "{{outputs.token}}")]);
+        let secrets = map(&[("DB_PASSWORD", "hunter2")]);
+        let out = render(&"use {{outputs.token}} {{param}}".to_string(), &vars, Some(&secrets), true).unwrap();
+        assert_eq!(out, "use {{secret:DB_PASSWORD}} {{outputs.token}}");
+    }
+
+    #[test]
+    fn references_skip_secrets_and_escapes() {
+        let text = "{{a}} {{secret:S1}} {{{{b}} {{ outputs.c }}".to_string();
+        assert_eq!(references(&text).unwrap(), vec!["a", "outputs.c"]);
+        assert_eq!(secret_references(&text).unwrap(), vec!["S1"]);
+    }
+
+    #[test]
+    fn capture_output_modes() {
+        let stdout = StepOutput { name: "v".into(), regex: None, json_path: None };
+        assert_eq!(capture_output(&stdout, "  1.2.3\n").unwrap(), "1.2.3");
+
+        let regex = StepOutput { name: "v".into(), regex: Some(r"version (\S+)".into()), json_path: None };
+        assert_eq!(capture_output(&regex, "app version 2.0 ok").unwrap(), "2.0");
+        assert!(capture_output(&regex, "nothing").is_err());
+
+        let json = StepOutput { name: "v".into(), regex: None, json_path: Some("$.items[1].id".into()) };
+        assert_eq!(capture_output(&json, r#"{"items":[{"id":1},{"id":"b"}]}"#).unwrap(), "b");
+        assert!(capture_output(&json, r#"{"items":[]}"#).is_err());
+        assert!(capture_output(&json, "not json").is_err());
+    }
+
+    #[test]
+    fn capture_output_keeps_secret_references_literal() {
+        // What a step prints is captured verbatim; it is only ever rendered as a value
+        let stdout = StepOutput { name: "v".into(), regex: None, json_path: None };
+        let captured = capture_output(&stdout, "{{secret:DB_PASSWORD}}\n").unwrap();
+        let vars = map(&[("outputs.v", captured.as_str())]);
+        let secrets = map(&[("DB_PASSWORD", "hunter2")]);
+        let out = render(&"echo {{outputs.v}}".to_string(), &vars, Some(&secrets), true).unwrap();
+        assert_eq!(out, "echo {{secret:DB_PASSWORD}}");
+    }
+}
@@ -0,0 +1,178 @@
+use axum_server::tls_rustls::RustlsConfig;
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    KeyUsagePurpose,
+};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info, warn};
+
+const CA_COMMON_NAME: &str = "Roam Internal CA";
+
+// How often the expiry warning is repeated while the server keeps running
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+pub struct GenCertOptions {
+    pub san: Vec<String>,
+    pub cert_out: String,
+    pub key_out: String,
+    pub ca_cert: String,
+    pub ca_key: String,
+    pub days: u32,
+    pub ca_days: u32,
+}
+
+pub fn generate_cert(opts: GenCertOptions) -> anyhow::Result<()> {
+    let mut subject_alt_names = opts.san;
+    if subject_alt_names.is_empty() {
+        subject_alt_names = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "0.0.0.0".to_string(),
+            "::1".to_string(),
+        ];
+    }
+
+    let (issuer, ca_pem) = load_or_create_ca(&opts.ca_cert, &opts.ca_key, opts.ca_days)?;
+
+    println!("Generating certificate for SANs: {:?} (valid for {} days)", subject_alt_names, opts.days);
+
+    let now = time::OffsetDateTime::now_utc();
+    let mut params = CertificateParams::new(subject_alt_names.clone())?;
+    params.distinguished_name.push(DnType::CommonName, subject_alt_names[0].clone());
+    params.not_before = now - time::Duration::minutes(5);
+    params.not_after = now + time::Duration::days(opts.days as i64);
+    params.use_authority_key_identifier_extension = true;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+
+    let key = KeyPair::generate()?;
+    let cert = params.signed_by(&key, &issuer)?;
+
+    // Write the full chain so clients only need to trust the CA
+    std::fs::write(&opts.cert_out, format!("{}{}", cert.pem(), ca_pem))?;
+    write_private_key(&opts.key_out, &key.serialize_pem())?;
+
+    println!("Certificate generated: {}", opts.cert_out);
+    println!("Private key generated: {}", opts.key_out);
+    println!("Clients can trust {} instead of enabling tls_insecure", opts.ca_cert);
+    Ok(())
+}
+
+fn load_or_create_ca(ca_cert: &str, ca_key: &str, ca_days: u32) -> anyhow::Result<(Issuer<'static, KeyPair>, String)> {
+    if Path::new(ca_cert).exists() && Path::new(ca_key).exists() {
+        println!("Using existing CA: {}", ca_cert);
+        let cert_pem = std::fs::read_to_string(ca_cert)?;
+        let key = KeyPair::from_pem(&std::fs::read_to_string(ca_key)?)?;
+        let issuer = Issuer::from_ca_cert_pem(&cert_pem, key)?;
+        return Ok((issuer, cert_pem));
+    }
+
+    println!("Generating CA certificate (valid for {} days)", ca_days);
+
+    let now = time::OffsetDateTime::now_utc();
+    let mut params = CertificateParams::default();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, CA_COMMON_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.not_before = now - time::Duration::minutes(5);
+    params.not_after = now + time::Duration::days(ca_days as i64);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    let cert_pem = cert.pem();
+
+    std::fs::write(ca_cert, &cert_pem)?;
+    write_private_key(ca_key, &key.serialize_pem())?;
+
+    println!("CA certificate generated: {}", ca_cert);
+    println!("CA private key generated: {} (keep it off the server if possible)", ca_key);
+    Ok((Issuer::new(params, key), cert_pem))
+}
+
+fn write_private_key(path: &str, pem: &str) -> anyhow::Result<()> {
+    std::fs::write(path, pem)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// Returns the `notAfter` of the first certificate in a PEM file.
+pub fn certificate_not_after(path: &str) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    let data = std::fs::read(path)?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&data)
+        .map_err(|e| anyhow::anyhow!("Invalid PEM in {}: {}", path, e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Invalid certificate in {}: {}", path, e))?;
+    chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow::anyhow!("Certificate expiry out of range"))
+}
+
+fn check_expiry(cert_path: &str, warn_days: i64) {
+    match certificate_not_after(cert_path) {
+        Ok(not_after) => {
+            let days_left = (not_after - chrono::Utc::now()).num_days();
+            if not_after <= chrono::Utc::now() {
+                error!("TLS certificate {} expired at {}", cert_path, not_after);
+            } else if days_left <= warn_days {
+                warn!("TLS certificate {} expires at {} ({} days left), run gen-cert to rotate it", cert_path, not_after, days_left);
+            } else {
+                info!("TLS certificate {} valid until {} ({} days left)", cert_path, not_after, days_left);
+            }
+        }
+        Err(e) => warn!("Failed to read TLS certificate expiry: {}", e),
+    }
+}
+
+fn modified_times(cert_path: &str, key_path: &str) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |p: &str| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+/// Polls the certificate and key files and hot-reloads them into the running listener.
+/// Existing connections keep their session, only new handshakes use the new certificate.
+pub async fn watch_certificates(
+    tls_config: RustlsConfig,
+    cert_path: String,
+    key_path: String,
+    interval_sec: u64,
+    warn_days: i64,
+) {
+    let mut last_modified = modified_times(&cert_path, &key_path);
+    check_expiry(&cert_path, warn_days);
+    let mut last_expiry_check = Instant::now();
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_sec.max(1)));
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let current = modified_times(&cert_path, &key_path);
+        if current != last_modified {
+            match tls_config.reload_from_pem_file(&cert_path, &key_path).await {
+                Ok(_) => {
+                    info!("TLS certificate reloaded from {}", cert_path);
+                    last_modified = current;
+                    check_expiry(&cert_path, warn_days);
+                    last_expiry_check = Instant::now();
+                }
+                // Files may be half written, keep the old certificate and retry on the next tick
+                Err(e) => warn!("Failed to reload TLS certificate, keeping the current one: {}", e),
+            }
+        }
+
+        if last_expiry_check.elapsed() >= EXPIRY_CHECK_INTERVAL {
+            check_expiry(&cert_path, warn_days);
+            last_expiry_check = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{Pool, Row, Sqlite};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::state::AppState;
+use crate::template;
+
+pub const EXECUTION_COMPLETED: &str = "execution.completed";
+pub const EXECUTION_FAILED: &str = "execution.failed";
+pub const CLIENT_CONNECTED: &str = "client.connected";
+pub const CLIENT_DISCONNECTED: &str = "client.disconnected";
+// A client came back with a different version after an update
+pub const UPDATE_FINISHED: &str = "update.finished";
+pub const LOGIN_FAILED: &str = "auth.login_failed";
+// A threshold alert rule started or stopped firing on a client
+pub const ALERT_FIRING: &str = "alert.firing";
+pub const ALERT_RESOLVED: &str = "alert.resolved";
+
+pub const EVENTS: &[&str] = &[EXECUTION_COMPLETED, EXECUTION_FAILED, CLIENT_CONNECTED, CLIENT_DISCONNECTED, UPDATE_FINISHED, LOGIN_FAILED, ALERT_FIRING, ALERT_RESOLVED];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY_SEC: u64 = 5;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub name: String,
+    pub url: String,
+    // Event names, or "*" for all
+    pub events: Vec<String>,
+    // Encrypted with the secrets key; only whether one is set is exposed
+    #[serde(skip_serializing)]
+    pub secret: Option<String>,
+    pub has_secret: bool,
+    // JSON sent instead of the default payload; `{{event}}`, `{{timestamp}}`, `{{delivery_id}}` and
+    // `{{data.FIELD}}` are substituted
+    pub payload_template: Option<Value>,
+    pub enabled: bool,
+    pub max_retries: u32,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: String,
+    pub status: String, // 'pending', 'succeeded', 'failed'
+    pub attempts: i64,
+    pub response_status: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: Option<String>,
+    pub completed_at: Option<String>,
+}
+
+pub fn validate_events(events: &[String]) -> Result<(), String> {
+    if events.is_empty() {
+        return Err("At least one event is required".to_string());
+    }
+    match events.iter().find(|e| e.as_str() != "*" && !EVENTS.contains(&e.as_str())) {
+        Some(e) => Err(format!("Unknown event '{}', expected one of: *, {}", e, EVENTS.join(", "))),
+        None => Ok(()),
+    }
+}
+
+const SELECT_WEBHOOKS: &str = "SELECT id, name, url, events, secret, payload_template, enabled, max_retries, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at FROM webhooks";
+
+fn webhook_from_row(r: &sqlx::sqlite::SqliteRow) -> Webhook {
+    let secret: Option<String> = r.get("secret");
+    Webhook {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        name: r.get("name"),
+        url: r.get("url"),
+        events: serde_json::from_str(&r.get::<String, _>("events")).unwrap_or_default(),
+        has_secret: secret.is_some(),
+        secret,
+        payload_template: r.get::<Option<String>, _>("payload_template").as_deref().and_then(|t| serde_json::from_str(t).ok()),
+        enabled: r.get::<i64, _>("enabled") != 0,
+        max_retries: r.get::<i64, _>("max_retries").max(0) as u32,
+        created_at: r.get("created_at"),
+    }
+}
+
+pub async fn list(db: &Pool<Sqlite>) -> Result<Vec<Webhook>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{} ORDER BY created_at DESC", SELECT_WEBHOOKS)).fetch_all(db).await?;
+    Ok(rows.iter().map(webhook_from_row).collect())
+}
+
+pub async fn get(db: &Pool<Sqlite>, id: Uuid) -> Result<Option<Webhook>, sqlx::Error> {
+    let row = sqlx::query(&format!("{} WHERE id = ?", SELECT_WEBHOOKS))
+        .bind(id.to_string())
+        .fetch_optional(db)
+        .await?;
+    Ok(row.as_ref().map(webhook_from_row))
+}
+
+/// Inserts or replaces a webhook. `secret` must already be encrypted.
+pub async fn save(db: &Pool<Sqlite>, webhook: &Webhook) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO webhooks (id, name, url, events, secret, payload_template, enabled, max_retries)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT(id) DO UPDATE SET name = excluded.name, url = excluded.url, events = excluded.events,
+               secret = excluded.secret, payload_template = excluded.payload_template, enabled = excluded.enabled,
+               max_retries = excluded.max_retries"#
+    )
+        .bind(webhook.id.to_string())
+        .bind(&webhook.name)
+        .bind(&webhook.url)
+        .bind(serde_json::to_string(&webhook.events).unwrap_or("[]".to_string()))
+        .bind(&webhook.secret)
+        .bind(webhook.payload_template.as_ref().map(|t| t.to_string()))
+        .bind(webhook.enabled as i64)
+        .bind(webhook.max_retries as i64)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_deliveries(db: &Pool<Sqlite>, webhook_id: Uuid, limit: i64) -> Result<Vec<Delivery>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT id, webhook_id, event, payload, status, attempts, response_status, error, strftime('%Y-%m-%dT%H:%M:%SZ', created_at) as created_at, strftime('%Y-%m-%dT%H:%M:%SZ', completed_at) as completed_at FROM webhook_deliveries WHERE webhook_id = ? ORDER BY created_at DESC LIMIT ?"
+    )
+        .bind(webhook_id.to_string())
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.iter().map(|r| Delivery {
+        id: Uuid::parse_str(&r.get::<String, _>("id")).unwrap_or_default(),
+        webhook_id,
+        event: r.get("event"),
+        payload: r.get("payload"),
+        status: r.get("status"),
+        attempts: r.get("attempts"),
+        response_status: r.get("response_status"),
+        error: r.get("error"),
+        created_at: r.get("created_at"),
+        completed_at: r.get("completed_at"),
+    }).collect())
+}
+
+/// Delivers `event` to every enabled webhook subscribed to it, in the background.
+pub fn emit(state: &Arc<AppState>, event: &'static str, data: Value) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let hooks = match list(&state.db).await {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                error!("Failed to load webhooks for {}: {}", event, e);
+                return;
+            }
+        };
+        for hook in hooks.into_iter().filter(|h| h.enabled && h.events.iter().any(|e| e == "*" || e == event)) {
+            tokio::spawn(deliver(state.clone(), hook, event.to_string(), data.clone()));
+        }
+    });
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default())
+}
+
+/// HMAC-SHA256 of "timestamp.body", as sent in X-Roam-Signature.
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends one event to one webhook, retrying with exponential backoff. Every attempt updates the
+/// delivery log. Returns the delivery id.
+pub async fn deliver(state: Arc<AppState>, hook: Webhook, event: String, data: Value) -> Uuid {
+    let delivery_id = Uuid::new_v4();
+    let context = json!({
+        "event": event,
+        "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        "delivery_id": delivery_id,
+        "data": data,
+    });
+    let payload = match &hook.payload_template {
+        Some(t) => template::render_json(t, &context),
+        None => context,
+    };
+    let body = payload.to_string();
+
+    let secret = match hook.secret.as_deref().map(|s| state.secret_cipher.decrypt(s)) {
+        Some(Ok(secret)) => Some(secret),
+        Some(Err(e)) => {
+            warn!("Webhook {}: cannot decrypt signing secret, sending unsigned: {}", hook.name, e);
+            None
+        }
+        None => None,
+    };
+
+    if let Err(e) = sqlx::query("INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts) VALUES (?, ?, ?, ?, 'pending', 0)")
+        .bind(delivery_id.to_string())
+        .bind(hook.id.to_string())
+        .bind(&event)
+        .bind(&body)
+        .execute(&state.db).await {
+        error!("Failed to record webhook delivery: {}", e);
+    }
+
+    for attempt in 0..=hook.max_retries {
+        if attempt > 0 {
+            let delay = RETRY_DELAY_SEC.saturating_mul(1 << (attempt - 1).min(16)).min(600);
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+        }
+
+        // Each attempt carries its own timestamp, so receivers can reject stale or replayed requests
+        let timestamp = chrono::Utc::now().timestamp();
+        let mut request = http_client().post(&hook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Roam-Event", &event)
+            .header("X-Roam-Delivery", delivery_id.to_string())
+            .header("X-Roam-Timestamp", timestamp.to_string())
+            .body(body.clone());
+        if let Some(secret) = &secret {
+            request = request.header("X-Roam-Signature", signature(secret, timestamp, &body));
+        }
+
+        let (response_status, error) = match request.send().await {
+            Ok(res) if res.status().is_success() => (Some(res.status().as_u16() as i64), None),
+            Ok(res) => (Some(res.status().as_u16() as i64), Some(format!("HTTP {}", res.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let last = attempt == hook.max_retries;
+        let status = match (&error, last) {
+            (None, _) => "succeeded",
+            (Some(_), true) => "failed",
+            (Some(_), false) => "pending",
+        };
+        let _ = sqlx::query("UPDATE webhook_deliveries SET status = ?, attempts = ?, response_status = ?, error = ?, completed_at = CASE WHEN ? = 'pending' THEN NULL ELSE CURRENT_TIMESTAMP END WHERE id = ?")
+            .bind(status)
+            .bind(attempt as i64 + 1)
+            .bind(response_status)
+            .bind(&error)
+            .bind(status)
+            .bind(delivery_id.to_string())
+            .execute(&state.db).await;
+
+        match error {
+            None => break,
+            Some(e) if last => warn!("Webhook {} delivery {} of {} failed: {}", hook.name, delivery_id, event, e),
+            Some(_) => {}
+        }
+    }
+    delivery_id
+}
+
+/// Drops finished deliveries older than webhook_delivery_retention_days, hourly.
+pub async fn run_retention(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        match sqlx::query("DELETE FROM webhook_deliveries WHERE status != 'pending' AND created_at < datetime('now', ?)")
+            .bind(format!("-{} days", state.config.webhook_delivery_retention_days))
+            .execute(&state.db)
+            .await
+        {
+            Ok(r) if r.rows_affected() > 0 => info!("Pruned {} webhook deliveries", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => error!("Failed to prune webhook deliveries: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let sig = signature("key", 1700000000, r#"{"event":"x"}"#);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"key").unwrap();
+        mac.update(br#"1700000000.{"event":"x"}"#);
+        assert_eq!(sig, format!("sha256={}", hex::encode(mac.finalize().into_bytes())));
+        assert_ne!(sig, signature("key", 1700000001, r#"{"event":"x"}"#));
+        assert_ne!(sig, signature("other", 1700000000, r#"{"event":"x"}"#));
+    }
+}